@@ -0,0 +1,165 @@
+use chrono::{Datelike, Local, Timelike};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use zip::{
+    result::ZipError, write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter,
+};
+
+/// The zip archives the files are sorted into with `--archive`, one for every
+/// day. A file is at a path within its archive, eg. `2008-05-30.zip/photo.jpg`.
+pub(crate) struct Archives {
+    archives: Mutex<HashMap<PathBuf, Archive>>,
+}
+
+struct Archive {
+    // the size of every entry, including those added by earlier runs.
+    entries: HashMap<String, u64>,
+    // opened when the first file is added, so a dry run never writes one.
+    writer: Option<ZipWriter<File>>,
+}
+
+impl Archives {
+    pub(crate) fn new() -> Self {
+        Archives {
+            archives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The size of the entry at the path when its archive already holds it.
+    pub(crate) fn entry_len(&self, path: &Path) -> io::Result<Option<u64>> {
+        let (archive_path, name) = split(path)?;
+        let mut archives = self.archives.lock().unwrap();
+        let archive = load(&mut archives, archive_path)?;
+        Ok(archive.entries.get(name).copied())
+    }
+
+    /// Stores the source at the path within its archive, without compressing it
+    /// since photos are compressed already.
+    pub(crate) fn add(&self, source: &Path, path: &Path, preserve_mtime: bool) -> io::Result<u64> {
+        let (archive_path, name) = split(path)?;
+        let mut archives = self.archives.lock().unwrap();
+        let archive = load(&mut archives, archive_path)?;
+        let writer = match &mut archive.writer {
+            Some(writer) => writer,
+            None => archive.writer.insert(open_writer(archive_path)?),
+        };
+
+        let mut file = File::open(source)?;
+        let metadata = file.metadata()?;
+        let modified = match preserve_mtime {
+            true => metadata.modified()?.into(),
+            false => Local::now(),
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(metadata.len() >= u32::MAX as u64)
+            .last_modified_time(zip_time(modified));
+        writer.start_file(name, options).map_err(io_error)?;
+        // a failed copy leaves no partial entry behind, so it can be retried.
+        let bytes = match io::copy(&mut file, writer) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                writer.abort_file().map_err(io_error)?;
+                return Err(err);
+            }
+        };
+        archive.entries.insert(name.to_string(), bytes);
+        Ok(bytes)
+    }
+
+    /// Writes the central directory of every archive a file was added to.
+    pub(crate) fn finish(self) -> Vec<(PathBuf, io::Error)> {
+        let archives = self.archives.into_inner().unwrap();
+        archives
+            .into_iter()
+            .filter_map(|(path, archive)| Some((path, archive.writer?.finish().err()?)))
+            .map(|(path, err)| (path, io_error(err)))
+            .collect()
+    }
+}
+
+// the archive and the name of the entry within it.
+fn split(path: &Path) -> io::Result<(&Path, &str)> {
+    match (
+        path.parent(),
+        path.file_name().and_then(|name| name.to_str()),
+    ) {
+        (Some(archive_path), Some(name)) => Ok((archive_path, name)),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a path within an archive", path.display()),
+        )),
+    }
+}
+
+fn load<'a>(
+    archives: &'a mut HashMap<PathBuf, Archive>,
+    archive_path: &Path,
+) -> io::Result<&'a mut Archive> {
+    if !archives.contains_key(archive_path) {
+        let entries = match File::open(archive_path) {
+            Ok(file) => read_entries(file)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        archives.insert(
+            archive_path.to_path_buf(),
+            Archive {
+                entries,
+                writer: None,
+            },
+        );
+    }
+    Ok(archives.get_mut(archive_path).unwrap())
+}
+
+fn read_entries(file: File) -> io::Result<HashMap<String, u64>> {
+    let mut archive = ZipArchive::new(file).map_err(io_error)?;
+    let mut entries = HashMap::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(io_error)?;
+        entries.insert(entry.name().to_string(), entry.size());
+    }
+    Ok(entries)
+}
+
+// an archive of an earlier run is added to rather than replaced.
+fn open_writer(archive_path: &Path) -> io::Result<ZipWriter<File>> {
+    if archive_path.exists() {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(archive_path)?;
+        return ZipWriter::new_append(file).map_err(io_error);
+    }
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(ZipWriter::new(File::create(archive_path)?))
+}
+
+// zip times are local times from 1980 on, so an older time is clamped to it.
+fn zip_time(time: chrono::DateTime<Local>) -> DateTime {
+    DateTime::from_date_and_time(
+        time.year().clamp(1980, 2107) as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+// a broken archive is not worth retrying, unlike a failed read or write.
+fn io_error(err: ZipError) -> io::Error {
+    match err {
+        ZipError::Io(err) => err,
+        err => io::Error::new(ErrorKind::InvalidData, err),
+    }
+}
@@ -0,0 +1,64 @@
+use crate::date::{exif_compatible_extension, read_exif};
+use exif::{In, Tag, Value};
+use walkdir::DirEntry;
+
+/// The folder of the files whose camera is not known, eg. videos and scans
+/// without a model in their exif.
+pub static UNKNOWN_CAMERA: &str = "Unknown";
+
+/// The folder of the files whose lens is not known, eg. phone photos and
+/// videos without a lens model in their exif.
+pub static UNKNOWN_LENS: &str = "Unknown Lens";
+
+/// The camera model from the exif of the file, made safe to use as a folder
+/// name, eg. Canon EOS 40D.
+pub fn get_camera_model(entry: &DirEntry) -> Option<String> {
+    get_model(entry, Tag::Model)
+}
+
+/// The lens model from the exif of the file, made safe to use as a folder
+/// name, eg. EF-S17-85mm f-4-5.6 IS USM.
+pub fn get_lens(entry: &DirEntry) -> Option<String> {
+    get_model(entry, Tag::LensModel)
+}
+
+fn get_model(entry: &DirEntry, tag: Tag) -> Option<String> {
+    if !exif_compatible_extension(entry) {
+        return None;
+    }
+    let exif = read_exif(entry.path()).ok()?;
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    match &field.value {
+        Value::Ascii(values) => sanitize_model(&String::from_utf8_lossy(values.first()?)),
+        _ => None,
+    }
+}
+
+// a model is a single folder, so the separators are replaced and the names
+// which would point elsewhere, eg. "..", are left out.
+fn sanitize_model(model: &str) -> Option<String> {
+    let model: String = model
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if matches!(c, '/' | '\\') { '-' } else { c })
+        .collect();
+    let model = model.trim();
+    (!model.is_empty() && !model.chars().all(|c| c == '.')).then(|| model.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_model_keeps_a_single_folder() {
+        assert_eq!(
+            sanitize_model("Canon EOS 40D\0"),
+            Some("Canon EOS 40D".to_string())
+        );
+        assert_eq!(sanitize_model(" DSC/RX100 "), Some("DSC-RX100".to_string()));
+        assert_eq!(sanitize_model("..\\.."), Some("..-..".to_string()));
+        assert_eq!(sanitize_model(".."), None);
+        assert_eq!(sanitize_model("  "), None);
+    }
+}
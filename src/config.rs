@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use serde::Deserialize;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+use crate::Cli;
+
+// looked up in the working directory first, then in the user config directory.
+static CONFIG_FILE_NAME: &str = "photosort.toml";
+
+/// The default options of a sort, keyed by the long name of their flag, eg.
+/// `layout = "%Y/%m"` or `dedupe-by-hash = true`.
+#[derive(Deserialize, Default)]
+#[serde(transparent)]
+pub(crate) struct Config {
+    options: toml::Table,
+}
+
+/// Reads the first config file found, or the empty config when there's none.
+pub(crate) fn load_config() -> Result<Config> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    let contents = fs::read_to_string(&path).map_err(|err| {
+        anyhow::anyhow!(
+            "Failed to read the config file {} - [{}]",
+            path.display(),
+            err
+        )
+    })?;
+    toml::from_str(&contents).map_err(|err| {
+        anyhow::anyhow!(
+            "The config file {} is invalid - [{}]",
+            path.display(),
+            err.message()
+        )
+    })
+}
+
+fn config_path() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("photosort").join("config.toml")).filter(|path| path.is_file())
+}
+
+impl Config {
+    /// Adds the options of the config to the command line arguments of a sort.
+    /// An option given on the command line wins over the config.
+    pub(crate) fn apply(&self, args: Vec<OsString>) -> Result<Vec<OsString>> {
+        // the config holds the sort options, so it's left out of the other subcommands
+        // apart from where, which previews a sort.
+        let insert_at = match args.get(1).and_then(|arg| arg.to_str()) {
+            None => 1,
+            Some("sort" | "where") => 2,
+            Some(arg) if arg.starts_with('-') => 1,
+            Some(_) => return Ok(args),
+        };
+        let command = Cli::command();
+        let mut defaults = Vec::new();
+        for (name, value) in &self.options {
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name.as_str()))
+                .filter(|_| !matches!(name.as_str(), "help" | "version"))
+                .with_context(|| format!("Unknown option {} in the config file", name))?;
+            if given(&args[insert_at..], name, arg.get_short()) {
+                continue;
+            }
+            let flag = OsString::from(format!("--{}", name));
+            match (value, arg.is_takes_value_set()) {
+                (Value::Boolean(true), false) => defaults.push(flag),
+                (Value::Boolean(false), false) => {}
+                (Value::String(value), true) => defaults.extend([flag, value.into()]),
+                (Value::Integer(value), true) => defaults.extend([flag, value.to_string().into()]),
+                (Value::Float(value), true) => defaults.extend([flag, value.to_string().into()]),
+                (Value::Array(values), true) => {
+                    let values = values
+                        .iter()
+                        .map(|value| match value {
+                            Value::String(value) => Ok(value.clone()),
+                            _ => anyhow::bail!(
+                                "Expected a list of strings for {} in the config file",
+                                name
+                            ),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    defaults.extend([flag, values.join(",").into()]);
+                }
+                (_, false) => {
+                    anyhow::bail!("Expected true or false for {} in the config file", name)
+                }
+                (_, true) => anyhow::bail!("Expected a value for {} in the config file", name),
+            }
+        }
+        let mut args = args;
+        args.splice(insert_at..insert_at, defaults);
+        Ok(args)
+    }
+}
+
+// whether the option is on the command line, eg. --jobs 4, --jobs=4 or -j4.
+fn given(args: &[OsString], long: &str, short: Option<char>) -> bool {
+    let long = format!("--{}", long);
+    args.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        arg == long
+            || arg.starts_with(&format!("{}=", long))
+            || short.is_some_and(|short| {
+                arg.starts_with('-') && !arg.starts_with("--") && arg[1..].starts_with(short)
+            })
+    })
+}
@@ -0,0 +1,974 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::ArgEnum;
+use exif::{In, Tag, Value};
+use regex::Regex;
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+use walkdir::DirEntry;
+
+/// The exif tag tried first for the date of a file.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum DateTag {
+    Original,
+    Digitized,
+    Auto,
+}
+
+/// How the date of a file is picked when it has more than one, eg. an exif
+/// date and a modified time which disagree.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum DatePolicy {
+    /// The exif date, falling back to the modified time.
+    ExifFirst,
+    /// The modified time, falling back to the exif date.
+    MtimeFirst,
+    /// The earliest of the dates.
+    Oldest,
+    /// The latest of the dates.
+    Newest,
+}
+
+/// How far an exif date may be from the file modified time before it's
+/// suspect, eg. a corrupt tag which reads 1970.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DateSkew {
+    pub max_days: u32,
+    /// Sort a file with a suspect exif date by its modified time instead.
+    pub fallback: bool,
+}
+
+/// Whether the dates are more than the given number of days apart.
+pub fn exceeds_skew(date: NaiveDate, modified: NaiveDate, max_days: u32) -> bool {
+    (date - modified).num_days().unsigned_abs() > u64::from(max_days)
+}
+
+/// The time zone the dates are taken in, given as an offset like `+05:30` or
+/// as a name like `Asia/Kolkata`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Zone {
+    Offset(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    fn date_of<Tz: TimeZone>(self, instant: &DateTime<Tz>) -> NaiveDate {
+        match self {
+            Zone::Offset(offset) => instant.with_timezone(&offset).date_naive(),
+            Zone::Named(tz) => instant.with_timezone(&tz).date_naive(),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    fn from_str(zone: &str) -> Result<Self> {
+        if let Ok(offset) = zone.parse::<FixedOffset>() {
+            return Ok(Zone::Offset(offset));
+        }
+        zone.parse::<chrono_tz::Tz>().map(Zone::Named).map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid time zone {}. Expected an offset like +05:30 or a name like Asia/Kolkata",
+                zone
+            )
+        })
+    }
+}
+
+/// An external tool the date of a file is asked from before its exif, eg.
+/// `exiftool -s3 -d %Y-%m-%d -DateTimeOriginal`, for formats the exif isn't
+/// read from. It's run with the path of the file after its own arguments and
+/// prints the date as `YYYY-MM-DD`. Parsed from a string, its words are split
+/// like a shell would, so an argument holding a space is quoted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DateCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl DateCommand {
+    // a command which fails or prints anything else leaves the file to the
+    // other dates, as does one which can't be run at all.
+    fn date_of(&self, path: &Path) -> Option<NaiveDate> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_command_output(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+impl FromStr for DateCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(command: &str) -> Result<Self> {
+        let mut words = split_words(command)?.into_iter();
+        match words.next() {
+            Some(program) => Ok(DateCommand {
+                program,
+                args: words.collect(),
+            }),
+            None => anyhow::bail!("The date command is empty"),
+        }
+    }
+}
+
+// the words of a command as a shell splits them, so an argument holding a space
+// is quoted, eg. `-d "%Y-%m-%d %H"`. Nothing else of the shell is expanded.
+fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let quoted = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => quoted.push(c),
+                        None => anyhow::bail!("The date command has an unclosed quote"),
+                    }
+                }
+            }
+            '"' => {
+                let quoted = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => quoted.push(c),
+                            Some(c) => quoted.extend(['\\', c]),
+                            None => anyhow::bail!("The date command has an unclosed quote"),
+                        },
+                        Some(c) => quoted.push(c),
+                        None => anyhow::bail!("The date command has an unclosed quote"),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+// the date leads the output, which may go on with a time, eg. `2019-07-15 12:34:56`.
+fn parse_command_output(output: &str) -> Option<NaiveDate> {
+    let date = output.trim_start().get(..10)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+static EXIF_COMPATIBLE_EXTENSIONS: [&str; 13] = [
+    "jpg", "jpeg", "png", "tif", "tiff", "heic", "heif", // images
+    "cr2", "nef", "arw", "dng", "orf", "raf", // camera raw
+];
+
+static VIDEO_EXTENSIONS: [&str; 3] = ["mp4", "mov", "m4v"];
+
+// quicktime and mp4 times count the seconds since 1-Jan-1904.
+static QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+// fujifilm raw files start with this magic followed by the offset and length
+// of an embedded jpeg preview which holds the exif.
+static RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+static RAF_JPEG_OFFSET: usize = 84;
+
+// make or model fragments that identify a scanner rather than a camera.
+static SCANNER_KEYWORDS: [&str; 4] = ["scan", "epson", "perfection", "fujitsu"];
+
+// the file names of phones and cameras holding the date, tried in order -
+// IMG_20190715_123456.jpg or PXL_20190715_123456789.jpg from android,
+// IMG-20190715-WA0001.jpg from whatsapp, and 2019-07-15 12.34.56.jpg or
+// Screenshot 2019-07-15 at 12.34.56.png from dropbox and ios.
+static NAME_PATTERNS: [&str; 3] = [
+    r"(?:^|\D)(?P<year>(?:19|20)\d{2})(?P<month>\d{2})(?P<day>\d{2})[_-]\d{6}",
+    r"(?:^|\D)(?P<year>(?:19|20)\d{2})(?P<month>\d{2})(?P<day>\d{2})-WA\d+",
+    r"(?:^|\D)(?P<year>(?:19|20)\d{2})-(?P<month>\d{2})-(?P<day>\d{2})(?: at |[ _T-])\d{2}[.:-]?\d{2}[.:-]?\d{2}",
+];
+
+// the date tags in the order they are tried.
+static ORIGINAL_FIRST: [Tag; 3] = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime];
+static DIGITIZED_FIRST: [Tag; 3] = [Tag::DateTimeDigitized, Tag::DateTimeOriginal, Tag::DateTime];
+
+// the tags holding the utc offset of each date tag.
+static OFFSET_TAGS: [(Tag, Tag); 3] = [
+    (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+    (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+    (Tag::DateTime, Tag::OffsetTime),
+];
+
+// tags which only a camera fills in.
+static CAMERA_TAGS: [Tag; 4] = [
+    Tag::ExposureTime,
+    Tag::FNumber,
+    Tag::FocalLength,
+    Tag::PhotographicSensitivity,
+];
+
+/// The date of a file along with where it was read from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DateSource {
+    // the output of the date command.
+    Command(NaiveDate),
+    // the exif of an image or the metadata of a video.
+    Exif(NaiveDate),
+    // the xmp sidecar file next to the file.
+    Xmp(NaiveDate),
+    // the json sidecar of a google takeout export.
+    Takeout(NaiveDate),
+    // the date in the file name, eg. IMG_20190715_123456.jpg.
+    Name(NaiveDate),
+    Modified(NaiveDate),
+}
+
+impl DateSource {
+    pub fn date(self) -> NaiveDate {
+        match self {
+            DateSource::Command(date)
+            | DateSource::Exif(date)
+            | DateSource::Xmp(date)
+            | DateSource::Takeout(date)
+            | DateSource::Name(date)
+            | DateSource::Modified(date) => date,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DateSource::Command(_) => "command",
+            DateSource::Exif(_) => "exif",
+            DateSource::Xmp(_) => "xmp",
+            DateSource::Takeout(_) => "takeout",
+            DateSource::Name(_) => "name",
+            DateSource::Modified(_) => "mtime",
+        }
+    }
+}
+
+/// The sources a file may be dated from besides its exif and its modified
+/// time. The default reads none of them.
+#[derive(Default)]
+pub struct DateSources<'a> {
+    /// The patterns tried on the file name when there's neither an exif date
+    /// nor a sidecar, see [`parse_date_from_filename`]. Left empty to never
+    /// read the name.
+    pub name_patterns: &'a [Regex],
+    /// Read the json sidecar of a google takeout export in place of the exif
+    /// too, after the xmp sidecar.
+    pub takeout: bool,
+    /// Take the date of this command ahead of every other source, which are
+    /// only read when it has none, see [`DateCommand`].
+    pub command: Option<&'a DateCommand>,
+}
+
+/// The date of a file from its exif, or the metadata of a video, or its xmp
+/// sidecar file, and the file modified time, picked by the policy. The exif
+/// error is returned along with the modified time when it's picked. The other
+/// sources are tried as [`DateSources`] describes.
+///
+/// With a skew, an exif date too far from the modified time is returned with
+/// an error describing it, and with `fallback` it's never picked.
+///
+/// Without a zone the exif date is taken as it is and the other dates are in UTC.
+/// With a zone every date which can be placed in time is moved to it.
+pub fn get_file_date(
+    entry: &DirEntry,
+    date_tag: DateTag,
+    zone: Option<Zone>,
+    policy: DatePolicy,
+    skew: Option<DateSkew>,
+    sources: &DateSources,
+) -> (Result<DateSource>, Option<anyhow::Error>) {
+    let mut candidates = Vec::new();
+    let mut exif_error = None;
+    // the exif isn't read once the command has a date.
+    match sources
+        .command
+        .and_then(|command| command.date_of(entry.path()))
+    {
+        Some(date) => candidates.push(DateSource::Command(date)),
+        None if exif_compatible_extension(entry) => {
+            match get_dates_from_exif(entry, date_tag, zone) {
+                Ok(dates) => candidates.extend(dates.into_iter().map(DateSource::Exif)),
+                Err(err) if lacks_optional_exif(entry, &err) => {}
+                Err(err) => exif_error = Some(err),
+            }
+        }
+        None if video_extension(entry) => match get_date_from_video(entry, zone) {
+            Ok(date) => candidates.push(DateSource::Exif(date)),
+            Err(err) => exif_error = Some(err),
+        },
+        None => {}
+    }
+    // the sidecar stands in for the exif the file lacks.
+    if candidates.is_empty() {
+        candidates.extend(get_date_from_xmp_sidecar(entry).map(DateSource::Xmp));
+    }
+    if candidates.is_empty() && sources.takeout {
+        candidates.extend(get_date_from_takeout_json(entry, zone).map(DateSource::Takeout));
+    }
+    if candidates.is_empty() {
+        candidates.extend(
+            parse_date_from_filename(&entry.file_name().to_string_lossy(), sources.name_patterns)
+                .map(DateSource::Name),
+        );
+    }
+    let modified = get_date_from_file(entry, zone);
+    if let Ok(date) = &modified {
+        candidates.push(DateSource::Modified(*date));
+    }
+    let modified_date = modified.as_ref().ok().copied();
+    let suspect = |date: &DateSource| match (date, skew, modified_date) {
+        (DateSource::Exif(date), Some(skew), Some(modified)) => {
+            exceeds_skew(*date, modified, skew.max_days)
+        }
+        _ => false,
+    };
+    let skew_error = choose_date(&candidates, policy)
+        .filter(|date| suspect(date))
+        .zip(modified_date)
+        .map(|(date, modified)| {
+            anyhow::anyhow!(
+                "The exif date {} is {} days from the file modified time {}",
+                date.date(),
+                (date.date() - modified).num_days().abs(),
+                modified
+            )
+        });
+    if skew_error.is_some() && skew.is_some_and(|skew| skew.fallback) {
+        candidates.retain(|date| !suspect(date));
+    }
+    match choose_date(&candidates, policy) {
+        Some(date @ DateSource::Modified(_)) => (Ok(date), exif_error.or(skew_error)),
+        Some(date) => (Ok(date), skew_error),
+        None => (modified.map(DateSource::Modified), exif_error),
+    }
+}
+
+/// The date in a file name from the first of the patterns which matches it
+/// with a valid date. The patterns capture the `year`, `month` and `day` groups.
+pub fn parse_date_from_filename(name: &str, patterns: &[Regex]) -> Option<NaiveDate> {
+    patterns.iter().find_map(|pattern| {
+        let captures = pattern.captures(name)?;
+        let group = |group: &str| captures.name(group)?.as_str().parse::<u32>().ok();
+        let year = i32::try_from(group("year")?).ok()?;
+        NaiveDate::from_ymd_opt(year, group("month")?, group("day")?)
+    })
+}
+
+/// The patterns for [`parse_date_from_filename`], the given ones followed by
+/// the built in ones for the file names of phones and cameras.
+pub(crate) fn name_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    let mut compiled = Vec::new();
+    for pattern in patterns {
+        let regex = Regex::new(pattern)
+            .map_err(|err| anyhow::anyhow!("Invalid pattern {} - {}", pattern, err))?;
+        let groups: Vec<&str> = regex.capture_names().flatten().collect();
+        if let Some(group) = ["year", "month", "day"]
+            .into_iter()
+            .find(|group| !groups.contains(group))
+        {
+            anyhow::bail!("The pattern {} has no {} group", pattern, group);
+        }
+        compiled.push(regex);
+    }
+    compiled.extend(
+        NAME_PATTERNS
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("the built in patterns are valid")),
+    );
+    Ok(compiled)
+}
+
+// the candidates are in order of preference, from the exif tags to the modified time.
+fn choose_date(candidates: &[DateSource], policy: DatePolicy) -> Option<DateSource> {
+    match policy {
+        DatePolicy::ExifFirst => candidates.first(),
+        DatePolicy::MtimeFirst => candidates
+            .iter()
+            .find(|date| matches!(date, DateSource::Modified(_)))
+            .or(candidates.first()),
+        DatePolicy::Oldest => candidates.iter().min_by_key(|date| date.date()),
+        // max_by_key keeps the last of equal dates, so the candidates are reversed
+        // to keep the preferred one like min_by_key does.
+        DatePolicy::Newest => candidates.iter().rev().max_by_key(|date| date.date()),
+    }
+    .copied()
+}
+
+fn get_date_from_file(entry: &DirEntry, zone: Option<Zone>) -> Result<NaiveDate> {
+    let datetime: DateTime<Utc> = entry
+        .metadata()
+        .context("Failed to read file metadata")?
+        .modified()
+        .context("Failed to read file modified time")?
+        .into();
+    Ok(date_in_zone(&datetime, zone))
+}
+
+fn date_in_zone(instant: &DateTime<Utc>, zone: Option<Zone>) -> NaiveDate {
+    match zone {
+        Some(zone) => zone.date_of(instant),
+        None => instant.date_naive(),
+    }
+}
+
+fn get_dates_from_exif(
+    entry: &DirEntry,
+    date_tag: DateTag,
+    zone: Option<Zone>,
+) -> Result<Vec<NaiveDate>> {
+    let datetimes = get_datetimes_from_exif(entry, date_tag)?;
+    // the local time can only be moved to the zone when its offset is known.
+    datetimes
+        .into_iter()
+        .map(|(datetime, offset)| match (zone, offset) {
+            (Some(zone), Some(offset)) => offset
+                .from_local_datetime(&datetime)
+                .single()
+                .map(|datetime| zone.date_of(&datetime))
+                .context("Failed to place the exif datetime at its offset"),
+            _ => Ok(datetime.date()),
+        })
+        .collect()
+}
+
+/// The moment a photo was taken according to its exif. A local time without
+/// an offset is taken as UTC, which keeps the photos of a camera in order.
+pub(crate) fn get_capture_time(entry: &DirEntry, date_tag: DateTag) -> Option<DateTime<Utc>> {
+    if !exif_compatible_extension(entry) {
+        return None;
+    }
+    let (datetime, offset) = *get_datetimes_from_exif(entry, date_tag).ok()?.first()?;
+    match offset {
+        Some(offset) => Some(offset.from_local_datetime(&datetime).single()?.to_utc()),
+        None => Some(datetime.and_utc()),
+    }
+}
+
+// the valid datetimes in the order of the tags, eg. the original date first.
+fn get_datetimes_from_exif(
+    entry: &DirEntry,
+    date_tag: DateTag,
+) -> Result<Vec<(NaiveDateTime, Option<FixedOffset>)>> {
+    let exif = read_exif(entry.path())?;
+    let tags = match date_tag {
+        DateTag::Original => ORIGINAL_FIRST,
+        DateTag::Digitized => DIGITIZED_FIRST,
+        DateTag::Auto if is_scanner(&exif) => DIGITIZED_FIRST,
+        DateTag::Auto => ORIGINAL_FIRST,
+    };
+    let datetimes: Vec<(String, Option<FixedOffset>)> = tags
+        .iter()
+        .filter_map(|&tag| {
+            let field = exif.get_field(tag, In::PRIMARY)?;
+            let Value::Ascii(values) = &field.value else {
+                return None;
+            };
+            let value = String::from_utf8_lossy(values.first()?).into_owned();
+            Some((value, read_offset(&exif, tag)))
+        })
+        .collect();
+    if datetimes.is_empty() {
+        anyhow::bail!("No datetime in the exif data");
+    }
+    let datetimes: Vec<_> = datetimes
+        .iter()
+        .filter_map(|(datetime, offset)| {
+            parse_exif_datetime(datetime).map(|datetime| (datetime, *offset))
+        })
+        .collect();
+    if datetimes.is_empty() {
+        anyhow::bail!("Failed to parse the exif datetime");
+    }
+    Ok(datetimes)
+}
+
+/// The datetime of an exif tag, eg. `2008:05:30 15:56:01`. Some cameras add
+/// subseconds or an offset after it, or write dashes or a `T` instead, so a
+/// value which doesn't parse in full falls back to its leading date at midnight.
+fn parse_exif_datetime(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let datetime = value.get(..19).and_then(|datetime| {
+        [
+            "%Y:%m:%d %H:%M:%S",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M:%S",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(datetime, format).ok())
+    });
+    datetime.or_else(|| {
+        let date = value.get(..10)?;
+        ["%Y:%m:%d", "%Y-%m-%d"]
+            .iter()
+            .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+}
+
+// the names of the takeout sidecar of a file, after its own name.
+static TAKEOUT_SIDECAR_SUFFIXES: [&str; 2] = [".json", ".supplemental-metadata.json"];
+
+// the xmp properties holding the capture date, by namespace, in the order they're tried.
+static XMP_DATE_PROPERTIES: [(&str, &str); 3] = [
+    ("http://ns.adobe.com/exif/1.0/", "DateTimeOriginal"),
+    ("http://ns.adobe.com/xap/1.0/", "CreateDate"),
+    ("http://ns.adobe.com/photoshop/1.0/", "DateCreated"),
+];
+
+/// The capture date from the xmp sidecar of a file, eg. `IMG_0001.xmp` or
+/// `IMG_0001.CR2.xmp` next to `IMG_0001.CR2`, as written by raw editors.
+fn get_date_from_xmp_sidecar(entry: &DirEntry) -> Option<NaiveDate> {
+    let path = entry.path();
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"))
+    {
+        return None;
+    }
+    let mut with_extension = path.as_os_str().to_owned();
+    with_extension.push(".xmp");
+    let sidecars = [
+        path.with_extension("xmp"),
+        path.with_extension("XMP"),
+        with_extension.into(),
+    ];
+    let contents = sidecars
+        .iter()
+        .find_map(|sidecar| std::fs::read_to_string(sidecar).ok())?;
+    parse_xmp_date(&contents)
+}
+
+/// The capture date from the json sidecar google takeout exports next to a
+/// photo, eg. `IMG_0001.jpg.json`, or `IMG_0001.jpg.supplemental-metadata.json`
+/// in the newer exports. It's a unix timestamp, so it's in UTC without a zone.
+fn get_date_from_takeout_json(entry: &DirEntry, zone: Option<Zone>) -> Option<NaiveDate> {
+    let path = entry.path();
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    {
+        return None;
+    }
+    let contents = TAKEOUT_SIDECAR_SUFFIXES.iter().find_map(|suffix| {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        std::fs::read_to_string(sidecar).ok()
+    })?;
+    let timestamp = parse_takeout_timestamp(&contents)?;
+    Some(date_in_zone(&DateTime::from_timestamp(timestamp, 0)?, zone))
+}
+
+// the timestamp is written as a string of seconds, eg. "1503185400".
+fn parse_takeout_timestamp(contents: &str) -> Option<i64> {
+    let metadata: serde_json::Value = serde_json::from_str(contents).ok()?;
+    match &metadata["photoTakenTime"]["timestamp"] {
+        serde_json::Value::String(timestamp) => timestamp.trim().parse().ok(),
+        timestamp => timestamp.as_i64(),
+    }
+}
+
+// the properties are written either as attributes of the rdf:Description or as
+// elements within it. The date is in ISO 8601, eg. 2019-06-12T10:30:00+02:00,
+// and its local date is used like that of the exif.
+fn parse_xmp_date(contents: &str) -> Option<NaiveDate> {
+    let document = roxmltree::Document::parse(contents).ok()?;
+    XMP_DATE_PROPERTIES.iter().find_map(|&(namespace, name)| {
+        document.descendants().find_map(|node| {
+            let value = node
+                .attribute((namespace, name))
+                .or_else(|| node.has_tag_name((namespace, name)).then(|| node.text())?)?;
+            NaiveDate::parse_from_str(value.trim().get(..10)?, "%Y-%m-%d").ok()
+        })
+    })
+}
+
+// the offset is written as text, eg. +05:30
+fn read_offset(exif: &exif::Exif, tag: Tag) -> Option<FixedOffset> {
+    let (_, offset_tag) = OFFSET_TAGS.iter().find(|(date_tag, _)| *date_tag == tag)?;
+    match &exif.get_field(*offset_tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => std::str::from_utf8(values.first()?)
+            .ok()?
+            .trim()
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_exif(path: &Path) -> Result<exif::Exif> {
+    let file = File::open(path).context("Failed to open the file for reading exif")?;
+    let mut bufreader = BufReader::new(&file);
+    let exifreader = exif::Reader::new();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_ref().and_then(|extension| extension.to_str()) {
+        Some("raf") => {
+            let mut header = [0; RAF_JPEG_OFFSET + 8];
+            bufreader.read_exact(&mut header)?;
+            if !header.starts_with(RAF_MAGIC) {
+                anyhow::bail!("Not a Fujifilm raw file");
+            }
+            let offset = u32::from_be_bytes(header[RAF_JPEG_OFFSET..][..4].try_into()?);
+            let len = u32::from_be_bytes(header[RAF_JPEG_OFFSET + 4..][..4].try_into()?);
+            bufreader.seek(SeekFrom::Start(offset.into()))?;
+            let mut jpeg = Vec::new();
+            bufreader.take(len.into()).read_to_end(&mut jpeg)?;
+            Ok(exifreader.read_from_container(&mut Cursor::new(jpeg))?)
+        }
+        Some("orf") => {
+            // olympus raw files are tiff files with their own magic number.
+            let mut data = Vec::new();
+            bufreader.read_to_end(&mut data)?;
+            match data.get(..4) {
+                Some(b"IIRO") | Some(b"IIRS") => data[2..4].copy_from_slice(&[0x2a, 0]),
+                Some(b"MMOR") => data[2..4].copy_from_slice(&[0, 0x2a]),
+                _ => {}
+            }
+            Ok(exifreader.read_raw(data)?)
+        }
+        _ => Ok(exifreader.read_from_container(&mut bufreader)?),
+    }
+}
+
+fn get_date_from_video(entry: &DirEntry, zone: Option<Zone>) -> Result<NaiveDate> {
+    let file = File::open(entry.path()).context("Failed to open the video for reading")?;
+    let mut bufreader = BufReader::new(&file);
+    let moov_len =
+        find_box(&mut bufreader, u64::MAX, b"moov")?.context("No moov box in the video")?;
+    find_box(&mut bufreader, moov_len, b"mvhd")?.context("No mvhd box in the video")?;
+
+    // the version decides between 32 and 64 bit times.
+    let mut version_and_flags = [0; 4];
+    bufreader.read_exact(&mut version_and_flags)?;
+    let creation_time = if version_and_flags[0] == 1 {
+        let mut creation_time = [0; 8];
+        bufreader.read_exact(&mut creation_time)?;
+        u64::from_be_bytes(creation_time)
+    } else {
+        let mut creation_time = [0; 4];
+        bufreader.read_exact(&mut creation_time)?;
+        u32::from_be_bytes(creation_time).into()
+    };
+    if creation_time == 0 {
+        anyhow::bail!("No creation time in the video");
+    }
+    let timestamp = i64::try_from(creation_time)? - QUICKTIME_EPOCH_OFFSET;
+    let datetime = DateTime::from_timestamp(timestamp, 0)
+        .context("Failed to parse the video creation time")?;
+    Ok(date_in_zone(&datetime, zone))
+}
+
+// walks the boxes in the next `len` bytes and stops at the contents of the first
+// box of the given type, returning their length.
+fn find_box<R: Read + Seek>(reader: &mut R, len: u64, box_type: &[u8; 4]) -> Result<Option<u64>> {
+    let mut offset = 0;
+    while offset < len {
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let mut header_len = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into()?) {
+            // the box runs to the end of its parent.
+            0 => len - offset,
+            // the real size follows the type.
+            1 => {
+                let mut size = [0; 8];
+                reader.read_exact(&mut size)?;
+                header_len = 16;
+                u64::from_be_bytes(size)
+            }
+            size => size.into(),
+        };
+        if size < header_len {
+            anyhow::bail!("Invalid box size in the video");
+        }
+        if &header[4..] == box_type {
+            return Ok(Some(size - header_len));
+        }
+        reader.seek(SeekFrom::Current(i64::try_from(size - header_len)?))?;
+        offset += size;
+    }
+    Ok(None)
+}
+
+// scanners record the scan in the digitized date while the original date is
+// often rewritten by the software exporting the scan.
+fn is_scanner(exif: &exif::Exif) -> bool {
+    let make_or_model = [Tag::Make, Tag::Model].iter().any(|&tag| {
+        exif.get_field(tag, In::PRIMARY).is_some_and(|field| {
+            let value = field.display_value().to_string().to_lowercase();
+            SCANNER_KEYWORDS
+                .iter()
+                .any(|keyword| value.contains(keyword))
+        })
+    });
+    let camera_tags = CAMERA_TAGS
+        .iter()
+        .any(|&tag| exif.get_field(tag, In::PRIMARY).is_some());
+    make_or_model || !camera_tags
+}
+
+fn video_extension(entry: &DirEntry) -> bool {
+    entry.path().extension().is_some_and(|extension| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|&e| e == extension.to_ascii_lowercase())
+    })
+}
+
+// most pngs have no exif, eg. screenshots, so a png without an eXIf chunk is
+// dated by its modified time without a warning.
+fn lacks_optional_exif(entry: &DirEntry, err: &anyhow::Error) -> bool {
+    let png = entry
+        .path()
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    png && matches!(err.downcast_ref(), Some(exif::Error::NotFound(_)))
+}
+
+pub(crate) fn exif_compatible_extension(entry: &DirEntry) -> bool {
+    entry.path().extension().is_some_and(|extension| {
+        EXIF_COMPATIBLE_EXTENSIONS
+            .iter()
+            .any(|&e| e == extension.to_ascii_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 1, day).unwrap()
+    }
+
+    // the original and digitized exif dates, and the modified time.
+    fn candidates() -> [DateSource; 3] {
+        [
+            DateSource::Exif(day(6)),
+            DateSource::Exif(day(4)),
+            DateSource::Modified(day(5)),
+        ]
+    }
+
+    fn datetime(day: u32, hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2008, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_exif_datetime_reads_the_exif_format() {
+        assert_eq!(
+            parse_exif_datetime("2008:05:30 15:56:01"),
+            Some(datetime(30, 15, 56, 1))
+        );
+        assert_eq!(
+            parse_exif_datetime("2008-05-30 15:56:01\0"),
+            Some(datetime(30, 15, 56, 1))
+        );
+        assert_eq!(
+            parse_exif_datetime("2008-05-30T15:56:01"),
+            Some(datetime(30, 15, 56, 1))
+        );
+    }
+
+    #[test]
+    fn parse_exif_datetime_ignores_subseconds_and_offsets() {
+        assert_eq!(
+            parse_exif_datetime("2008:05:30 15:56:01.123"),
+            Some(datetime(30, 15, 56, 1))
+        );
+        assert_eq!(
+            parse_exif_datetime("2008:05:30 15:56:01+02:00"),
+            Some(datetime(30, 15, 56, 1))
+        );
+    }
+
+    #[test]
+    fn parse_exif_datetime_falls_back_to_the_date() {
+        assert_eq!(
+            parse_exif_datetime("2008:05:30"),
+            Some(datetime(30, 0, 0, 0))
+        );
+        assert_eq!(
+            parse_exif_datetime("2008-05-30 at noon"),
+            Some(datetime(30, 0, 0, 0))
+        );
+        assert_eq!(parse_exif_datetime("    :  :     :  :  "), None);
+        assert_eq!(parse_exif_datetime("2008:13:30 15:56:01"), None);
+    }
+
+    #[test]
+    fn choose_date_prefers_the_exif_or_the_modified_time() {
+        let candidates = candidates();
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::ExifFirst),
+            Some(DateSource::Exif(day(6)))
+        );
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::MtimeFirst),
+            Some(DateSource::Modified(day(5)))
+        );
+    }
+
+    #[test]
+    fn choose_date_picks_the_oldest_or_newest() {
+        let candidates = candidates();
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::Oldest),
+            Some(DateSource::Exif(day(4)))
+        );
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::Newest),
+            Some(DateSource::Exif(day(6)))
+        );
+    }
+
+    #[test]
+    fn choose_date_prefers_the_earlier_candidate_of_equal_dates() {
+        let candidates = [DateSource::Exif(day(6)), DateSource::Modified(day(6))];
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::Oldest),
+            Some(DateSource::Exif(day(6)))
+        );
+        assert_eq!(
+            choose_date(&candidates, DatePolicy::Newest),
+            Some(DateSource::Exif(day(6)))
+        );
+    }
+
+    #[test]
+    fn choose_date_falls_back_to_what_is_there() {
+        let exif_only = [DateSource::Exif(day(6))];
+        assert_eq!(
+            choose_date(&exif_only, DatePolicy::MtimeFirst),
+            Some(DateSource::Exif(day(6)))
+        );
+        let mtime_only = [DateSource::Modified(day(5))];
+        assert_eq!(
+            choose_date(&mtime_only, DatePolicy::ExifFirst),
+            Some(DateSource::Modified(day(5)))
+        );
+        assert_eq!(choose_date(&[], DatePolicy::Oldest), None);
+    }
+
+    #[test]
+    fn splits_the_date_command_like_a_shell() {
+        let command: DateCommand =
+            r#"exiftool -d "%Y-%m-%d %H" -s3 'Date Time' a\ b"#.parse().unwrap();
+        assert_eq!(command.program, "exiftool");
+        assert_eq!(
+            command.args,
+            ["-d", "%Y-%m-%d %H", "-s3", "Date Time", "a b"]
+        );
+        assert_eq!(
+            split_words(r#"say "" 'it''s'"#).unwrap(),
+            ["say", "", "its"]
+        );
+        assert!("exiftool -d \"%Y".parse::<DateCommand>().is_err());
+        assert!("  ".parse::<DateCommand>().is_err());
+    }
+
+    #[test]
+    fn parses_the_date_leading_the_command_output() {
+        let date = NaiveDate::from_ymd_opt(2019, 7, 15);
+        assert_eq!(parse_command_output("2019-07-15\n"), date);
+        assert_eq!(parse_command_output("2019-07-15 12:34:56\n"), date);
+        assert_eq!(parse_command_output("2019:07:15 12:34:56\n"), None);
+        assert_eq!(parse_command_output(""), None);
+    }
+
+    fn date_from_name(name: &str) -> Option<NaiveDate> {
+        parse_date_from_filename(name, &name_patterns(&[]).unwrap())
+    }
+
+    #[test]
+    fn parses_the_android_file_names() {
+        let date = NaiveDate::from_ymd_opt(2019, 7, 15);
+        assert_eq!(date_from_name("IMG_20190715_123456.jpg"), date);
+        assert_eq!(date_from_name("VID_20190715_123456.mp4"), date);
+        assert_eq!(date_from_name("PXL_20190715_123456789.jpg"), date);
+        assert_eq!(date_from_name("20190715_123456.jpg"), date);
+        assert_eq!(date_from_name("Screenshot_20190715-123456.png"), date);
+        assert_eq!(date_from_name("IMG-20190715-WA0001.jpg"), date);
+    }
+
+    #[test]
+    fn parses_the_ios_and_dropbox_file_names() {
+        let date = NaiveDate::from_ymd_opt(2019, 7, 15);
+        assert_eq!(date_from_name("2019-07-15 12.34.56.jpg"), date);
+        assert_eq!(
+            date_from_name("Screenshot 2019-07-15 at 12.34.56.png"),
+            date
+        );
+        assert_eq!(date_from_name("signal-2019-07-15-123456.jpg"), date);
+    }
+
+    #[test]
+    fn leaves_out_the_names_without_a_valid_date() {
+        assert_eq!(date_from_name("IMG_1234.JPG"), None);
+        assert_eq!(date_from_name("IMG_20191345_123456.jpg"), None);
+        // a longer number is not a date.
+        assert_eq!(date_from_name("120190715_123456.jpg"), None);
+    }
+
+    #[test]
+    fn tries_the_given_patterns_first() {
+        let patterns =
+            name_patterns(&[r"^(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{4})".to_string()])
+                .unwrap();
+        assert_eq!(
+            parse_date_from_filename("15.07.2019 IMG_20200101_000000.jpg", &patterns),
+            NaiveDate::from_ymd_opt(2019, 7, 15)
+        );
+        assert!(name_patterns(&[r"(?P<year>\d{4})".to_string()]).is_err());
+    }
+
+    #[test]
+    fn flags_the_dates_beyond_the_skew() {
+        assert!(!exceeds_skew(day(6), day(4), 2));
+        assert!(exceeds_skew(day(6), day(3), 2));
+        // in either direction.
+        assert!(exceeds_skew(day(3), day(6), 2));
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        assert!(exceeds_skew(epoch, day(6), 3650));
+    }
+
+    #[test]
+    fn reads_the_takeout_timestamp() {
+        let sidecar = r#"{"photoTakenTime": {"timestamp": "1503185400", "formatted": ""}}"#;
+        assert_eq!(parse_takeout_timestamp(sidecar), Some(1503185400));
+        assert_eq!(
+            parse_takeout_timestamp(r#"{"photoTakenTime": {"timestamp": 1503185400}}"#),
+            Some(1503185400)
+        );
+        assert_eq!(
+            parse_takeout_timestamp(r#"{"creationTime": {"timestamp": "1503185400"}}"#),
+            None
+        );
+        assert_eq!(parse_takeout_timestamp("not json"), None);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::camera::{get_camera_model, get_lens, UNKNOWN_CAMERA, UNKNOWN_LENS};
+use crate::location::{find_place, get_gps_from_exif};
+use crate::orientation::{get_orientation, Orientation, UNKNOWN_ORIENTATION};
+use clap::ArgEnum;
+use walkdir::DirEntry;
+
+/// The folder of the photos whose place is not known, eg. those without GPS
+/// coordinates or taken far from every city.
+pub static UNKNOWN_LOCATION: &str = "Unknown Location";
+
+/// Names the folder a file goes under before the layout, eg. its camera model.
+/// A file it knows nothing about still gets a folder, eg. `Unknown`.
+pub trait GroupResolver {
+    fn folder(&self, entry: &DirEntry) -> String;
+}
+
+/// The folder of the camera model from the exif, eg. `Canon EOS 40D`.
+pub struct CameraGroup;
+
+impl GroupResolver for CameraGroup {
+    fn folder(&self, entry: &DirEntry) -> String {
+        get_camera_model(entry).unwrap_or_else(|| UNKNOWN_CAMERA.to_string())
+    }
+}
+
+/// The folder of the lens model from the exif, eg. `EF-S17-85mm f-4-5.6 IS USM`.
+pub struct LensGroup;
+
+impl GroupResolver for LensGroup {
+    fn folder(&self, entry: &DirEntry) -> String {
+        get_lens(entry).unwrap_or_else(|| UNKNOWN_LENS.to_string())
+    }
+}
+
+/// The `Landscape`, `Portrait` or `Square` folder of an image.
+pub struct OrientationGroup;
+
+impl GroupResolver for OrientationGroup {
+    fn folder(&self, entry: &DirEntry) -> String {
+        get_orientation(entry)
+            .map_or(UNKNOWN_ORIENTATION, Orientation::folder)
+            .to_string()
+    }
+}
+
+/// The country and city folders of the GPS coordinates, eg. `France/Paris`.
+pub struct LocationGroup;
+
+impl GroupResolver for LocationGroup {
+    fn folder(&self, entry: &DirEntry) -> String {
+        get_gps_from_exif(entry)
+            .and_then(|(latitude, longitude)| find_place(latitude, longitude))
+            .map_or_else(
+                || UNKNOWN_LOCATION.to_string(),
+                |place| format!("{}/{}", place.country, place.city),
+            )
+    }
+}
+
+/// A field the files are grouped by, each a folder before the layout. Given
+/// more than once, the folders nest in the order of the fields.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum GroupBy {
+    Camera,
+    Lens,
+    Orientation,
+    Location,
+}
+
+impl GroupBy {
+    pub fn resolver(self) -> &'static dyn GroupResolver {
+        match self {
+            GroupBy::Camera => &CameraGroup,
+            GroupBy::Lens => &LensGroup,
+            GroupBy::Orientation => &OrientationGroup,
+            GroupBy::Location => &LocationGroup,
+        }
+    }
+}
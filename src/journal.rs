@@ -0,0 +1,188 @@
+use crate::sorter::{move_file, Transfer};
+use anyhow::{Context, Result};
+use colored::*;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{LineWriter, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// An action taken by a run, as recorded in the journal.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JournalEntry {
+    pub action: Transfer,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    // the directories at the target are pruned up to, but not including, this one.
+    pub target_dir: PathBuf,
+    pub bytes: u64,
+}
+
+/// Appends a JSON line for every action as soon as it's taken, so an
+/// interrupted run can still be undone.
+pub(crate) struct Journal {
+    writer: LineWriter<File>,
+    target_dir: PathBuf,
+}
+
+impl Journal {
+    pub(crate) fn open(path: &Path, target_dir: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            writer: LineWriter::new(file),
+            target_dir: std::path::absolute(target_dir)?,
+        })
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        action: Transfer,
+        source: &Path,
+        target: &Path,
+        bytes: u64,
+    ) -> Result<()> {
+        let entry = JournalEntry {
+            action,
+            source: source.to_path_buf(),
+            // a link target is kept as it is, not resolved to the source.
+            target: std::path::absolute(target)?,
+            target_dir: self.target_dir.clone(),
+            bytes,
+        };
+        writeln!(self.writer, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct UndoSummary {
+    pub undone_count: u32,
+    pub skipped_count: u32,
+    pub error_count: u32,
+}
+
+impl UndoSummary {
+    pub fn display(&self) -> String {
+        let mut messages = Vec::new();
+        messages.push("\n".to_string());
+        messages.push(format!("{} {} actions", "Undid".green(), self.undone_count));
+        if self.skipped_count > 0 {
+            messages.push(format!(
+                "{} {} actions since their target no longer matches the journal",
+                "Skipped".cyan(),
+                self.skipped_count
+            ));
+        }
+        if self.error_count > 0 {
+            messages.push(format!(
+                "{} to undo {} actions",
+                "Failed".red(),
+                self.error_count
+            ));
+        }
+        messages.join("\n")
+    }
+}
+
+/// Reverses the actions recorded in a journal, latest first. Copies and links
+/// are removed, moved files are moved back and the directories left empty at
+/// the target are removed. An action whose target no longer matches the journal,
+/// eg. because it was already undone, is skipped.
+pub fn undo(journal: &Path) -> Result<UndoSummary> {
+    let contents = fs::read_to_string(journal).context("Failed to read the journal")?;
+    let entries = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str::<JournalEntry>)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse the journal")?;
+
+    let mut summary = UndoSummary::default();
+    for entry in entries.iter().rev() {
+        match undo_entry(entry) {
+            Ok(true) => {
+                summary.undone_count += 1;
+                prune_empty_dirs(&entry.target, &entry.target_dir);
+            }
+            Ok(false) => summary.skipped_count += 1,
+            Err(err) => {
+                error!(
+                    "{} while undoing the {} of {} - [{}]",
+                    "Error".red(),
+                    entry.action.noun(),
+                    entry.source.display(),
+                    err
+                );
+                summary.error_count += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+// false when the target no longer holds what the run left there.
+fn undo_entry(entry: &JournalEntry) -> std::io::Result<bool> {
+    let target = match entry.target.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    match entry.action {
+        Transfer::Symlink => {
+            if !target.file_type().is_symlink() || link_source(&entry.target)? != entry.source {
+                return Ok(false);
+            }
+            fs::remove_file(&entry.target)?;
+        }
+        Transfer::Copy | Transfer::Hardlink => {
+            if !target.is_file() || target.len() != entry.bytes {
+                return Ok(false);
+            }
+            fs::remove_file(&entry.target)?;
+        }
+        Transfer::Move => {
+            if !target.is_file() || target.len() != entry.bytes || entry.source.exists() {
+                return Ok(false);
+            }
+            if let Some(parent) = entry.source.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            move_file(&entry.target, &entry.source)?;
+        }
+    }
+    Ok(true)
+}
+
+// the source a link points at. A relative link, made with --relative-symlink,
+// leads from the resolved folder of the link like it was made from.
+fn link_source(link: &Path) -> std::io::Result<PathBuf> {
+    let source = fs::read_link(link)?;
+    let folder = match link.parent() {
+        Some(folder) if source.is_relative() => fs::canonicalize(folder)?,
+        _ => return Ok(source),
+    };
+    let mut resolved = PathBuf::new();
+    for component in folder.join(source).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    Ok(resolved)
+}
+
+// removes the directories emptied by the undo, stopping at the target directory.
+pub(crate) fn prune_empty_dirs(target: &Path, target_dir: &Path) {
+    let mut dir = target.parent();
+    while let Some(path) = dir {
+        if path == target_dir || !path.starts_with(target_dir) || fs::remove_dir(path).is_err() {
+            break;
+        }
+        dir = path.parent();
+    }
+}
@@ -1,21 +1,589 @@
-use std::path::PathBuf;
+//! Sorts photos into a target directory based on their exif timestamp.
+//!
+//! The [`sort`] function runs the same sort as the `photosort` binary, so it can
+//! be embedded in other tools without spawning a process.
+//!
+//! ```no_run
+//! use photosort::{SortOptions, Transfer};
+//!
+//! let options = SortOptions {
+//!     layout: "%Y/%m".to_string(),
+//!     transfer: Transfer::Move,
+//!     ..SortOptions::new("/media/camera/DCIM", "/home/me/Pictures")
+//! };
+//! let summary = photosort::sort(options)?;
+//! println!("Moved {} photos", summary.moved_count);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The warnings and errors for the files which fail to sort are written through
+//! the [`log`](https://docs.rs/log) crate, so they show up in the logger of the
+//! embedding tool.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use colored::Colorize;
+use log::error;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+mod archive;
+mod camera;
+mod date;
+mod group;
+mod journal;
+mod location;
+mod orientation;
+mod owner;
+mod prompt;
+mod sorter;
+mod state;
+mod template;
+
+pub use camera::{get_camera_model, get_lens, UNKNOWN_CAMERA, UNKNOWN_LENS};
+pub use date::{
+    exceeds_skew, get_file_date, parse_date_from_filename, DateCommand, DatePolicy, DateSkew,
+    DateSource, DateSources, DateTag, Zone,
+};
+pub use group::{
+    CameraGroup, GroupBy, GroupResolver, LensGroup, LocationGroup, OrientationGroup,
+    UNKNOWN_LOCATION,
+};
+pub use journal::{undo, JournalEntry, UndoSummary};
+pub use location::{find_place, get_gps_from_exif, Place};
+pub use orientation::{get_orientation, Orientation, UNKNOWN_ORIENTATION};
+pub use owner::Owner;
+pub use prompt::ask_on_collision;
+pub use sorter::{
+    CollisionHook, EventHook, SortEvent, SortOrder, SortedFile, TargetExists, Transfer,
+};
+pub use template::{
+    get_target_path, group_root, validate_template, FileNaming, MonthFormat, DEFAULT_TEMPLATE,
+};
+
+/// What to sort and how. Start from [`SortOptions::new`] and override the
+/// fields which differ from the defaults.
+pub struct SortOptions {
+    /// The directory to sort, or a glob pattern for the files to sort, eg. `photos/*.jpg`.
+    pub source_dir: PathBuf,
+    pub target_dir: PathBuf,
+    /// Walk into the symlinked files and directories of the source.
+    pub follow_symlinks: bool,
+    /// Leave out the files and directories whose name starts with a dot, eg.
+    /// `.thumbnails`, without walking into them.
+    pub skip_hidden: bool,
+    /// Only sort the files at least this many directories down the source, where
+    /// the files directly in the source are at depth 1.
+    pub min_depth: Option<usize>,
+    /// Only sort the files at most this many directories down the source.
+    pub max_depth: Option<usize>,
+    /// The folder layout at the target as a strftime pattern. Defaults to [`DEFAULT_TEMPLATE`].
+    pub layout: String,
+    pub month_format: MonthFormat,
+    /// Added to the start of every file name at the target as a strftime pattern, eg. `%Y-%m-%d_`.
+    pub name_prefix: Option<String>,
+    /// Added to the end of every file name at the target, before the extension.
+    pub name_suffix: Option<String>,
+    /// Replaces every file name at the target, eg. `{date}_{camera}_{counter}.{ext}`.
+    /// See [`FileNaming::rename`] for the tokens.
+    pub rename: Option<String>,
+    /// Sort the photos with GPS coordinates into country and city folders
+    /// followed by the year. The rest keep the layout.
+    pub by_location: bool,
+    /// Sort the files into a folder for each of the fields first, eg. their
+    /// camera model in Canon EOS 40D/2008/May/30, nested in the given order.
+    /// See [`GroupBy`] for the fields.
+    pub group_by: Vec<GroupBy>,
+    /// Store the files in a zip archive for every day at the root of the target,
+    /// eg. `2008-05-30.zip`, rather than in the folders of the layout.
+    pub archive: bool,
+    /// Sort the files with these extensions under another target directory, eg.
+    /// the videos. The rest go under `target_dir`.
+    pub routes: Vec<(String, PathBuf)>,
+    pub transfer: Transfer,
+    /// Point the symlinks at the source by a path relative to their folder, so
+    /// the source and the target can be moved together.
+    pub relative_symlink: bool,
+    pub date_tag: DateTag,
+    pub date_policy: DatePolicy,
+    /// Never date a file by its modified time. A file without a capture date,
+    /// from its exif, sidecar or name, is left out, or goes to `unknown_dir`.
+    pub exif_only: bool,
+    /// The folder under the target for the files without a capture date with
+    /// `exif_only`, eg. `Unknown`.
+    pub unknown_dir: Option<PathBuf>,
+    /// Warn about an exif date this far from the file modified time, and
+    /// optionally sort the file by its modified time instead.
+    pub date_skew: Option<DateSkew>,
+    /// Read the date from the json sidecar of a google takeout export, eg.
+    /// IMG_0001.jpg.json, when the file has no exif.
+    pub takeout: bool,
+    /// Ask this command for the date of every file before reading its exif,
+    /// eg. for a format the exif isn't read from.
+    pub date_command: Option<DateCommand>,
+    /// Read the date from the file name, eg. IMG_20190715_123456.jpg, when the
+    /// file has no exif. It's tried before the modified time.
+    pub date_from_name: bool,
+    /// More regexes for the date in a file name, tried before the built in
+    /// ones. They capture the `year`, `month` and `day` groups.
+    pub name_patterns: Vec<String>,
+    /// Move the dates to this time zone. Without it the exif dates are taken as they are.
+    pub zone: Option<Zone>,
+    /// With `verify_content` an overwrite leaves a file with the same contents alone.
+    pub target_exists: TargetExists,
+    /// Replace a file at the target only when the source was modified after
+    /// it, whatever their sizes, and skip it otherwise, like `cp -u`.
+    pub update: bool,
+    /// Only sort the files captured on or after this date.
+    pub after: Option<NaiveDate>,
+    /// Only sort the files captured on or before this date.
+    pub before: Option<NaiveDate>,
+    /// Leave out the empty source files rather than sorting them.
+    pub skip_empty: bool,
+    /// Only sort the files of at least this many bytes.
+    pub min_size: Option<u64>,
+    /// Only sort the files of at most this many bytes.
+    pub max_size: Option<u64>,
+    /// Only sort the files with these extensions. Empty sorts every extension.
+    pub include_ext: Vec<String>,
+    pub exclude_ext: Vec<String>,
+    /// Stop copying before the copied bytes exceed this many bytes.
+    pub max_bytes: Option<u64>,
+    pub sort_order: SortOrder,
+    /// The number of files sorted at once. Defaults to the number of logical
+    /// CPUs, or to 4 when the files are hashed since the copies are then left
+    /// with the disk to themselves.
+    pub jobs: Option<usize>,
+    /// The number of files hashed at once for `dedupe_by_hash`, `stable_by_hash`
+    /// and `no_clobber_across_dates`. Defaults to the number of logical CPUs.
+    pub hash_threads: Option<usize>,
+    pub dedupe_by_hash: bool,
+    /// Skip the files whose contents are already anywhere at the target, so a
+    /// file whose date changed since an earlier run isn't sorted again.
+    pub stable_by_hash: bool,
+    /// Skip the files whose contents are already at the target under the same
+    /// name, in any date folder, eg. after exif was added to a sorted photo.
+    pub no_clobber_across_dates: bool,
+    /// Compare the contents of a file with the file of the same name and size
+    /// at the target before skipping it. A different file is a collision.
+    pub verify_content: bool,
+    /// Carry the modified time of a copied file over to the copy.
+    pub preserve_mtime: bool,
+    /// Give a copied file the permission bits of its source on Unix.
+    pub preserve_perms: bool,
+    /// Copy the extended attributes of a copied file over to the copy on Linux
+    /// and macOS, eg. the Finder tags and comments.
+    pub preserve_xattrs: bool,
+    /// Give the copied or moved files, and the folders created for them, this
+    /// owner on Unix. Needs the privilege to, usually root.
+    pub owner: Option<Owner>,
+    /// How many times a failed copy or move is tried again when the failure may be
+    /// transient, eg. a timeout on a network share.
+    pub retries: u32,
+    pub dry_run: bool,
+    pub mapping_csv: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    /// Append a `sha256sum` line for every copied, moved or linked file to this
+    /// file, with the target path relative to the target directory, so the
+    /// copies can be checked later with `sha256sum -c`.
+    pub checksum_manifest: Option<PathBuf>,
+    /// Record the paths in the manifests relative to the target, or to the
+    /// source for the source files, eg. to share them without the home
+    /// directory. The summary is recorded the same way by
+    /// [`Summary::with_relative_paths`].
+    pub relative_paths: bool,
+    /// Append a JSON line for every copied, moved or linked file, which [`undo`] reverses.
+    pub journal: Option<PathBuf>,
+    /// Record every sorted source file in this file as soon as it's sorted, and
+    /// leave out the files recorded by an earlier run, eg. to resume one which
+    /// was interrupted.
+    pub state: Option<PathBuf>,
+    /// Reorganize the source within itself, with the target the same directory.
+    /// The files are moved, those already at their target are left alone, and
+    /// the directories emptied by the run are removed.
+    pub in_place: bool,
+    /// Remove the `state` file once a run sorted every file without an error.
+    pub remove_state: bool,
+    /// Leave out the files modified before the last run recorded in this file
+    /// started, and record this run in it once every file was sorted.
+    pub since_last_run: Option<PathBuf>,
+    /// Remove the files at the target which no source file sorts to, eg. after
+    /// deleting photos from the source, without asking. The own files are kept.
+    pub mirror: bool,
+    /// Files which are never sorted, eg. the running binary. The mapping and
+    /// manifest files are always left out.
+    pub own_files: Vec<PathBuf>,
+    /// Show a progress bar when stdout is a terminal.
+    pub progress: bool,
+    /// Report the photos taken within this long of each other as bursts in the summary.
+    pub burst_window: Option<Duration>,
+    /// Called with the progress of the run, eg. to print a line for every file.
+    pub on_event: Option<EventHook>,
+    /// Called with every name collision with a different file to choose what
+    /// to do with it, eg. by asking. Only `sort` calls it.
+    pub on_collision: Option<CollisionHook>,
+}
+
+impl SortOptions {
+    /// Copies the files with the default layout, printing only the warnings and errors.
+    pub fn new(source_dir: impl Into<PathBuf>, target_dir: impl Into<PathBuf>) -> Self {
+        SortOptions {
+            source_dir: source_dir.into(),
+            target_dir: target_dir.into(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            min_depth: None,
+            max_depth: None,
+            layout: DEFAULT_TEMPLATE.to_string(),
+            month_format: MonthFormat::Name,
+            name_prefix: None,
+            name_suffix: None,
+            rename: None,
+            by_location: false,
+            group_by: Vec::new(),
+            archive: false,
+            routes: Vec::new(),
+            transfer: Transfer::Copy,
+            relative_symlink: false,
+            date_tag: DateTag::Original,
+            date_policy: DatePolicy::ExifFirst,
+            date_skew: None,
+            exif_only: false,
+            unknown_dir: None,
+            takeout: false,
+            date_command: None,
+            date_from_name: false,
+            name_patterns: Vec::new(),
+            zone: None,
+            target_exists: TargetExists::Skip,
+            update: false,
+            after: None,
+            before: None,
+            skip_empty: false,
+            min_size: None,
+            max_size: None,
+            include_ext: Vec::new(),
+            exclude_ext: Vec::new(),
+            max_bytes: None,
+            sort_order: SortOrder::Name,
+            jobs: None,
+            hash_threads: None,
+            dedupe_by_hash: false,
+            stable_by_hash: false,
+            no_clobber_across_dates: false,
+            verify_content: false,
+            preserve_mtime: true,
+            preserve_perms: false,
+            preserve_xattrs: false,
+            owner: None,
+            retries: 0,
+            dry_run: false,
+            mapping_csv: None,
+            manifest: None,
+            checksum_manifest: None,
+            relative_paths: false,
+            journal: None,
+            state: None,
+            in_place: false,
+            remove_state: false,
+            since_last_run: None,
+            mirror: false,
+            own_files: Vec::new(),
+            progress: false,
+            burst_window: None,
+            on_event: None,
+            on_collision: None,
+        }
+    }
+
+    /// The directories the paths are recorded from with `relative_paths`,
+    /// none without it.
+    pub fn path_roots(&self) -> PathRoots {
+        match self.relative_paths {
+            true => PathRoots {
+                source: vec![self.source_dir.clone()],
+                targets: iter::once(&self.target_dir)
+                    .chain(self.routes.iter().map(|(_, dir)| dir))
+                    .cloned()
+                    .collect(),
+            },
+            false => PathRoots::default(),
+        }
+    }
+}
+
+/// The source and target directories the paths of the manifests and the
+/// summary are recorded from, see [`SortOptions::path_roots`]. A path under
+/// none of them is recorded in full.
+#[derive(Default, Clone, Debug)]
+pub struct PathRoots {
+    source: Vec<PathBuf>,
+    targets: Vec<PathBuf>,
+}
+
+impl PathRoots {
+    pub(crate) fn source_path<'a>(&self, path: &'a Path) -> &'a Path {
+        relative_path(path, &self.source)
+    }
+
+    // a routed file is recorded from its route.
+    pub(crate) fn target_path<'a>(&self, path: &'a Path) -> &'a Path {
+        relative_path(path, &self.targets)
+    }
+}
+
+fn relative_path<'a>(path: &'a Path, roots: &[PathBuf]) -> &'a Path {
+    roots
+        .iter()
+        .find_map(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+}
+
+/// Sorts the files of the source directory into the target directory.
+///
+/// Fails without sorting anything when the options are invalid, eg. when the
+/// source directory doesn't exist or the layout is not a valid template. The
+/// files which fail to sort are counted in the summary instead.
+pub fn sort(mut options: SortOptions) -> Result<Summary> {
+    let on_event = options.on_event.take();
+    let on_collision = options.on_collision.take();
+    sorter::run(&options, on_event, on_collision, None)
+}
+
+/// Compares the files of the source directory against the target directory
+/// without copying anything.
+pub fn diff(mut options: SortOptions) -> Result<DiffReport> {
+    let mut report = DiffReport::init();
+    let on_event = options.on_event.take();
+    sorter::run(&options, on_event, None, Some(&mut report))?;
+    Ok(report)
+}
+
+/// Lists the target paths which two or more source files sort to, eg. the
+/// photos of the same name and day from different folders, without copying
+/// anything. Only the first of them would be copied, whatever is at the target.
+pub fn collisions(mut options: SortOptions) -> Result<CollisionReport> {
+    let targets = Arc::new(Mutex::new(BTreeMap::<PathBuf, Vec<PathBuf>>::new()));
+    let recorder = Arc::clone(&targets);
+    let mut on_event = options.on_event.take();
+    let hook: EventHook = Box::new(move |event| {
+        if let SortEvent::Copied(file) | SortEvent::Skipped(file) | SortEvent::Duplicate(file) =
+            &event
+        {
+            if let Some(target) = &file.target {
+                let mut targets = recorder.lock().unwrap();
+                targets
+                    .entry(target.clone())
+                    .or_default()
+                    .push(file.source.clone());
+            }
+        }
+        if let Some(on_event) = on_event.as_mut() {
+            on_event(event);
+        }
+    });
+    // a numbered name would hide the collision it resolves.
+    options.target_exists = TargetExists::Skip;
+    sorter::run(&options, Some(hook), None, Some(&mut DiffReport::init()))?;
+    let targets = std::mem::take(&mut *targets.lock().unwrap());
+    let collisions = targets
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(target, mut sources)| {
+            sources.sort();
+            Collision { target, sources }
+        })
+        .collect();
+    Ok(CollisionReport { collisions })
+}
+
+/// Groups the files of the source directory which hold the same contents, eg.
+/// the same photo imported twice, without copying anything. The files left out
+/// of a run, eg. by their extension, are left out of the groups too.
+pub fn find_duplicates(mut options: SortOptions) -> Result<DuplicateReport> {
+    let files = Arc::new(Mutex::new(Vec::<(PathBuf, u64)>::new()));
+    let recorder = Arc::clone(&files);
+    let mut on_event = options.on_event.take();
+    let hook: EventHook = Box::new(move |event| {
+        if let SortEvent::Copied(file) | SortEvent::Skipped(file) | SortEvent::Duplicate(file) =
+            &event
+        {
+            recorder
+                .lock()
+                .unwrap()
+                .push((file.source.clone(), file.bytes));
+        }
+        if let Some(on_event) = on_event.as_mut() {
+            on_event(event);
+        }
+    });
+    sorter::run(&options, Some(hook), None, Some(&mut DiffReport::init()))?;
+    let files = std::mem::take(&mut *files.lock().unwrap());
+    // only the files sharing their size with another one can share the contents.
+    let mut sizes: HashMap<u64, u32> = HashMap::new();
+    for (_, len) in &files {
+        *sizes.entry(*len).or_default() += 1;
+    }
+    let candidates: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|(_, len)| sizes[len] > 1)
+        .map(|(path, _)| path)
+        .collect();
+    let mut groups: Vec<DuplicateGroup> = group_by_hash(&candidates)
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(hash, mut files)| {
+            files.sort();
+            DuplicateGroup { hash, files }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.files.cmp(&b.files));
+    Ok(DuplicateReport { groups })
+}
+
+/// Lists the files a run would copy, skip or collide on at the target, without
+/// copying anything.
+pub fn plan(mut options: SortOptions) -> Result<SortPlan> {
+    let plan = Arc::new(Mutex::new(SortPlan::default()));
+    let recorder = Arc::clone(&plan);
+    let mut on_event = options.on_event.take();
+    let hook: EventHook = Box::new(move |event| {
+        recorder.lock().unwrap().mark(&event);
+        if let Some(on_event) = on_event.as_mut() {
+            on_event(event);
+        }
+    });
+    sorter::run(&options, Some(hook), None, Some(&mut DiffReport::init()))?;
+    let mut plan = std::mem::take(&mut *plan.lock().unwrap());
+    // the files are sorted in parallel, so they come in any order.
+    for files in [&mut plan.new, &mut plan.skipped, &mut plan.conflicting] {
+        files.sort();
+    }
+    Ok(plan)
+}
+
+/// Where a single file would be sorted to, and the date it would be sorted by,
+/// without copying anything. The source directory is only used to find the
+/// layouts of the folders the file is in.
+pub fn locate(file: &Path, options: &SortOptions) -> Result<SortedFile> {
+    sorter::locate(options, file)
+}
 
-#[derive(Default)]
+/// Counts the files of the source directory per year and month of their date,
+/// without copying anything.
+pub fn scan(source: &Path) -> Result<ScanReport> {
+    if !source.is_dir() {
+        anyhow::bail!("The source path is invalid. Please make sure it exists and is a directory.");
+    }
+    let mut report = ScanReport::default();
+    let ignore = sorter::load_ignore_file(source);
+    let walker = WalkDir::new(source).into_iter().filter_entry(|entry| {
+        !ignore
+            .matched(entry.path(), entry.file_type().is_dir())
+            .is_ignore()
+    });
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                error!("{} while scanning - [{}]", "Error".red(), err);
+                report.error_count += 1;
+                continue;
+            }
+        };
+        if entry.file_type().is_dir()
+            || (entry.path_is_symlink() && entry.path().is_dir())
+            || entry.file_name() == template::TEMPLATE_FILE_NAME
+            || entry.path() == source.join(sorter::IGNORE_FILE_NAME)
+        {
+            continue;
+        }
+        match get_file_date(
+            &entry,
+            DateTag::Original,
+            None,
+            DatePolicy::ExifFirst,
+            None,
+            &DateSources::default(),
+        )
+        .0
+        {
+            Ok(date_source) => report.mark(date_source.date()),
+            Err(err) => {
+                error!(
+                    "{} while reading the file date for the file {} - [{}]",
+                    "Error".red(),
+                    entry.path().display(),
+                    err
+                );
+                report.error_count += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+// the extension counted for the files without one.
+static NO_EXTENSION: &str = "(none)";
+
+// a summary saved by an older version lacks the newer counts.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Summary {
+    /// The total size of the source files the run came across, whatever became of them.
+    pub scanned_bytes: u64,
     pub scan_error_count: u32,
+    /// The directories reached again through a symlink, which are walked only once.
+    pub loop_skipped_count: u32,
     pub error_count: u32,
+    /// The files which failed to sort since their folder couldn't be created at the target.
+    pub dir_error_count: u32,
     pub skipped_count: u32,
+    /// The empty source files left out by `skip_empty`.
+    pub empty_skipped_count: u32,
     pub duplicate_count: u32,
+    /// The files left out of the run, for every reason.
+    pub filtered_count: u32,
+    pub filtered_reasons: BTreeMap<FilterReason, u32>,
     pub exif_error_count: u32,
     pub copy_count: u32,
     pub copied_bytes: u64,
+    pub moved_count: u32,
+    pub moved_bytes: u64,
+    pub symlinked_count: u32,
+    pub hardlinked_count: u32,
+    /// The files copied, moved or linked by their lowercase extension, with
+    /// `(none)` for the files without one.
+    pub extension_counts: BTreeMap<String, u32>,
+    pub removed_count: u32,
+    /// The copied or moved files which replaced a file at the target.
+    pub overwritten_count: u32,
+    /// The copied or moved files which replaced an older file at the target with `update`.
+    pub updated_count: u32,
+    /// The oldest and newest dates of the files sorted, unless none was dated.
+    pub min_date: Option<NaiveDate>,
+    pub max_date: Option<NaiveDate>,
+    pub budget_reached: bool,
+    #[serde(rename = "duration_ms", with = "duration_ms")]
     pub duration: Duration,
     pub errored_files: Vec<PathBuf>,
     pub duplicate_files: Vec<PathBuf>,
     pub exif_errored_files: Vec<PathBuf>,
+    /// The folders which couldn't be created at the target.
+    pub errored_dirs: Vec<PathBuf>,
+    /// The photos taken in a burst, as found by [`find_bursts`].
+    pub bursts: Vec<Vec<PathBuf>>,
 }
 
 impl Summary {
@@ -23,38 +591,210 @@ impl Summary {
         Default::default()
     }
 
+    pub fn mark_scanned(&mut self, len: u64) {
+        self.scanned_bytes += len;
+    }
+
     pub fn mark_scan_error(&mut self) {
         self.scan_error_count += 1;
     }
 
+    pub fn mark_loop_skipped(&mut self) {
+        self.loop_skipped_count += 1;
+    }
+
     pub fn mark_error(&mut self, path: PathBuf) {
         self.error_count += 1;
         self.errored_files.push(path);
     }
 
+    pub fn mark_dir_error(&mut self, dir: PathBuf) {
+        self.dir_error_count += 1;
+        if !self.errored_dirs.contains(&dir) {
+            self.errored_dirs.push(dir);
+        }
+    }
+
     pub fn mark_skipped(&mut self) {
         self.skipped_count += 1;
     }
 
+    pub fn mark_empty_skipped(&mut self) {
+        self.empty_skipped_count += 1;
+    }
+
     pub fn mark_duplicate(&mut self, path: PathBuf) {
         self.duplicate_count += 1;
         self.duplicate_files.push(path);
     }
 
+    pub fn mark_filtered(&mut self, reason: FilterReason) {
+        self.filtered_count += 1;
+        *self.filtered_reasons.entry(reason).or_default() += 1;
+    }
+
     pub fn mark_exif_error(&mut self, path: PathBuf) {
         self.exif_error_count += 1;
         self.exif_errored_files.push(path);
     }
 
+    /// The summary with its file lists recorded from the roots, eg. to share
+    /// its json. The failed files are mostly source files, apart from those a
+    /// mirror failed to remove from the target.
+    pub fn with_relative_paths(&self, roots: &PathRoots) -> Summary {
+        let mut summary = self.clone();
+        let source_lists = [
+            &mut summary.errored_files,
+            &mut summary.duplicate_files,
+            &mut summary.exif_errored_files,
+        ];
+        for path in source_lists.into_iter().flatten() {
+            let source = roots.source_path(path);
+            *path = match source == path.as_path() {
+                true => roots.target_path(path),
+                false => source,
+            }
+            .to_path_buf();
+        }
+        for path in summary
+            .errored_dirs
+            .iter_mut()
+            .chain(summary.bursts.iter_mut().flatten())
+        {
+            *path = roots.target_path(path).to_path_buf();
+        }
+        summary
+    }
+
     pub fn mark_copied(&mut self, len: u64) {
         self.copy_count += 1;
         self.copied_bytes += len;
     }
 
+    pub fn mark_moved(&mut self, len: u64) {
+        self.moved_count += 1;
+        self.moved_bytes += len;
+    }
+
+    pub fn mark_symlinked(&mut self) {
+        self.symlinked_count += 1;
+    }
+
+    pub fn mark_hardlinked(&mut self) {
+        self.hardlinked_count += 1;
+    }
+
+    pub fn mark_extension(&mut self, path: &Path) {
+        let extension = path.extension().map_or_else(
+            || NO_EXTENSION.to_string(),
+            |extension| extension.to_string_lossy().to_lowercase(),
+        );
+        *self.extension_counts.entry(extension).or_default() += 1;
+    }
+
+    pub fn mark_removed(&mut self) {
+        self.removed_count += 1;
+    }
+
+    pub fn mark_overwritten(&mut self) {
+        self.overwritten_count += 1;
+    }
+
+    pub fn mark_updated(&mut self) {
+        self.updated_count += 1;
+    }
+
+    pub fn mark_date(&mut self, date: NaiveDate) {
+        self.min_date = Some(self.min_date.map_or(date, |min_date| min_date.min(date)));
+        self.max_date = Some(self.max_date.map_or(date, |max_date| max_date.max(date)));
+    }
+
+    pub fn mark_budget_reached(&mut self) {
+        self.budget_reached = true;
+    }
+
+    /// Combines the summaries of files sorted separately.
+    pub fn merge(mut self, other: Summary) -> Summary {
+        self.scanned_bytes += other.scanned_bytes;
+        self.scan_error_count += other.scan_error_count;
+        self.loop_skipped_count += other.loop_skipped_count;
+        self.error_count += other.error_count;
+        self.dir_error_count += other.dir_error_count;
+        self.skipped_count += other.skipped_count;
+        self.empty_skipped_count += other.empty_skipped_count;
+        self.duplicate_count += other.duplicate_count;
+        self.filtered_count += other.filtered_count;
+        for (reason, count) in other.filtered_reasons {
+            *self.filtered_reasons.entry(reason).or_default() += count;
+        }
+        self.exif_error_count += other.exif_error_count;
+        self.copy_count += other.copy_count;
+        self.copied_bytes += other.copied_bytes;
+        self.moved_count += other.moved_count;
+        self.moved_bytes += other.moved_bytes;
+        self.symlinked_count += other.symlinked_count;
+        self.hardlinked_count += other.hardlinked_count;
+        for (extension, count) in other.extension_counts {
+            *self.extension_counts.entry(extension).or_default() += count;
+        }
+        self.removed_count += other.removed_count;
+        self.overwritten_count += other.overwritten_count;
+        self.updated_count += other.updated_count;
+        self.min_date = self.min_date.into_iter().chain(other.min_date).min();
+        self.max_date = self.max_date.into_iter().chain(other.max_date).max();
+        self.budget_reached |= other.budget_reached;
+        self.duration = self.duration.max(other.duration);
+        self.errored_files.extend(other.errored_files);
+        self.duplicate_files.extend(other.duplicate_files);
+        self.exif_errored_files.extend(other.exif_errored_files);
+        for dir in other.errored_dirs {
+            if !self.errored_dirs.contains(&dir) {
+                self.errored_dirs.push(dir);
+            }
+        }
+        self.bursts.extend(other.bursts);
+        self
+    }
+
+    /// 1 when a file failed to sort. With `strict`, 2 when a file was skipped as a duplicate.
+    pub fn exit_code(&self, strict: bool) -> i32 {
+        if self.error_count > 0 || self.dir_error_count > 0 || self.scan_error_count > 0 {
+            1
+        } else if strict && self.duplicate_count > 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// The number of source files the run came across, which each end up in
+    /// exactly one of the counts.
+    pub fn file_count(&self) -> u32 {
+        self.copy_count
+            + self.moved_count
+            + self.symlinked_count
+            + self.hardlinked_count
+            + self.skipped_count
+            + self.empty_skipped_count
+            + self.duplicate_count
+            + self.filtered_count
+            + self.error_count
+            + self.dir_error_count
+    }
+
     pub fn set_duration(&mut self, duration: Duration) {
         self.duration = duration;
     }
 
+    /// The copied bytes per second, when anything was copied.
+    pub fn throughput(&self) -> Option<u64> {
+        let seconds = self.duration.as_secs_f64();
+        if self.copied_bytes == 0 || seconds == 0.0 {
+            return None;
+        }
+        Some((self.copied_bytes as f64 / seconds) as u64)
+    }
+
     pub fn display(&self) -> String {
         let mut messages = Vec::new();
         messages.push("\n".to_string());
@@ -63,12 +803,92 @@ impl Summary {
             "Completed".green(),
             humantime::format_duration(self.duration)
         ));
+        messages.push(format!(
+            "{} {} files totalling {}",
+            "Scanned".green(),
+            self.file_count(),
+            bytesize::to_string(self.scanned_bytes, true)
+        ));
+        if let (Some(min_date), Some(max_date)) = (self.min_date, self.max_date) {
+            messages.push(format!(
+                "{} {} to {}",
+                "Date range:".green(),
+                min_date,
+                max_date
+            ));
+        }
         messages.push(format!(
             "{} {} files totalling {}",
             "Copied".green(),
             self.copy_count,
             bytesize::to_string(self.copied_bytes, true)
         ));
+        if self.moved_count > 0 {
+            messages.push(format!(
+                "{} {} files totalling {}",
+                "Moved".green(),
+                self.moved_count,
+                bytesize::to_string(self.moved_bytes, true)
+            ));
+        }
+        if let Some(throughput) = self.throughput() {
+            messages.push(format!(
+                "{} {}/s",
+                "Throughput".green(),
+                bytesize::to_string(throughput, true)
+            ));
+        }
+        if self.symlinked_count > 0 {
+            messages.push(format!(
+                "{} {} files",
+                "Symlinked".green(),
+                self.symlinked_count
+            ));
+        }
+        if self.hardlinked_count > 0 {
+            messages.push(format!(
+                "{} {} files",
+                "Hardlinked".green(),
+                self.hardlinked_count
+            ));
+        }
+        if !self.extension_counts.is_empty() {
+            // the most common first, and the ties by their name.
+            let mut counts: Vec<(&String, &u32)> = self.extension_counts.iter().collect();
+            counts.sort_by(|a, b| b.1.cmp(a.1));
+            let counts: Vec<String> = counts
+                .into_iter()
+                .map(|(extension, count)| format!("{} {}", count, extension))
+                .collect();
+            messages.push(format!("{} {}", "Extensions".green(), counts.join(", ")));
+        }
+        if self.overwritten_count > 0 {
+            messages.push(format!(
+                "{} {} files which were already at the target",
+                "Overwrote".yellow(),
+                self.overwritten_count
+            ));
+        }
+        if self.updated_count > 0 {
+            messages.push(format!(
+                "{} {} files which were older at the target",
+                "Updated".yellow(),
+                self.updated_count
+            ));
+        }
+        if self.removed_count > 0 {
+            messages.push(format!(
+                "{} {} files from the target since their source is gone",
+                "Removed".red(),
+                self.removed_count
+            ));
+        }
+        if self.budget_reached {
+            messages.push(format!(
+                "{} copying after reaching the byte budget. Run again to copy the remaining files.",
+                "Stopped".yellow()
+            ));
+        }
         if self.scan_error_count > 0 {
             messages.push(format!(
                 "{} to scan {} files.",
@@ -76,6 +896,13 @@ impl Summary {
                 self.scan_error_count
             ));
         }
+        if self.loop_skipped_count > 0 {
+            messages.push(format!(
+                "{} {} directories which were already scanned through a symlink",
+                "Skipped".cyan(),
+                self.loop_skipped_count
+            ));
+        }
         if self.skipped_count > 0 {
             messages.push(format!(
                 "{} copying {} files since they were already present at the target",
@@ -83,12 +910,29 @@ impl Summary {
                 self.skipped_count
             ));
         }
+        if self.empty_skipped_count > 0 {
+            messages.push(format!(
+                "{} {} empty files",
+                "Skipped".cyan(),
+                self.empty_skipped_count
+            ));
+        }
         if self.duplicate_count > 0 {
-            messages.push(format!("{} copying {} files since they were present at the target but was of a different size - ", "Skipped".red(), self.duplicate_count));
+            messages.push(format!("{} copying {} files since a different file with the same name or a file with the same contents was present at the target - ", "Skipped".red(), self.duplicate_count));
             for path in &self.duplicate_files {
                 messages.push(path.display().to_string());
             }
         }
+        if self.filtered_count > 0 {
+            messages.push(format!(
+                "{} {} files which were left out of the run - ",
+                "Filtered".cyan(),
+                self.filtered_count
+            ));
+            for (reason, count) in &self.filtered_reasons {
+                messages.push(format!("  {} {}", count, reason.description()));
+            }
+        }
         if self.error_count > 0 {
             messages.push(format!(
                 "{} to copy {} files. The following files were not copied - ",
@@ -99,12 +943,674 @@ impl Summary {
                 messages.push(path.display().to_string());
             }
         }
+        if self.dir_error_count > 0 {
+            messages.push(format!(
+                "{} to create {} folders at the target, so {} files were not copied. Check the permissions of the target - ",
+                "Failed".red(),
+                self.errored_dirs.len(),
+                self.dir_error_count
+            ));
+            for dir in &self.errored_dirs {
+                messages.push(dir.display().to_string());
+            }
+        }
         if self.exif_error_count > 0 {
             messages.push(format!("{} reading the exif data for {} files. They were copied using the file modified time - ", "Error".yellow(), self.exif_error_count));
             for path in &self.exif_errored_files {
                 messages.push(path.display().to_string());
             }
         }
+        if !self.bursts.is_empty() {
+            messages.push(format!(
+                "{} {} bursts of photos taken in quick succession - ",
+                "Found".cyan(),
+                self.bursts.len()
+            ));
+            for (index, burst) in self.bursts.iter().enumerate() {
+                messages.push(format!("Burst {} of {} photos", index + 1, burst.len()));
+                for path in burst {
+                    messages.push(format!("  {}", path.display()));
+                }
+            }
+        }
         messages.join("\n")
     }
 }
+
+/// Why a file was left out of a run.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterReason {
+    /// Not one of the extensions of `include_ext`, or one of `exclude_ext`.
+    Extension,
+    /// Matched by the ignore file at the root of the source.
+    IgnoreFile,
+    /// Named with a leading dot, or within such a directory, with `skip_hidden`.
+    Hidden,
+    /// Less than `min_depth` directories down the source.
+    Depth,
+    /// Captured outside of `after` and `before`.
+    DateRange,
+    /// Sized outside of `min_size` and `max_size`.
+    Size,
+    /// Left for a later run once `max_bytes` was used up.
+    Budget,
+    /// Recorded in the `state` file as sorted by an earlier run.
+    State,
+    /// Modified before the last run of `since_last_run` started.
+    SinceLastRun,
+    /// Left for a later run once `on_collision` stopped the run.
+    Stopped,
+    /// Without a capture date with `exif_only`.
+    Undated,
+}
+
+impl FilterReason {
+    pub fn description(self) -> &'static str {
+        match self {
+            FilterReason::Extension => "by their extension",
+            FilterReason::IgnoreFile => "matched by the ignore file",
+            FilterReason::Hidden => "hidden by a leading dot in their name or folder",
+            FilterReason::Depth => "above the minimum depth",
+            FilterReason::DateRange => "captured outside of the date range",
+            FilterReason::Size => "with a size outside of the size range",
+            FilterReason::Budget => "left for the next run after reaching the byte budget",
+            FilterReason::State => "sorted by an earlier run as recorded in the state file",
+            FilterReason::SinceLastRun => "modified before the last run started",
+            FilterReason::Stopped => "left for a later run after stopping at a name collision",
+            FilterReason::Undated => "without a capture date in their metadata",
+        }
+    }
+}
+
+/// Groups the files taken within `window` of the previous file, eg. the shots
+/// of a camera burst. Only the groups of two or more files are returned, each
+/// in the order the files were taken.
+pub fn find_bursts(dates: &[(PathBuf, DateTime<Utc>)], window: Duration) -> Vec<Vec<PathBuf>> {
+    let mut dates: Vec<&(PathBuf, DateTime<Utc>)> = dates.iter().collect();
+    dates.sort_by_key(|(path, date)| (*date, path));
+
+    let mut bursts = Vec::new();
+    let mut burst: Vec<PathBuf> = Vec::new();
+    let mut last: Option<DateTime<Utc>> = None;
+    for (path, date) in dates {
+        let in_window = last.is_some_and(|last| {
+            (*date - last)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed <= window)
+        });
+        if !in_window && burst.len() > 1 {
+            bursts.push(std::mem::take(&mut burst));
+        } else if !in_window {
+            burst.clear();
+        }
+        burst.push(path.clone());
+        last = Some(*date);
+    }
+    if burst.len() > 1 {
+        bursts.push(burst);
+    }
+    bursts
+}
+
+/// Where a source file falls when comparing a run against the target.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DiffCategory {
+    /// The file would be copied.
+    New,
+    /// The file is already present at the target.
+    Present,
+    /// A different file with the same name is present at the target.
+    Collision,
+    /// The file would not be copied in this run.
+    Filtered,
+}
+
+impl DiffCategory {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DiffCategory::New => "NEW",
+            DiffCategory::Present => "PRESENT",
+            DiffCategory::Collision => "COLLISION",
+            DiffCategory::Filtered => "FILTERED",
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct DiffReport {
+    pub new_count: u32,
+    pub new_bytes: u64,
+    pub present_count: u32,
+    pub collision_count: u32,
+    pub filtered_count: u32,
+}
+
+impl DiffReport {
+    pub fn init() -> Self {
+        Default::default()
+    }
+
+    pub fn mark(&mut self, category: DiffCategory, len: u64) {
+        match category {
+            DiffCategory::New => {
+                self.new_count += 1;
+                self.new_bytes += len;
+            }
+            DiffCategory::Present => self.present_count += 1,
+            DiffCategory::Collision => self.collision_count += 1,
+            DiffCategory::Filtered => self.filtered_count += 1,
+        }
+    }
+
+    pub fn display(&self) -> String {
+        let mut messages = Vec::new();
+        messages.push("\n".to_string());
+        messages.push(format!(
+            "{} {} files totalling {} would be copied",
+            "New".green(),
+            self.new_count,
+            bytesize::to_string(self.new_bytes, true)
+        ));
+        messages.push(format!(
+            "{} {} files are already present at the target",
+            "Present".cyan(),
+            self.present_count
+        ));
+        messages.push(format!(
+            "{} {} files have a different file with the same name at the target",
+            "Collision".red(),
+            self.collision_count
+        ));
+        messages.push(format!(
+            "{} {} files would not be copied in this run",
+            "Filtered".yellow(),
+            self.filtered_count
+        ));
+        messages.join("\n")
+    }
+}
+
+/// The source and target paths of the files of a run, grouped by what the run
+/// would do with them, see [`plan`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct SortPlan {
+    /// The files which would be copied.
+    pub new: Vec<(PathBuf, PathBuf)>,
+    /// The files already present at the target.
+    pub skipped: Vec<(PathBuf, PathBuf)>,
+    /// The files with a different file of the same name at the target.
+    pub conflicting: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SortPlan {
+    pub fn mark(&mut self, event: &SortEvent) {
+        let (files, file) = match event {
+            SortEvent::Copied(file) => (&mut self.new, file),
+            SortEvent::Skipped(file) => (&mut self.skipped, file),
+            SortEvent::Duplicate(file) => (&mut self.conflicting, file),
+            _ => return,
+        };
+        if let Some(target) = &file.target {
+            files.push((file.source.clone(), target.clone()));
+        }
+    }
+
+    pub fn display(&self) -> String {
+        let mut messages = Vec::new();
+        for (label, description, files) in [
+            ("New".green(), "would be copied", &self.new),
+            (
+                "Skipped".cyan(),
+                "are already present at the target",
+                &self.skipped,
+            ),
+            (
+                "Conflicting".red(),
+                "have a different file with the same name at the target",
+                &self.conflicting,
+            ),
+        ] {
+            messages.push(format!("{} {} files {} -", label, files.len(), description));
+            for (source, target) in files {
+                messages.push(format!("  {} to {}", source.display(), target.display()));
+            }
+        }
+        messages.join("\n")
+    }
+}
+
+/// The target paths which more than one source file sorts to, see [`collisions`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct CollisionReport {
+    pub collisions: Vec<Collision>,
+}
+
+/// The files of a source directory which hold the same contents, see [`find_duplicates`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+/// The files with the same contents, along with the SHA-256 of the contents.
+#[derive(Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub files: Vec<PathBuf>,
+}
+
+impl DuplicateReport {
+    pub fn display(&self) -> String {
+        let mut messages = vec![format!(
+            "{} {} groups of files with the same contents -",
+            "Found".cyan(),
+            self.groups.len()
+        )];
+        for group in &self.groups {
+            messages.push(format!("  {}", group.hash));
+            for file in &group.files {
+                messages.push(format!("    {}", file.display()));
+            }
+        }
+        messages.join("\n")
+    }
+}
+
+/// A target path along with the source files which sort to it.
+#[derive(Serialize, Deserialize)]
+pub struct Collision {
+    pub target: PathBuf,
+    pub sources: Vec<PathBuf>,
+}
+
+impl CollisionReport {
+    pub fn display(&self) -> String {
+        let mut messages = vec![format!(
+            "{} {} target paths have more than one source file -",
+            "Colliding".red(),
+            self.collisions.len()
+        )];
+        for collision in &self.collisions {
+            messages.push(format!("  {}", collision.target.display()));
+            for source in &collision.sources {
+                messages.push(format!("    {}", source.display()));
+            }
+        }
+        messages.join("\n")
+    }
+}
+
+/// The number of files of a source directory per year and month, see [`scan`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub file_count: u32,
+    /// The files whose date could not be read.
+    pub error_count: u32,
+    /// The file counts per month, keyed by the year and then the month from 1 to 12.
+    pub years: BTreeMap<i32, BTreeMap<u32, u32>>,
+}
+
+impl ScanReport {
+    pub fn mark(&mut self, date: NaiveDate) {
+        self.file_count += 1;
+        *self
+            .years
+            .entry(date.year())
+            .or_default()
+            .entry(date.month())
+            .or_default() += 1;
+    }
+
+    pub fn year_count(&self, year: i32) -> u32 {
+        self.years
+            .get(&year)
+            .map_or(0, |months| months.values().sum())
+    }
+
+    /// A histogram of the years with the months of each year below it.
+    pub fn display(&self) -> String {
+        // the bars are scaled so the largest month fits in the line.
+        let largest = self
+            .years
+            .values()
+            .flat_map(|months| months.values())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let bar = |count: u32| "#".repeat((count * 40).div_ceil(largest.max(40)) as usize);
+        let mut messages = Vec::new();
+        messages.push("\n".to_string());
+        for (year, months) in &self.years {
+            messages.push(format!(
+                "{} {} files",
+                year.to_string().green(),
+                self.year_count(*year)
+            ));
+            for (month, count) in months {
+                let name = NaiveDate::from_ymd_opt(*year, *month, 1)
+                    .map_or(String::new(), |date| date.format("%b").to_string());
+                messages.push(format!("  {} {:>5} {}", name, count, bar(*count)));
+            }
+        }
+        messages.push(format!("{} {} files", "Total".cyan(), self.file_count));
+        if self.error_count > 0 {
+            messages.push(format!(
+                "{} Could not read the date of {} files",
+                "Error".red(),
+                self.error_count
+            ));
+        }
+        messages.join("\n")
+    }
+}
+
+// durations are written as whole milliseconds, like in the history file.
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u128(duration.as_millis())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// A single run recorded in the history file.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub copy_count: u32,
+    pub copied_bytes: u64,
+    pub skipped_count: u32,
+    pub duplicate_count: u32,
+    pub error_count: u32,
+    pub scan_error_count: u32,
+    pub exif_error_count: u32,
+    pub duration_ms: u128,
+}
+
+impl HistoryEntry {
+    pub fn new(summary: &Summary, timestamp: DateTime<Utc>) -> Self {
+        HistoryEntry {
+            timestamp,
+            copy_count: summary.copy_count,
+            copied_bytes: summary.copied_bytes,
+            skipped_count: summary.skipped_count,
+            duplicate_count: summary.duplicate_count,
+            error_count: summary.error_count,
+            scan_error_count: summary.scan_error_count,
+            exif_error_count: summary.exif_error_count,
+            duration_ms: summary.duration.as_millis(),
+        }
+    }
+}
+
+/// Appends the entry to a JSON lines history file, keeping only the last `limit` entries.
+pub fn append_history(path: &Path, entry: &HistoryEntry, limit: usize) -> Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("Failed to read the history file"),
+    };
+    let mut lines: Vec<String> = existing
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    lines.push(serde_json::to_string(entry)?);
+    let start = lines.len().saturating_sub(limit);
+
+    let mut contents = lines[start..].join("\n");
+    contents.push('\n');
+    fs::write(path, contents).context("Failed to write the history file")?;
+    Ok(())
+}
+
+/// Appends the text of the summary to a file under a header with the time of
+/// the run, so the summaries of repeated runs add up, eg. for a cron job. The
+/// colours are left out whatever the terminal settings.
+pub fn append_summary(path: &Path, summary: &Summary, at: DateTime<Utc>) -> Result<()> {
+    let ansi_codes = Regex::new(r"\x1b\[[0-9;]*m").expect("the pattern is valid");
+    let text = summary.display();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Failed to open the summary file")?;
+    writeln!(
+        file,
+        "photosort run at {}\n{}\n",
+        at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        ansi_codes.replace_all(text.trim_start(), "")
+    )
+    .context("Failed to write the summary file")?;
+    Ok(())
+}
+
+/// Reads a summary saved with `--format json`, eg. by the previous run.
+pub fn load_summary(path: &Path) -> Result<Summary> {
+    let contents = fs::read_to_string(path).context("Failed to read the summary file")?;
+    serde_json::from_str(&contents).context("Failed to parse the summary file")
+}
+
+/// How the counts of a run changed since an earlier run, see [`diff_summaries`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct SummaryDiff {
+    pub file_count: i64,
+    pub scanned_bytes: i64,
+    pub copy_count: i64,
+    pub copied_bytes: i64,
+    pub moved_count: i64,
+    pub moved_bytes: i64,
+    pub skipped_count: i64,
+    pub duplicate_count: i64,
+    pub filtered_count: i64,
+    pub error_count: i64,
+    pub removed_count: i64,
+}
+
+/// The counts of the current run less those of the previous one.
+pub fn diff_summaries(previous: &Summary, current: &Summary) -> SummaryDiff {
+    let count = |count: fn(&Summary) -> u32| i64::from(count(current)) - i64::from(count(previous));
+    let bytes = |bytes: fn(&Summary) -> u64| bytes(current) as i64 - bytes(previous) as i64;
+    SummaryDiff {
+        file_count: count(Summary::file_count),
+        scanned_bytes: bytes(|summary| summary.scanned_bytes),
+        copy_count: count(|summary| summary.copy_count),
+        copied_bytes: bytes(|summary| summary.copied_bytes),
+        moved_count: count(|summary| summary.moved_count),
+        moved_bytes: bytes(|summary| summary.moved_bytes),
+        skipped_count: count(|summary| summary.skipped_count),
+        duplicate_count: count(|summary| summary.duplicate_count),
+        filtered_count: count(|summary| summary.filtered_count),
+        error_count: count(|summary| summary.error_count),
+        removed_count: count(|summary| summary.removed_count),
+    }
+}
+
+impl SummaryDiff {
+    /// The counts which changed, each with its sign, eg. `Copied +3 files totalling +1.2 MiB`.
+    pub fn display(&self) -> String {
+        let signed = |count: i64| format!("{:+}", count);
+        let signed_bytes = |bytes: i64| {
+            let sign = if bytes < 0 { "-" } else { "+" };
+            format!(
+                "{}{}",
+                sign,
+                bytesize::to_string(bytes.unsigned_abs(), true)
+            )
+        };
+        let mut messages = Vec::new();
+        messages.push(format!("\n{} the baseline -", "Compared with".cyan()));
+        for (label, count, bytes) in [
+            ("Scanned", self.file_count, Some(self.scanned_bytes)),
+            ("Copied", self.copy_count, Some(self.copied_bytes)),
+            ("Moved", self.moved_count, Some(self.moved_bytes)),
+            ("Skipped", self.skipped_count, None),
+            ("Duplicate", self.duplicate_count, None),
+            ("Filtered", self.filtered_count, None),
+            ("Failed", self.error_count, None),
+            ("Removed", self.removed_count, None),
+        ] {
+            if count == 0 && bytes.unwrap_or(0) == 0 {
+                continue;
+            }
+            messages.push(match bytes {
+                Some(bytes) => format!(
+                    "  {} {} files totalling {}",
+                    label,
+                    signed(count),
+                    signed_bytes(bytes)
+                ),
+                None => format!("  {} {} files", label, signed(count)),
+            });
+        }
+        if messages.len() == 1 {
+            messages.push("  Nothing changed".to_string());
+        }
+        messages.join("\n")
+    }
+}
+
+/// Whether the two files have the same contents, compared byte by byte.
+pub fn files_identical(a: &Path, b: &Path) -> std::io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(fs::File::open(a)?);
+    let mut b = BufReader::new(fs::File::open(b)?);
+    loop {
+        let a_buf = a.fill_buf()?;
+        let b_buf = b.fill_buf()?;
+        let len = a_buf.len().min(b_buf.len());
+        if len == 0 {
+            return Ok(a_buf.len() == b_buf.len());
+        }
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+/// The files by the SHA-256 of their contents, hashed in parallel. The files
+/// which fail to hash are logged and left out.
+pub fn group_by_hash(files: &[PathBuf]) -> HashMap<String, Vec<PathBuf>> {
+    let hashes: Vec<(String, &PathBuf)> = files
+        .par_iter()
+        .filter_map(|path| match file_hash(path) {
+            Ok(hash) => Some((hash, path)),
+            Err(err) => {
+                error!(
+                    "{} while hashing the file {} - [{:#}]",
+                    "Error".red(),
+                    path.display(),
+                    err
+                );
+                None
+            }
+        })
+        .collect();
+    let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (hash, path) in hashes {
+        groups.entry(hash).or_default().push(path.clone());
+    }
+    groups
+}
+
+/// The SHA-256 of the file contents as a lowercase hex string.
+pub fn file_hash(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).context("Failed to open the file for hashing")?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).context("Failed to read the file for hashing")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn display_shows_the_throughput() {
+        let mut summary = Summary {
+            copy_count: 10,
+            copied_bytes: 90 * 1024 * 1024,
+            ..Summary::init()
+        };
+        summary.set_duration(Duration::from_secs(2));
+        assert_eq!(summary.throughput(), Some(45 * 1024 * 1024));
+        assert!(summary.display().contains("Throughput 45.0 MiB/s"));
+
+        // nothing is divided by a zero duration.
+        summary.set_duration(Duration::ZERO);
+        assert_eq!(summary.throughput(), None);
+        assert!(!summary.display().contains("Throughput"));
+    }
+
+    fn at(path: &str, seconds: i64) -> (PathBuf, DateTime<Utc>) {
+        let start = Utc.with_ymd_and_hms(2022, 1, 6, 12, 0, 0).unwrap();
+        (
+            PathBuf::from(path),
+            start + chrono::Duration::seconds(seconds),
+        )
+    }
+
+    fn paths(burst: &[&str]) -> Vec<PathBuf> {
+        burst.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn find_bursts_groups_photos_within_the_window() {
+        let dates = [
+            at("c.jpg", 2),
+            at("a.jpg", 0),
+            at("b.jpg", 1),
+            at("alone.jpg", 60),
+            at("e.jpg", 121),
+            at("d.jpg", 120),
+        ];
+        assert_eq!(
+            find_bursts(&dates, Duration::from_secs(1)),
+            vec![
+                paths(&["a.jpg", "b.jpg", "c.jpg"]),
+                paths(&["d.jpg", "e.jpg"])
+            ]
+        );
+    }
+
+    #[test]
+    fn find_bursts_chains_each_photo_to_the_previous_one() {
+        // the burst is longer than the window, but every gap is within it.
+        let dates = [
+            at("a.jpg", 0),
+            at("b.jpg", 2),
+            at("c.jpg", 4),
+            at("d.jpg", 7),
+        ];
+        assert_eq!(
+            find_bursts(&dates, Duration::from_secs(2)),
+            vec![paths(&["a.jpg", "b.jpg", "c.jpg"])]
+        );
+    }
+
+    #[test]
+    fn find_bursts_ignores_single_photos() {
+        let dates = [at("a.jpg", 0), at("b.jpg", 10), at("c.jpg", 20)];
+        assert!(find_bursts(&dates, Duration::from_secs(5)).is_empty());
+        assert!(find_bursts(&[], Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn find_bursts_keeps_photos_taken_at_the_same_time() {
+        let dates = [at("b.jpg", 0), at("a.jpg", 0)];
+        assert_eq!(
+            find_bursts(&dates, Duration::ZERO),
+            vec![paths(&["a.jpg", "b.jpg"])]
+        );
+    }
+}
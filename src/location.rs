@@ -0,0 +1,252 @@
+use crate::date::{exif_compatible_extension, read_exif};
+use exif::{In, Tag, Value};
+use walkdir::DirEntry;
+
+/// A place on the map, named by its country and the nearest large city.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Place {
+    pub country: &'static str,
+    pub city: &'static str,
+}
+
+impl Place {
+    // the country and city folders followed by the year, eg. France/Paris/2019
+    pub(crate) fn template(self) -> String {
+        format!("{}/{}/%Y", self.country, self.city)
+    }
+}
+
+// a photo further than this from every city in the table is left to the date layout.
+static MAX_DISTANCE_KM: f64 = 500.0;
+
+static EARTH_RADIUS_KM: f64 = 6371.0;
+
+// the capitals and the largest cities of each region as (country, city, latitude, longitude).
+// The nearest of them names the place, which is coarse but needs no network access.
+static CITIES: &[(&str, &str, f64, f64)] = &[
+    // europe
+    ("Austria", "Vienna", 48.2082, 16.3738),
+    ("Belgium", "Brussels", 50.8503, 4.3517),
+    ("Bulgaria", "Sofia", 42.6977, 23.3219),
+    ("Croatia", "Zagreb", 45.8150, 15.9819),
+    ("Czechia", "Prague", 50.0755, 14.4378),
+    ("Denmark", "Copenhagen", 55.6761, 12.5683),
+    ("Estonia", "Tallinn", 59.4370, 24.7536),
+    ("Finland", "Helsinki", 60.1699, 24.9384),
+    ("France", "Paris", 48.8566, 2.3522),
+    ("France", "Lyon", 45.7640, 4.8357),
+    ("France", "Marseille", 43.2965, 5.3698),
+    ("France", "Bordeaux", 44.8378, -0.5792),
+    ("Germany", "Berlin", 52.5200, 13.4050),
+    ("Germany", "Hamburg", 53.5511, 9.9937),
+    ("Germany", "Munich", 48.1351, 11.5820),
+    ("Germany", "Cologne", 50.9375, 6.9603),
+    ("Germany", "Frankfurt", 50.1109, 8.6821),
+    ("Greece", "Athens", 37.9838, 23.7275),
+    ("Hungary", "Budapest", 47.4979, 19.0402),
+    ("Iceland", "Reykjavik", 64.1466, -21.9426),
+    ("Ireland", "Dublin", 53.3498, -6.2603),
+    ("Italy", "Rome", 41.9028, 12.4964),
+    ("Italy", "Milan", 45.4642, 9.1900),
+    ("Italy", "Naples", 40.8518, 14.2681),
+    ("Italy", "Venice", 45.4408, 12.3155),
+    ("Italy", "Florence", 43.7696, 11.2558),
+    ("Latvia", "Riga", 56.9496, 24.1052),
+    ("Lithuania", "Vilnius", 54.6872, 25.2797),
+    ("Netherlands", "Amsterdam", 52.3676, 4.9041),
+    ("Norway", "Oslo", 59.9139, 10.7522),
+    ("Norway", "Bergen", 60.3913, 5.3221),
+    ("Poland", "Warsaw", 52.2297, 21.0122),
+    ("Poland", "Krakow", 50.0647, 19.9450),
+    ("Portugal", "Lisbon", 38.7223, -9.1393),
+    ("Portugal", "Porto", 41.1579, -8.6291),
+    ("Romania", "Bucharest", 44.4268, 26.1025),
+    ("Russia", "Moscow", 55.7558, 37.6173),
+    ("Russia", "Saint Petersburg", 59.9311, 30.3609),
+    ("Serbia", "Belgrade", 44.7866, 20.4489),
+    ("Slovakia", "Bratislava", 48.1486, 17.1077),
+    ("Slovenia", "Ljubljana", 46.0569, 14.5058),
+    ("Spain", "Madrid", 40.4168, -3.7038),
+    ("Spain", "Barcelona", 41.3851, 2.1734),
+    ("Spain", "Seville", 37.3891, -5.9845),
+    ("Spain", "Valencia", 39.4699, -0.3763),
+    ("Sweden", "Stockholm", 59.3293, 18.0686),
+    ("Sweden", "Gothenburg", 57.7089, 11.9746),
+    ("Switzerland", "Zurich", 47.3769, 8.5417),
+    ("Switzerland", "Geneva", 46.2044, 6.1432),
+    ("Turkey", "Istanbul", 41.0082, 28.9784),
+    ("Turkey", "Ankara", 39.9334, 32.8597),
+    ("Ukraine", "Kyiv", 50.4501, 30.5234),
+    ("United Kingdom", "London", 51.5074, -0.1278),
+    ("United Kingdom", "Manchester", 53.4808, -2.2426),
+    ("United Kingdom", "Edinburgh", 55.9533, -3.1883),
+    // asia
+    ("Bangladesh", "Dhaka", 23.8103, 90.4125),
+    ("Cambodia", "Phnom Penh", 11.5564, 104.9282),
+    ("China", "Beijing", 39.9042, 116.4074),
+    ("China", "Shanghai", 31.2304, 121.4737),
+    ("China", "Guangzhou", 23.1291, 113.2644),
+    ("China", "Chengdu", 30.5728, 104.0668),
+    ("China", "Xi'an", 34.3416, 108.9398),
+    ("China", "Hong Kong", 22.3193, 114.1694),
+    ("India", "New Delhi", 28.6139, 77.2090),
+    ("India", "Mumbai", 19.0760, 72.8777),
+    ("India", "Bengaluru", 12.9716, 77.5946),
+    ("India", "Chennai", 13.0827, 80.2707),
+    ("India", "Kolkata", 22.5726, 88.3639),
+    ("India", "Hyderabad", 17.3850, 78.4867),
+    ("India", "Kochi", 9.9312, 76.2673),
+    ("India", "Jaipur", 26.9124, 75.7873),
+    ("Indonesia", "Jakarta", -6.2088, 106.8456),
+    ("Indonesia", "Denpasar", -8.6705, 115.2126),
+    ("Iran", "Tehran", 35.6892, 51.3890),
+    ("Israel", "Jerusalem", 31.7683, 35.2137),
+    ("Japan", "Tokyo", 35.6762, 139.6503),
+    ("Japan", "Osaka", 34.6937, 135.5023),
+    ("Japan", "Sapporo", 43.0618, 141.3545),
+    ("Japan", "Fukuoka", 33.5904, 130.4017),
+    ("Jordan", "Amman", 31.9454, 35.9284),
+    ("Kazakhstan", "Almaty", 43.2220, 76.8512),
+    ("Malaysia", "Kuala Lumpur", 3.1390, 101.6869),
+    ("Mongolia", "Ulaanbaatar", 47.8864, 106.9057),
+    ("Nepal", "Kathmandu", 27.7172, 85.3240),
+    ("Pakistan", "Karachi", 24.8607, 67.0011),
+    ("Pakistan", "Islamabad", 33.6844, 73.0479),
+    ("Philippines", "Manila", 14.5995, 120.9842),
+    ("Qatar", "Doha", 25.2854, 51.5310),
+    ("Saudi Arabia", "Riyadh", 24.7136, 46.6753),
+    ("Singapore", "Singapore", 1.3521, 103.8198),
+    ("South Korea", "Seoul", 37.5665, 126.9780),
+    ("South Korea", "Busan", 35.1796, 129.0756),
+    ("Sri Lanka", "Colombo", 6.9271, 79.8612),
+    ("Taiwan", "Taipei", 25.0330, 121.5654),
+    ("Thailand", "Bangkok", 13.7563, 100.5018),
+    ("Thailand", "Chiang Mai", 18.7883, 98.9853),
+    ("United Arab Emirates", "Dubai", 25.2048, 55.2708),
+    ("Uzbekistan", "Tashkent", 41.2995, 69.2401),
+    ("Vietnam", "Hanoi", 21.0278, 105.8342),
+    ("Vietnam", "Ho Chi Minh City", 10.8231, 106.6297),
+    // africa
+    ("Algeria", "Algiers", 36.7538, 3.0588),
+    ("Egypt", "Cairo", 30.0444, 31.2357),
+    ("Ethiopia", "Addis Ababa", 9.0300, 38.7400),
+    ("Ghana", "Accra", 5.6037, -0.1870),
+    ("Kenya", "Nairobi", -1.2921, 36.8219),
+    ("Madagascar", "Antananarivo", -18.8792, 47.5079),
+    ("Morocco", "Casablanca", 33.5731, -7.5898),
+    ("Morocco", "Marrakesh", 31.6295, -7.9811),
+    ("Namibia", "Windhoek", -22.5609, 17.0658),
+    ("Nigeria", "Lagos", 6.5244, 3.3792),
+    ("Senegal", "Dakar", 14.7167, -17.4677),
+    ("South Africa", "Johannesburg", -26.2041, 28.0473),
+    ("South Africa", "Cape Town", -33.9249, 18.4241),
+    ("Tanzania", "Dar es Salaam", -6.7924, 39.2083),
+    ("Tunisia", "Tunis", 36.8065, 10.1815),
+    // north america
+    ("Canada", "Toronto", 43.6532, -79.3832),
+    ("Canada", "Montreal", 45.5017, -73.5673),
+    ("Canada", "Vancouver", 49.2827, -123.1207),
+    ("Canada", "Calgary", 51.0447, -114.0719),
+    ("Cuba", "Havana", 23.1136, -82.3666),
+    ("Mexico", "Mexico City", 19.4326, -99.1332),
+    ("Mexico", "Guadalajara", 20.6597, -103.3496),
+    ("Mexico", "Cancun", 21.1619, -86.8515),
+    ("United States", "New York", 40.7128, -74.0060),
+    ("United States", "Boston", 42.3601, -71.0589),
+    ("United States", "Washington", 38.9072, -77.0369),
+    ("United States", "Miami", 25.7617, -80.1918),
+    ("United States", "Atlanta", 33.7490, -84.3880),
+    ("United States", "Chicago", 41.8781, -87.6298),
+    ("United States", "Houston", 29.7604, -95.3698),
+    ("United States", "Dallas", 32.7767, -96.7970),
+    ("United States", "Denver", 39.7392, -104.9903),
+    ("United States", "Phoenix", 33.4484, -112.0740),
+    ("United States", "Las Vegas", 36.1699, -115.1398),
+    ("United States", "Los Angeles", 34.0522, -118.2437),
+    ("United States", "San Francisco", 37.7749, -122.4194),
+    ("United States", "Seattle", 47.6062, -122.3321),
+    ("United States", "Anchorage", 61.2181, -149.9003),
+    ("United States", "Honolulu", 21.3069, -157.8583),
+    // south america
+    ("Argentina", "Buenos Aires", -34.6037, -58.3816),
+    ("Bolivia", "La Paz", -16.4897, -68.1193),
+    ("Brazil", "Sao Paulo", -23.5505, -46.6333),
+    ("Brazil", "Rio de Janeiro", -22.9068, -43.1729),
+    ("Brazil", "Brasilia", -15.7975, -47.8919),
+    ("Brazil", "Manaus", -3.1190, -60.0217),
+    ("Chile", "Santiago", -33.4489, -70.6693),
+    ("Colombia", "Bogota", 4.7110, -74.0721),
+    ("Ecuador", "Quito", -0.1807, -78.4678),
+    ("Peru", "Lima", -12.0464, -77.0428),
+    ("Peru", "Cusco", -13.5320, -71.9675),
+    ("Uruguay", "Montevideo", -34.9011, -56.1645),
+    ("Venezuela", "Caracas", 10.4806, -66.9036),
+    // oceania
+    ("Australia", "Sydney", -33.8688, 151.2093),
+    ("Australia", "Melbourne", -37.8136, 144.9631),
+    ("Australia", "Brisbane", -27.4698, 153.0251),
+    ("Australia", "Perth", -31.9505, 115.8605),
+    ("Australia", "Adelaide", -34.9285, 138.6007),
+    ("Australia", "Darwin", -12.4634, 130.8456),
+    ("Fiji", "Suva", -18.1248, 178.4501),
+    ("New Zealand", "Auckland", -36.8485, 174.7633),
+    ("New Zealand", "Wellington", -41.2865, 174.7762),
+    ("New Zealand", "Christchurch", -43.5321, 172.6362),
+];
+
+/// The latitude and longitude of a photo from the GPS tags of its exif, in degrees.
+/// South and west are negative.
+pub fn get_gps_from_exif(entry: &DirEntry) -> Option<(f64, f64)> {
+    if !exif_compatible_extension(entry) {
+        return None;
+    }
+    let exif = read_exif(entry.path()).ok()?;
+    let latitude = read_coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let longitude = read_coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+    let valid = (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude);
+    valid.then_some((latitude, longitude))
+}
+
+// the coordinate is stored as degrees, minutes and seconds with the hemisphere in a separate tag.
+fn read_coordinate(exif: &exif::Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let parts = match &field.value {
+        Value::Rational(parts) if !parts.is_empty() => parts,
+        _ => return None,
+    };
+    let degrees = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(part, scale)| part.to_f64() / scale)
+        .sum::<f64>();
+    if !degrees.is_finite() {
+        return None;
+    }
+    let negative = exif
+        .get_field(ref_tag, In::PRIMARY)
+        .is_some_and(|field| field.display_value().to_string().trim() == negative_ref);
+    Some(if negative { -degrees } else { degrees })
+}
+
+/// The nearest city to the coordinates, or none when every city is too far away.
+pub fn find_place(latitude: f64, longitude: f64) -> Option<Place> {
+    CITIES
+        .iter()
+        .map(|&(country, city, city_latitude, city_longitude)| {
+            let distance = distance_km((latitude, longitude), (city_latitude, city_longitude));
+            (Place { country, city }, distance)
+        })
+        .filter(|(_, distance)| *distance <= MAX_DISTANCE_KM)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(place, _)| place)
+}
+
+// the great circle distance by the haversine formula.
+fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (from_latitude, to_latitude) = (from.0.to_radians(), to.0.to_radians());
+    let latitude_delta = (to.0 - from.0).to_radians();
+    let longitude_delta = (to.1 - from.1).to_radians();
+    let a = (latitude_delta / 2.0).sin().powi(2)
+        + from_latitude.cos() * to_latitude.cos() * (longitude_delta / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
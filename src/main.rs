@@ -1,250 +1,995 @@
-use anyhow::{Context, Result};
-use chrono::{DateTime, Datelike, Month, NaiveDate, Utc};
-use clap::Parser;
+use bytesize::ByteSize;
+use chrono::{NaiveDate, Utc};
+use clap::{ArgEnum, Args as ClapArgs, Parser, Subcommand};
 use colored::*;
-use exif::{In, Tag};
-use photosort::Summary;
-use std::io::Write;
-use std::{
-    fs::{self, File},
-    io::BufReader,
-    path::{Path, PathBuf},
-    time::Instant,
+use log::{error, warn, Level};
+use photosort::{
+    CollisionHook, DateCommand, DatePolicy, DateSkew, DateTag, DiffCategory, EventHook, GroupBy,
+    HistoryEntry, MonthFormat, Owner, SortEvent, SortOptions, SortOrder, TargetExists, Transfer,
+    Zone, DEFAULT_TEMPLATE,
 };
-use walkdir::{DirEntry, WalkDir};
+use serde::Serialize;
+use std::io::{BufWriter, IsTerminal, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 
-use num_traits::cast::FromPrimitive;
+mod config;
 
+// sorting is the default, so the sort flags can be given without a subcommand.
 #[derive(Parser)]
-#[clap(version, about)]
-struct Args {
-    #[clap(short, long, parse(from_os_str))]
-    source_dir: PathBuf,
+#[clap(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// When to color the output. auto colors it when stdout is a terminal, unless NO_COLOR is set
+    #[clap(long, arg_enum, default_value = "auto", global = true)]
+    color: ColorChoice,
 
-    #[clap(short, long, parse(from_os_str))]
-    target_dir: PathBuf,
+    #[clap(flatten)]
+    args: Args,
 }
 
-static EXIF_COMPATIBLE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "tiff"];
+#[derive(Subcommand)]
+enum Command {
+    /// Sort the photos into the target directory. The same as giving no subcommand
+    Sort(Box<Args>),
 
-fn main() {
-    let args = Args::parse();
-    if !args.source_dir.exists() || !args.source_dir.is_dir() {
-        eprintln!("The source path is invalid. Please make sure it exists and is a directory.");
-        std::process::exit(1);
+    /// Reverse the actions recorded in a journal by --journal
+    Undo {
+        #[clap(parse(from_os_str))]
+        journal: PathBuf,
+    },
+
+    /// Count the photos per year and month without copying anything
+    Scan {
+        #[clap(short, long, parse(from_os_str))]
+        source_dir: PathBuf,
+
+        /// The format of the counts
+        #[clap(long, arg_enum, default_value = "text")]
+        format: Format,
+    },
+
+    /// Print where a single file would be sorted to, and the date it's sorted by, without copying it
+    #[clap(
+        mut_arg("source-dir", |arg| arg.required(false)),
+        mut_arg("target-dir", |arg| arg.required(false))
+    )]
+    Where {
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+
+        #[clap(flatten)]
+        args: Box<Args>,
+    },
+}
+
+#[derive(ClapArgs)]
+struct Args {
+    // optional only so that the subcommands can leave them out.
+    #[clap(short, long, parse(from_os_str), required = true)]
+    source_dir: Option<PathBuf>,
+
+    #[clap(short, long, parse(from_os_str), required = true)]
+    target_dir: Option<PathBuf>,
+
+    /// Walk into the symlinked files and directories of the source
+    #[clap(long)]
+    follow_symlinks: bool,
+
+    /// Leave out the files and folders whose name starts with a dot, eg. .thumbnails or .Trash
+    #[clap(long)]
+    skip_hidden: bool,
+
+    /// Only sort the files at most this many folders down the source. 1 sorts only the files directly in it
+    #[clap(long)]
+    max_depth: Option<usize>,
+
+    /// Only sort the files at least this many folders down the source
+    #[clap(long)]
+    min_depth: Option<usize>,
+
+    /// The folder layout at the target as a strftime pattern, eg. %Y/%m/%d
+    #[clap(long, default_value = DEFAULT_TEMPLATE)]
+    layout: String,
+
+    /// How deep the folders at the target go. A simpler alternative to --layout
+    #[clap(long, arg_enum, conflicts_with = "layout")]
+    granularity: Option<Granularity>,
+
+    /// How the month name (%B) is written in the folder names. locale uses the language of LANG
+    #[clap(long, arg_enum, default_value = "name")]
+    month_format: MonthFormat,
+
+    /// Add a strftime pattern to the start of every file name, eg. %Y-%m-%d_
+    #[clap(long)]
+    name_prefix: Option<String>,
+
+    /// Add a strftime pattern to the end of every file name, before the extension, eg. _%Y
+    #[clap(long)]
+    name_suffix: Option<String>,
+
+    /// Rename every file from its metadata, eg. {date}_{camera}_{counter}.{ext}. See the README for the tokens
+    #[clap(long, conflicts_with_all = &["name-prefix", "name-suffix"])]
+    rename: Option<String>,
+
+    /// Sort the photos with GPS coordinates into country/city/year folders, eg. France/Paris/2019
+    #[clap(long)]
+    by_location: bool,
+
+    /// Sort the files into a folder of the field first, eg. camera for Canon EOS 40D/2008/May/30. Given more than once, the folders nest in the given order
+    #[clap(long, arg_enum, value_name = "FIELD", use_value_delimiter = true)]
+    group_by: Vec<GroupBy>,
+
+    /// Sort the photos into a folder of their camera model first, the same as --group-by camera
+    #[clap(long)]
+    by_camera: bool,
+
+    /// Sort the photos into a folder of their lens model first, the same as --group-by lens
+    #[clap(long)]
+    by_lens: bool,
+
+    /// Sort the images into Landscape, Portrait or Square folders first, the same as --group-by orientation
+    #[clap(long)]
+    by_orientation: bool,
+
+    /// Sort the files with an extension under another target directory, eg. mp4=/videos. Can be given more than once
+    #[clap(long = "route", value_name = "EXT=PATH", parse(try_from_str = parse_route), use_value_delimiter = true)]
+    routes: Vec<(String, PathBuf)>,
+
+    /// Write the absolute source and target paths of every copied file to a CSV file
+    #[clap(long, parse(from_os_str))]
+    mapping_csv: Option<PathBuf>,
+
+    /// The path to a CSV file listing the action taken for every source file
+    #[clap(long, parse(from_os_str))]
+    manifest: Option<PathBuf>,
+
+    /// Append the SHA-256 of every sorted file to this file, in the format of sha256sum -c run from the target
+    #[clap(long, parse(from_os_str))]
+    checksum_manifest: Option<PathBuf>,
+
+    /// Record the paths in the manifest and the json summary relative to the target, or to the source for the source files
+    #[clap(long)]
+    relative_paths: bool,
+
+    /// Append a JSON line for every copied, moved or linked file to this file, for photosort undo
+    #[clap(long, parse(from_os_str))]
+    journal: Option<PathBuf>,
+
+    /// Record the sorted files in this file as they are sorted, and leave out the files recorded by an earlier run, eg. to resume an interrupted run
+    #[clap(long, parse(from_os_str))]
+    state: Option<PathBuf>,
+
+    /// Remove the --state file once every file was sorted without an error
+    #[clap(long, requires = "state")]
+    remove_state: bool,
+
+    /// Leave out the files modified before the last run recorded in this file, and record the run in it once every file was sorted
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    since_last_run: Option<PathBuf>,
+
+    /// Remove the files at the target which no source file sorts to, eg. after deleting photos from the source
+    #[clap(long, conflicts_with_all = &["move-files", "dedupe-by-hash", "include-ext", "exclude-ext", "min-size", "max-size", "after", "before", "max-bytes", "min-depth", "max-depth", "routes", "skip-empty", "skip-hidden", "state", "since-last-run"])]
+    mirror: bool,
+
+    /// Remove the files for --mirror without asking
+    #[clap(short, long, requires = "mirror")]
+    yes: bool,
+
+    /// Stop copying before the copied bytes exceed this size, eg. 500MB or 2GiB
+    #[clap(long, visible_alias = "max-total")]
+    max_bytes: Option<ByteSize>,
+
+    /// The exif tag tried first for the date. auto picks the digitized date for scans
+    #[clap(long, arg_enum, default_value = "original")]
+    date_tag: DateTag,
+
+    /// Which date to sort by when a file has an exif date and a modified time
+    #[clap(long, arg_enum, default_value = "exif-first")]
+    date_policy: DatePolicy,
+
+    /// Never date a file by its modified time. The files without a capture date are left out, or go to --unknown-dir
+    #[clap(long)]
+    exif_only: bool,
+
+    /// The folder under the target for the files without a capture date with --exif-only, eg. Unknown
+    #[clap(long, parse(from_os_str), requires = "exif-only")]
+    unknown_dir: Option<PathBuf>,
+
+    /// Warn about a file whose exif date is more than this many days from its modified time, eg. a corrupt date in 1970
+    #[clap(long, value_name = "DAYS")]
+    max_date_skew: Option<u32>,
+
+    /// Sort a file whose exif date is beyond --max-date-skew by its modified time instead
+    #[clap(long, requires = "max-date-skew")]
+    skew_fallback: bool,
+
+    /// Read the date from the json sidecar of a Google Takeout export, eg. IMG_0001.jpg.json, when a file has no exif
+    #[clap(long)]
+    takeout: bool,
+
+    /// Ask this command for the date of every file ahead of its exif, eg. "exiftool -s3 -d %Y-%m-%d -DateTimeOriginal". It's run with the path of the file and prints the date as YYYY-MM-DD. Quote an argument holding a space like a shell would
+    #[clap(long, value_name = "CMD")]
+    date_command: Option<DateCommand>,
+
+    /// Read the date from the file name, eg. IMG_20190715_123456.jpg, when a file has no exif
+    #[clap(long)]
+    date_from_name: bool,
+
+    /// A regex for the date in a file name with the year, month and day groups, tried before the built in ones. Can be given more than once
+    #[clap(
+        long = "name-pattern",
+        value_name = "REGEX",
+        requires = "date-from-name"
+    )]
+    name_patterns: Vec<String>,
+
+    /// Sort by the date in this time zone, eg. +05:30 or Asia/Kolkata. Needs the offset in the exif
+    #[clap(long)]
+    tz: Option<Zone>,
+
+    /// Record a summary of the run in a history file at the target
+    #[clap(long)]
+    history: bool,
+
+    /// The number of runs kept in the history file
+    #[clap(long, default_value = "50")]
+    history_limit: usize,
+
+    /// Also append the summary to this file without colours, eg. to keep a log of cron runs
+    #[clap(long, parse(from_os_str))]
+    summary_out: Option<PathBuf>,
+
+    /// Compare the run with a summary saved by an earlier run with --format json, eg. last night's
+    #[clap(long, parse(from_os_str), conflicts_with_all = &["diff", "plan", "collision-report", "find-duplicates"])]
+    baseline: Option<PathBuf>,
+
+    /// Move the files to the target instead of copying them
+    #[clap(long = "move")]
+    move_files: bool,
+
+    /// Link to the files from the target instead of copying them
+    #[clap(long, conflicts_with = "move-files")]
+    symlink: bool,
+
+    /// Point the symlinks at the source by a relative path, so the source and the target can be moved together
+    #[clap(long, requires = "symlink")]
+    relative_symlink: bool,
+
+    /// Hard link the files at the target instead of copying them. Both must be on the same filesystem
+    #[clap(long, conflicts_with_all = &["move-files", "symlink"])]
+    hardlink: bool,
+
+    /// Reorganize the source into the layout by moving its files within it. The target must be the same directory
+    #[clap(long, conflicts_with_all = &["symlink", "hardlink", "archive", "mirror", "routes"])]
+    in_place: bool,
+
+    /// Copy the files into a zip archive for every day at the target, eg. 2008-05-30.zip, instead of the folders of the layout
+    #[clap(long, conflicts_with_all = &["move-files", "symlink", "hardlink", "mirror", "journal", "dedupe-by-hash", "stable-by-hash", "no-clobber-across-dates", "verify-content", "preserve-perms"])]
+    archive: bool,
+
+    /// What to do with a file when a file with the same name is at the target. A file of the same size is skipped unless overwritten
+    #[clap(long, arg_enum, default_value = "skip")]
+    target_exists: TargetExists,
+
+    /// Replace a file at the target only when the source was modified after it, whatever their sizes, and skip it otherwise, like cp -u
+    #[clap(long, conflicts_with_all = &["interactive", "archive", "symlink", "hardlink"])]
+    update: bool,
+
+    /// Ask on the terminal what to do with every file whose name is taken by a different file at the target. Follows --target-exists when not run in a terminal
+    #[clap(long, conflicts_with_all = &["dry-run", "diff", "plan", "collision-report", "find-duplicates", "archive", "symlink", "hardlink"])]
+    interactive: bool,
+
+    /// Exit with code 2 when a file was skipped as a duplicate
+    #[clap(long)]
+    strict: bool,
+
+    /// Let the copied files take the current time as their modified time
+    #[clap(long)]
+    no_preserve_mtime: bool,
+
+    /// Give the copied files the permission bits of their source on Unix instead of the defaults
+    #[clap(long)]
+    preserve_perms: bool,
+
+    /// Copy the extended attributes of the files, eg. the Finder tags and comments, on Linux and macOS
+    #[clap(long)]
+    preserve_xattrs: bool,
+
+    /// Give the copied or moved files, and the folders created for them, this user and group id on Unix, eg. 1000:1000. Usually needs root
+    #[clap(long, value_name = "UID:GID", conflicts_with_all = &["symlink", "hardlink", "archive"])]
+    owner: Option<Owner>,
+
+    /// Try a failed copy or move again up to this many times, waiting longer after each failure
+    #[clap(long, default_value = "0")]
+    retries: u32,
+
+    /// Print where every file would be copied without copying anything
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+
+    /// Show what a run would change at the target without copying anything
+    #[clap(long)]
+    diff: bool,
+
+    /// List every file along with its category in the diff
+    #[clap(long, requires = "diff")]
+    diff_list: bool,
+
+    /// List the files which would be copied, skipped or conflict at the target, without copying anything
+    #[clap(long, conflicts_with = "diff")]
+    plan: bool,
+
+    /// List the target paths which more than one source file sorts to, eg. the same name on the same day, without copying anything
+    #[clap(long, conflicts_with_all = &["diff", "plan"])]
+    collision_report: bool,
+
+    /// List the groups of source files with the same contents, eg. the same photo imported twice, without copying anything
+    #[clap(long, conflicts_with_all = &["diff", "plan", "collision-report"])]
+    find_duplicates: bool,
+
+    /// The order the files are sorted in, which decides eg. which of two files with the same name is numbered
+    #[clap(long, arg_enum, default_value = "name")]
+    sort_order: SortOrder,
+
+    /// The number of files sorted at once. Defaults to the number of logical CPUs, or to 4 when hashing the files
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    /// The number of files hashed at once with --dedupe-by-hash, --stable-by-hash or --no-clobber-across-dates. Defaults to the number of logical CPUs
+    #[clap(long)]
+    hash_threads: Option<usize>,
+
+    /// Skip files whose contents are already present anywhere at the target, compared by SHA-256
+    #[clap(long)]
+    dedupe_by_hash: bool,
+
+    /// Skip files whose contents are already anywhere at the target, even when their date changed since the last run
+    #[clap(long)]
+    stable_by_hash: bool,
+
+    /// Skip files whose contents are already at the target under the same name in any date folder, eg. after adding exif to a sorted photo
+    #[clap(long)]
+    no_clobber_across_dates: bool,
+
+    /// Compare the contents of a file with the file of the same name and size at the target before skipping it
+    #[clap(long)]
+    verify_content: bool,
+
+    /// The format of the summary printed at the end of the run
+    #[clap(long, arg_enum, default_value = "text")]
+    format: Format,
+
+    /// Only print the summary and the errors, not a line for every file
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Also print the date of every file and whether it came from the exif or the modified time
+    #[clap(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Show a progress bar while sorting. It's hidden when stdout is not a terminal
+    #[clap(long)]
+    progress: bool,
+
+    /// List the photos taken in quick succession, eg. the shots of a burst, in the summary
+    #[clap(long)]
+    report_bursts: bool,
+
+    /// The most seconds between two photos of a burst
+    #[clap(long, default_value = "2", requires = "report-bursts")]
+    burst_window: u64,
+
+    /// Leave out the empty files, eg. failed downloads, rather than sorting them
+    #[clap(long)]
+    skip_empty: bool,
+
+    /// Leave out the files smaller than this size, eg. 100KB or 1MiB
+    #[clap(long)]
+    min_size: Option<ByteSize>,
+
+    /// Leave out the files larger than this size, eg. 2MB or 4GiB
+    #[clap(long)]
+    max_size: Option<ByteSize>,
+
+    /// Only sort the files with these extensions, eg. jpg,png,heic
+    #[clap(long, use_value_delimiter = true)]
+    include_ext: Vec<String>,
+
+    /// Leave the files with these extensions alone, eg. txt,xmp
+    #[clap(long, use_value_delimiter = true)]
+    exclude_ext: Vec<String>,
+
+    /// Only sort the files captured on or after this date, eg. 2022-01-31
+    #[clap(long)]
+    after: Option<NaiveDate>,
+
+    /// Only sort the files captured on or before this date, eg. 2022-02-28
+    #[clap(long)]
+    before: Option<NaiveDate>,
+}
+
+impl Args {
+    // the per file lines would break the json on stdout, and the plan and the
+    // collision report list the files themselves.
+    fn per_file_output(&self) -> bool {
+        self.format == Format::Text
+            && !self.quiet
+            && !self.plan
+            && !self.collision_report
+            && !self.find_duplicates
     }
-    if !args.target_dir.exists() || !args.target_dir.is_dir() {
-        eprintln!("The target path is invalid. Please make sure it exists and is a directory.");
-        std::process::exit(1);
+
+    // the questions need a terminal to be answered on.
+    fn asks(&self) -> bool {
+        self.interactive && std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
     }
 
-    let stats = copy_files(args.source_dir, args.target_dir);
-    println!("{}", stats.display());
-}
+    fn layout(&self) -> &str {
+        match self.granularity {
+            Some(Granularity::Year) => "%Y",
+            Some(Granularity::Month) => "%Y/%B",
+            Some(Granularity::Day) => DEFAULT_TEMPLATE,
+            None => &self.layout,
+        }
+    }
 
-fn copy_files(source_path: PathBuf, target_path: PathBuf) -> Summary {
-    let now = Instant::now();
+    // clap requires both directories unless a subcommand is given.
+    fn source_dir(&self) -> &Path {
+        self.source_dir
+            .as_deref()
+            .expect("the source directory is required")
+    }
 
-    let mut summary = Summary::init();
+    fn target_dir(&self) -> &Path {
+        self.target_dir
+            .as_deref()
+            .expect("the target directory is required")
+    }
 
-    let stdout = std::io::stdout();
-    let mut lock = stdout.lock();
+    fn sort_options(&self, history_path: &Path) -> SortOptions {
+        // keep photosort from sorting its own binary, history and summary files,
+        // and a mirror from removing the history of earlier runs.
+        let mut own_files = Vec::new();
+        if let Ok(exe) = std::env::current_exe() {
+            own_files.push(exe);
+        }
+        own_files.push(history_path.to_path_buf());
+        own_files.extend(self.summary_out.clone());
+        own_files.extend(self.baseline.clone());
+        SortOptions {
+            follow_symlinks: self.follow_symlinks,
+            skip_hidden: self.skip_hidden,
+            archive: self.archive,
+            min_depth: self.min_depth,
+            max_depth: self.max_depth,
+            layout: self.layout().to_string(),
+            month_format: self.month_format,
+            name_prefix: self.name_prefix.clone(),
+            name_suffix: self.name_suffix.clone(),
+            rename: self.rename.clone(),
+            by_location: self.by_location,
+            group_by: self.group_by(),
+            routes: self.routes.clone(),
+            transfer: self.transfer(),
+            relative_symlink: self.relative_symlink,
+            date_tag: self.date_tag,
+            date_policy: self.date_policy,
+            date_skew: self.max_date_skew.map(|max_days| DateSkew {
+                max_days,
+                fallback: self.skew_fallback,
+            }),
+            exif_only: self.exif_only,
+            unknown_dir: self.unknown_dir.clone(),
+            takeout: self.takeout,
+            date_command: self.date_command.clone(),
+            date_from_name: self.date_from_name,
+            name_patterns: self.name_patterns.clone(),
+            zone: self.tz,
+            target_exists: self.target_exists,
+            update: self.update,
+            after: self.after,
+            before: self.before,
+            skip_empty: self.skip_empty,
+            min_size: self.min_size.map(|min_size| min_size.as_u64()),
+            max_size: self.max_size.map(|max_size| max_size.as_u64()),
+            include_ext: self.include_ext.clone(),
+            exclude_ext: self.exclude_ext.clone(),
+            max_bytes: self.max_bytes.map(|max_bytes| max_bytes.as_u64()),
+            sort_order: self.sort_order,
+            jobs: self.jobs,
+            hash_threads: self.hash_threads,
+            dedupe_by_hash: self.dedupe_by_hash,
+            stable_by_hash: self.stable_by_hash,
+            no_clobber_across_dates: self.no_clobber_across_dates,
+            verify_content: self.verify_content,
+            preserve_mtime: !self.no_preserve_mtime,
+            preserve_perms: self.preserve_perms,
+            preserve_xattrs: self.preserve_xattrs,
+            owner: self.owner,
+            retries: self.retries,
+            dry_run: self.dry_run,
+            mapping_csv: self.mapping_csv.clone(),
+            manifest: self.manifest.clone(),
+            checksum_manifest: self.checksum_manifest.clone(),
+            relative_paths: self.relative_paths,
+            journal: self.journal.clone(),
+            state: self.state.clone(),
+            in_place: self.in_place,
+            remove_state: self.remove_state,
+            since_last_run: self.since_last_run.clone(),
+            mirror: self.mirror,
+            own_files,
+            progress: self.progress,
+            burst_window: self
+                .report_bursts
+                .then(|| Duration::from_secs(self.burst_window)),
+            on_event: self.per_file_output().then(|| {
+                let printer = Printer {
+                    flush_lines: std::io::stdout().is_terminal(),
+                    transfer: self.transfer(),
+                    dry_run: self.dry_run,
+                    diff: self.diff,
+                    diff_list: self.diff_list,
+                    verbose: self.verbose,
+                };
+                Box::new(move |event| printer.print(event)) as EventHook
+            }),
+            on_collision: self.asks().then(|| {
+                let mut ask = photosort::ask_on_collision(
+                    std::io::BufReader::new(std::io::stdin()),
+                    std::io::stderr(),
+                );
+                // the lines of the files sorted so far come before the question.
+                Box::new(move |source: &Path, target: &Path| {
+                    flush_output();
+                    ask(source, target)
+                }) as CollisionHook
+            }),
+            ..SortOptions::new(self.source_dir(), self.target_dir())
+        }
+    }
 
-    for entry in WalkDir::new(source_path) {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(err) => {
-                eprintln!("{} while scanning - [{}]", "Error".red(), err);
-                summary.mark_scan_error();
-                continue;
+    // the shorthands nest after the fields given with --group-by, in a fixed order.
+    fn group_by(&self) -> Vec<GroupBy> {
+        let mut group_by = self.group_by.clone();
+        for (given, group) in [
+            (self.by_camera, GroupBy::Camera),
+            (self.by_lens, GroupBy::Lens),
+            (self.by_orientation, GroupBy::Orientation),
+        ] {
+            if given && !group_by.contains(&group) {
+                group_by.push(group);
             }
-        };
+        }
+        group_by
+    }
 
-        // walkdir also returns directory entries. Skip them.
-        if entry.file_type().is_dir() {
-            continue;
+    fn transfer(&self) -> Transfer {
+        if self.move_files || self.in_place {
+            Transfer::Move
+        } else if self.symlink {
+            Transfer::Symlink
+        } else if self.hardlink {
+            Transfer::Hardlink
+        } else {
+            Transfer::Copy
         }
+    }
+}
+
+/// Prints the line for every file sorted by the run.
+struct Printer {
+    // a terminal shows every line as it comes, while a pipe or a file gets
+    // them in blocks, since flushing every line slows down a large run.
+    flush_lines: bool,
+    transfer: Transfer,
+    dry_run: bool,
+    diff: bool,
+    diff_list: bool,
+    verbose: bool,
+}
 
-        // get the date of the file from the exif or the metadata
-        let (file_date, exif_error) = get_file_date(&entry);
-        let file_date = match file_date {
-            Ok(file_date) => {
-                if exif_error {
-                    summary.mark_exif_error(entry.clone().into_path());
-                }
-                file_date
+impl Printer {
+    fn print(&self, event: SortEvent) {
+        let file = match &event {
+            SortEvent::Scanned { .. } => return,
+            SortEvent::Removed(path) => {
+                let verb = if self.dry_run {
+                    "Would remove"
+                } else {
+                    "Removed"
+                };
+                self.line(format_args!("{} {}", verb.red().bold(), path.display()));
+                return;
             }
-            Err(err) => {
-                eprintln!(
-                    "{} while reading the file date for the file {} - [{}]",
-                    "Error".red(),
-                    entry.path().display(),
-                    err
-                );
-                summary.mark_error(entry.into_path());
-                continue;
+            SortEvent::Copied(file)
+            | SortEvent::Skipped(file)
+            | SortEvent::Duplicate(file)
+            | SortEvent::Filtered(file)
+            | SortEvent::Error(file) => file,
+        };
+        if self.verbose {
+            if let Some(date) = file.date {
+                self.line(format_args!(
+                    "{} {} is from {} by its {}",
+                    "Dated".blue(),
+                    file.source.display(),
+                    date.date(),
+                    date.label()
+                ));
             }
+        }
+        let target = match &file.target {
+            Some(target) => target,
+            None => return,
         };
-
-        // convert the timestamp to a path at the target
-        let target_path = get_target_path(&entry, file_date, &target_path);
-
-        // if the file already exists at the target then skip it
-        if target_path.exists() {
-            let source_len = match entry.metadata() {
-                Ok(metadata) => metadata.len(),
-                Err(err) => {
-                    eprintln!(
-                        "{} while trying to read the size of the source file {} - [{}]",
-                        "Error".red(),
-                        entry.path().display(),
-                        err
-                    );
-                    summary.mark_error(entry.into_path());
-                    continue;
-                }
-            };
-            let target_len = match target_path.metadata() {
-                Ok(metadata) => metadata.len(),
-                Err(err) => {
-                    eprintln!(
-                        "{} while trying to read the size of the target file {} - [{}]",
-                        "Error".red(),
-                        target_path.display(),
-                        err
-                    );
-                    summary.mark_error(entry.into_path());
-                    continue;
-                }
+        if self.diff {
+            let category = match event {
+                SortEvent::Copied(_) => DiffCategory::New,
+                SortEvent::Skipped(_) => DiffCategory::Present,
+                SortEvent::Duplicate(_) => DiffCategory::Collision,
+                SortEvent::Filtered(_) => DiffCategory::Filtered,
+                _ => return,
             };
-            if source_len == target_len {
-                writeln!(
-                    lock,
-                    "{} {}. It's already present at {}",
-                    "Skipping".cyan(),
-                    entry.path().display(),
-                    target_path.display()
-                )
-                .expect("Error writing to stdout");
-                summary.mark_skipped();
-            } else {
-                eprintln!("A file with the same name but a different size exists at the target {}. This file would be skipped for copying - {}", 
-                    target_path.parent().unwrap().display(), entry.path().display());
-                summary.mark_duplicate(entry.into_path());
-            }
-            continue;
-        }
-
-        // create the parent directory structure if it does not exist
-        if let Some(parent_path) = target_path.parent() {
-            match fs::create_dir_all(parent_path) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!(
-                        "{} creating the parent directory {} at the target - [{}]",
-                        "Error".red(),
-                        parent_path.display(),
-                        err
-                    );
-                    summary.mark_error(entry.into_path());
-                    continue;
-                }
+            if self.diff_list {
+                self.line(format_args!(
+                    "{:<9} {} to {}",
+                    category.label(),
+                    file.source.display(),
+                    target.display()
+                ));
             }
+            return;
         }
-
-        // copy the file
-        match fs::copy(entry.path(), &target_path) {
-            Ok(bytes) => {
-                writeln!(
-                    lock,
+        match event {
+            SortEvent::Copied(_) => {
+                let verb = if self.dry_run {
+                    self.transfer.planned_verb()
+                } else {
+                    self.transfer.verb()
+                };
+                self.line(format_args!(
                     "{} {} to {}",
-                    "Copied".green().bold(),
-                    entry.path().display(),
-                    target_path.display()
-                )
-                .expect("Error writing to stdout");
-                summary.mark_copied(bytes);
-            }
-            Err(err) => {
-                eprintln!(
-                    "{} while copying {} to {} - [{}]",
-                    "Error".red(),
-                    entry.path().display(),
-                    target_path.display(),
-                    err
-                );
-                summary.mark_error(entry.into_path());
+                    verb.green().bold(),
+                    file.source.display(),
+                    target.display()
+                ));
             }
+            SortEvent::Skipped(_) => self.line(format_args!(
+                "{} {}. It's already present at {}",
+                "Skipping".cyan(),
+                file.source.display(),
+                target.display()
+            )),
+            _ => {}
         }
     }
-    summary.set_duration(now.elapsed());
 
-    summary
+    fn line(&self, line: std::fmt::Arguments) {
+        let mut output = output();
+        // a closed pipe, eg. head, leaves nothing more to print to.
+        let _ = writeln!(output, "{}", line);
+        if self.flush_lines {
+            let _ = output.flush();
+        }
+    }
 }
 
-fn get_target_path(entry: &DirEntry, file_date: NaiveDate, target_root: &Path) -> PathBuf {
-    let mut final_path = PathBuf::new();
-    final_path.push(target_root);
-    final_path.push(file_date.year().to_string());
-    final_path.push(Month::from_u32(file_date.month()).unwrap().name());
-    final_path.push(file_date.day().to_string());
-    final_path.push(entry.file_name());
+// the lines of the files, shared with the logger and the interrupt handler so
+// that they're flushed before a warning or an exit and come out in order.
+static OUTPUT: LazyLock<Mutex<BufWriter<Stdout>>> =
+    LazyLock::new(|| Mutex::new(BufWriter::new(std::io::stdout())));
 
-    final_path
+fn output() -> MutexGuard<'static, BufWriter<Stdout>> {
+    OUTPUT.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-fn get_file_date(entry: &DirEntry) -> (Result<NaiveDate>, bool) {
-    let mut exif_error = false;
-    if exif_compatible_extension(entry) {
-        match get_date_from_exif(entry) {
-            Ok(date) => return (Ok(date), exif_error),
-            Err(err) => {
-                exif_error = true;
-                eprintln!(
-                    "{} Could not read exif from the file {} - [{}]. Will default to file modified time.", "Warning.".yellow(),
-                    entry.path().display(),
-                    err.root_cause()
-                );
+fn flush_output() {
+    let _ = output().flush();
+}
+
+// a run stopped with ctrl-c still prints the lines of the files it sorted.
+fn flush_on_interrupt() {
+    let handler = ctrlc::set_handler(|| {
+        flush_output();
+        std::process::exit(130);
+    });
+    if let Err(err) = handler {
+        error!(
+            "{} while setting up the interrupt handler - [{}]",
+            "Error".red(),
+            err
+        );
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum Granularity {
+    Year,
+    Month,
+    Day,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// the history of runs, kept at the root of the target.
+static HISTORY_FILE_NAME: &str = ".photosort-history.jsonl";
+
+fn main() {
+    init_logger();
+    let args = config::load_config()
+        .and_then(|config| config.apply(std::env::args_os().collect()))
+        .unwrap_or_else(|err| fail(err));
+    let cli = Cli::parse_from(args);
+    // auto leaves it to the colored crate, which checks the terminal and NO_COLOR.
+    match cli.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+    }
+    match cli.command {
+        Some(Command::Sort(args)) => sort(*args),
+        Some(Command::Undo { journal }) => undo(&journal),
+        Some(Command::Scan { source_dir, format }) => scan(&source_dir, format),
+        Some(Command::Where { file, args }) => locate(&file, *args),
+        None => sort(cli.args),
+    }
+}
+
+fn sort(args: Args) {
+    let history_path = args.target_dir().join(HISTORY_FILE_NAME);
+    if args.per_file_output() {
+        flush_on_interrupt();
+    }
+
+    if args.diff {
+        let diff =
+            photosort::diff(args.sort_options(&history_path)).unwrap_or_else(|err| fail(err));
+        flush_output();
+        match args.format {
+            Format::Text => println!("{}", diff.display()),
+            Format::Json => print_json(&diff),
+        }
+        return;
+    }
+
+    if args.plan {
+        let plan =
+            photosort::plan(args.sort_options(&history_path)).unwrap_or_else(|err| fail(err));
+        flush_output();
+        match args.format {
+            Format::Text => println!("{}", plan.display()),
+            Format::Json => print_json(&plan),
+        }
+        return;
+    }
+
+    if args.collision_report {
+        let report =
+            photosort::collisions(args.sort_options(&history_path)).unwrap_or_else(|err| fail(err));
+        flush_output();
+        match args.format {
+            Format::Text => println!("{}", report.display()),
+            Format::Json => print_json(&report),
+        }
+        return;
+    }
+
+    if args.find_duplicates {
+        let report = photosort::find_duplicates(args.sort_options(&history_path))
+            .unwrap_or_else(|err| fail(err));
+        flush_output();
+        match args.format {
+            Format::Text => println!("{}", report.display()),
+            Format::Json => print_json(&report),
+        }
+        return;
+    }
+
+    if args.mirror && !args.dry_run && !args.yes {
+        confirm_mirror(args.target_dir());
+    }
+    if args.interactive && !args.asks() {
+        warn!(
+            "{} --interactive needs a terminal to ask on, so the collisions follow --target-exists.",
+            "Warning.".yellow()
+        );
+    }
+    // a broken baseline is found before the run rather than after it.
+    let baseline = args.baseline.as_ref().map(|path| {
+        photosort::load_summary(path).unwrap_or_else(|err| {
+            fail(anyhow::anyhow!(
+                "Failed to load the baseline {} - [{:#}]",
+                path.display(),
+                err
+            ))
+        })
+    });
+    let options = args.sort_options(&history_path);
+    let roots = options.path_roots();
+    let stats = photosort::sort(options).unwrap_or_else(|err| fail(err));
+    flush_output();
+    // the printed summary keeps the whole paths, unlike the json shared from it.
+    let shared = args
+        .relative_paths
+        .then(|| stats.with_relative_paths(&roots));
+    let shared = shared.as_ref().unwrap_or(&stats);
+    if args.history && !args.dry_run {
+        let entry = HistoryEntry::new(&stats, Utc::now());
+        if let Err(err) = photosort::append_history(&history_path, &entry, args.history_limit) {
+            error!(
+                "{} while recording the run in {} - [{}]",
+                "Error".red(),
+                history_path.display(),
+                err
+            );
+        }
+    }
+    if let Some(path) = &args.summary_out {
+        if let Err(err) = photosort::append_summary(path, shared, Utc::now()) {
+            error!(
+                "{} while writing the summary to {} - [{:#}]",
+                "Error".red(),
+                path.display(),
+                err
+            );
+        }
+    }
+    match args.format {
+        Format::Text => println!("{}", stats.display()),
+        Format::Json => print_json(shared),
+    }
+    // stdout holds only the summary json, so it can be the baseline of the next run.
+    if let Some(baseline) = &baseline {
+        let changes = photosort::diff_summaries(baseline, &stats).display();
+        match args.format {
+            Format::Text => println!("{}", changes),
+            Format::Json => eprintln!("{}", changes),
+        }
+    }
+    std::process::exit(stats.exit_code(args.strict));
+}
+
+// a mirror deletes files, so it's only run once the user agrees.
+fn confirm_mirror(target_dir: &Path) {
+    if !std::io::stdin().is_terminal() {
+        fail(anyhow::anyhow!(
+            "--mirror removes files from the target. Pass --yes to confirm it when not running in a terminal."
+        ));
+    }
+    print!(
+        "--mirror removes every file at {} which no source file sorts to. Continue? [y/N] ",
+        target_dir.display()
+    );
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    let confirmed = std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    if !confirmed {
+        fail(anyhow::anyhow!("Cancelled the run."));
+    }
+}
+
+fn undo(journal: &Path) {
+    let summary = photosort::undo(journal).unwrap_or_else(|err| fail(err));
+    println!("{}", summary.display());
+    if summary.error_count > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn scan(source_dir: &Path, format: Format) {
+    let report = photosort::scan(source_dir).unwrap_or_else(|err| fail(err));
+    match format {
+        Format::Text => println!("{}", report.display()),
+        Format::Json => print_json(&report),
+    }
+    if report.error_count > 0 {
+        std::process::exit(1);
+    }
+}
+
+// where the file goes, relative to the current directory unless a target is
+// given. The layouts of the folders it's in are read from the given source.
+fn locate(file: &Path, mut args: Args) {
+    args.target_dir.get_or_insert_with(PathBuf::new);
+    args.source_dir
+        .get_or_insert_with(|| file.parent().unwrap_or(Path::new("")).to_path_buf());
+    let history_path = args.target_dir().join(HISTORY_FILE_NAME);
+    let located =
+        photosort::locate(file, &args.sort_options(&history_path)).unwrap_or_else(|err| fail(err));
+    let (Some(target), Some(date)) = (located.target, located.date) else {
+        unreachable!("a located file has a target and a date");
+    };
+    match args.format {
+        Format::Text => {
+            println!("{}", target.display());
+            println!("Dated {} from the {}", date.date(), date.label());
+        }
+        Format::Json => print_json(&Location {
+            source: located.source,
+            target,
+            date: date.date(),
+            date_source: date.label(),
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct Location {
+    source: PathBuf,
+    target: PathBuf,
+    date: NaiveDate,
+    date_source: &'static str,
+}
+
+// the warnings and errors are printed as they are, RUST_LOG=error silences the
+// warnings and RUST_LOG=debug adds the traces of the sort.
+fn init_logger() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            // the lines of the files before it are printed first.
+            flush_output();
+            match record.level() {
+                Level::Debug | Level::Trace => writeln!(
+                    buf,
+                    "[{} {}] {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ),
+                _ => writeln!(buf, "{}", record.args()),
             }
-        };
+        })
+        .init();
+}
+
+// an extension and the target directory of its files, eg. mp4=/videos.
+fn parse_route(route: &str) -> Result<(String, PathBuf), String> {
+    match route.split_once('=') {
+        Some((extension, path)) if !extension.is_empty() && !path.is_empty() => {
+            Ok((extension.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("Expected EXT=PATH, eg. mp4=/videos, got {}", route)),
+    }
+}
+
+fn fail(err: anyhow::Error) -> ! {
+    flush_output();
+    eprintln!("{}", err);
+    std::process::exit(1);
+}
+
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!(
+                "{} while writing the summary as json - [{}]",
+                "Error".red(),
+                err
+            );
+            std::process::exit(1);
+        }
     }
-    (get_date_from_file(entry), exif_error)
-}
-
-fn get_date_from_file(entry: &DirEntry) -> Result<NaiveDate> {
-    let datetime: DateTime<Utc> = entry
-        .metadata()
-        .context("Failed to read file metadata")?
-        .modified()
-        .context("Failed to read file modified time")?
-        .into();
-    Ok(NaiveDate::from_ymd(
-        datetime.year(),
-        datetime.month(),
-        datetime.day(),
-    ))
-}
-
-fn get_date_from_exif(entry: &DirEntry) -> Result<NaiveDate> {
-    let file = File::open(entry.path()).context("Failed to open the file for reading exif")?;
-    let mut bufreader = BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let datetime = exifreader
-        .read_from_container(&mut bufreader)?
-        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
-        .context("No datetime in the exif data")?
-        .display_value()
-        .to_string();
-    let datetime = NaiveDate::parse_from_str(&datetime, "%Y-%m-%d %H:%M:%S")
-        .context("Failed to parse the exif datetime")?;
-
-    Ok(datetime)
-}
-
-fn exif_compatible_extension(entry: &DirEntry) -> bool {
-    entry.path().extension().map_or(false, |extension| {
-        EXIF_COMPATIBLE_EXTENSIONS
-            .iter()
-            .any(|&e| e == extension.to_ascii_lowercase())
-    })
 }
@@ -0,0 +1,124 @@
+use crate::date::{exif_compatible_extension, read_exif};
+use exif::{In, Tag};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+use walkdir::DirEntry;
+
+/// The folder of the files whose orientation is not known, eg. videos.
+pub static UNKNOWN_ORIENTATION: &str = "Unknown";
+
+static PNG_MAGIC: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Whether an image is wider or taller as it's shown.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+impl Orientation {
+    fn of(width: u32, height: u32) -> Self {
+        match width.cmp(&height) {
+            std::cmp::Ordering::Greater => Orientation::Landscape,
+            std::cmp::Ordering::Less => Orientation::Portrait,
+            std::cmp::Ordering::Equal => Orientation::Square,
+        }
+    }
+
+    pub fn folder(self) -> &'static str {
+        match self {
+            Orientation::Landscape => "Landscape",
+            Orientation::Portrait => "Portrait",
+            Orientation::Square => "Square",
+        }
+    }
+}
+
+/// The orientation of an image from the dimensions in its exif, or in the
+/// header of a jpeg or png without them, turned by its exif `Orientation` tag.
+pub fn get_orientation(entry: &DirEntry) -> Option<Orientation> {
+    if !exif_compatible_extension(entry) {
+        return None;
+    }
+    let exif = read_exif(entry.path()).ok();
+    let field = |tag| {
+        exif.as_ref()?
+            .get_field(tag, In::PRIMARY)?
+            .value
+            .get_uint(0)
+    };
+    let (width, height) = field(Tag::PixelXDimension)
+        .zip(field(Tag::PixelYDimension))
+        .or_else(|| field(Tag::ImageWidth).zip(field(Tag::ImageLength)))
+        .or_else(|| header_dimensions(entry.path()))?;
+    // the orientations 5 to 8 turn the image by a quarter, which swaps its sides.
+    let (width, height) = match field(Tag::Orientation) {
+        Some(5..=8) => (height, width),
+        _ => (width, height),
+    };
+    (width > 0 && height > 0).then(|| Orientation::of(width, height))
+}
+
+// the width and height in the header of a jpeg or a png.
+fn header_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic).ok()?;
+    if magic == PNG_MAGIC {
+        // the IHDR chunk comes first, its length and type followed by the sides.
+        let mut header = [0; 16];
+        file.read_exact(&mut header).ok()?;
+        let side = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().unwrap());
+        return Some((side(8), side(12)));
+    }
+    if magic[..2] != [0xff, 0xd8] {
+        return None;
+    }
+    // the segments are skipped by their length until the start of the frame.
+    file.seek(SeekFrom::Start(2)).ok()?;
+    loop {
+        let mut marker = [0; 4];
+        file.read_exact(&mut marker).ok()?;
+        if marker[0] != 0xff {
+            return None;
+        }
+        let len = u16::from_be_bytes([marker[2], marker[3]]);
+        // every SOFn marker, apart from the DHT, JPG and DAC ones among them.
+        if matches!(marker[1], 0xc0..=0xcf) && !matches!(marker[1], 0xc4 | 0xc8 | 0xcc) {
+            let mut frame = [0; 5];
+            file.read_exact(&mut frame).ok()?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]);
+            let width = u16::from_be_bytes([frame[3], frame[4]]);
+            return Some((u32::from(width), u32::from(height)));
+        }
+        file.seek(SeekFrom::Current(i64::from(len) - 2)).ok()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orientation_follows_the_longer_side() {
+        assert_eq!(Orientation::of(100, 68), Orientation::Landscape);
+        assert_eq!(Orientation::of(68, 100), Orientation::Portrait);
+        assert_eq!(Orientation::of(64, 64), Orientation::Square);
+    }
+
+    #[test]
+    fn reads_the_dimensions_from_the_jpeg_header() {
+        assert_eq!(
+            header_dimensions(Path::new("tests/data/jpeg_with_no_exif.jpg")),
+            Some((636, 227))
+        );
+        assert_eq!(
+            header_dimensions(Path::new("tests/data/non_image_file.txt")),
+            None
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use std::{fs, path::Path, str::FromStr};
+
+/// The user and group the sorted files and the folders created for them are
+/// given on Unix, as numeric ids like `1000:1000`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FromStr for Owner {
+    type Err = anyhow::Error;
+
+    fn from_str(owner: &str) -> Result<Self> {
+        let parsed = owner
+            .split_once(':')
+            .and_then(|(uid, gid)| Some((uid.parse().ok()?, gid.parse().ok()?)));
+        match parsed {
+            Some((uid, gid)) => Ok(Owner { uid, gid }),
+            None => anyhow::bail!(
+                "Invalid owner {}. Expected a user and a group id like 1000:1000",
+                owner
+            ),
+        }
+    }
+}
+
+// a file is created and given the owner before the run, so a run without the
+// privilege to change it fails before sorting anything rather than for every file.
+pub(crate) fn check_owner(target_dir: &Path, owner: Owner) -> Result<()> {
+    let probe = target_dir.join(".photosort-owner-check");
+    fs::write(&probe, "").context("Failed to write to the target")?;
+    let changed = set_owner(&probe, owner);
+    let _ = fs::remove_file(&probe);
+    changed.map_err(|err| {
+        anyhow::anyhow!(
+            "Failed to give the files at the target the owner {}:{} - [{}]. Changing the owner usually needs root.",
+            owner.uid,
+            owner.gid,
+            err
+        )
+    })
+}
+
+#[cfg(unix)]
+pub(crate) fn set_owner(path: &Path, owner: Owner) -> std::io::Result<()> {
+    std::os::unix::fs::chown(path, Some(owner.uid), Some(owner.gid))
+}
+
+// files have no such owner on Windows, so they keep the one they get.
+#[cfg(windows)]
+pub(crate) fn set_owner(_path: &Path, _owner: Owner) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_user_and_group_ids() {
+        assert_eq!(
+            "1000:100".parse::<Owner>().unwrap(),
+            Owner {
+                uid: 1000,
+                gid: 100
+            }
+        );
+        assert!("media".parse::<Owner>().is_err());
+        assert!("1000:".parse::<Owner>().is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use crate::sorter::{CollisionHook, TargetExists};
+use std::io::{BufRead, Write};
+
+/// A [`CollisionHook`] asking what to do with every name collision, by writing
+/// the question to the prompt, eg. stderr, and reading a line of the input as
+/// the answer, eg. stdin. `a` skips this and every later collision without
+/// asking again, while `q` or the end of the input stops the run.
+pub fn ask_on_collision<R, W>(mut input: R, mut prompt: W) -> CollisionHook
+where
+    R: BufRead + Send + Sync + 'static,
+    W: Write + Send + Sync + 'static,
+{
+    let mut all = None;
+    Box::new(move |source, target| {
+        if all.is_some() {
+            return all;
+        }
+        loop {
+            let _ = write!(
+                prompt,
+                "A different file is at {} with the same name as {}\n[s]kip / [o]verwrite / [r]ename / [a]ll-skip / [q]uit: ",
+                target.display(),
+                source.display()
+            );
+            let _ = prompt.flush();
+            let mut answer = String::new();
+            if input.read_line(&mut answer).unwrap_or(0) == 0 {
+                return None;
+            }
+            match answer.trim().to_lowercase().as_str() {
+                "s" | "skip" => return Some(TargetExists::Skip),
+                "o" | "overwrite" => return Some(TargetExists::Overwrite),
+                "r" | "rename" => return Some(TargetExists::Rename),
+                "a" | "all-skip" => {
+                    all = Some(TargetExists::Skip);
+                    return all;
+                }
+                "q" | "quit" => return None,
+                // anything else is asked again.
+                _ => {}
+            }
+        }
+    })
+}
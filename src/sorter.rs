@@ -0,0 +1,2803 @@
+use crate::archive::Archives;
+use crate::date::{
+    get_capture_time, get_file_date, name_patterns, DatePolicy, DateSource, DateSources,
+};
+use crate::group::{CameraGroup, GroupBy, GroupResolver};
+use crate::journal::{prune_empty_dirs, Journal};
+use crate::location::{find_place, get_gps_from_exif};
+use crate::owner::{check_owner, set_owner};
+use crate::state::{read_last_run, write_last_run, SortState};
+use crate::template::{
+    get_target_path, group_root, validate_name_affix, validate_rename, validate_template,
+    FileNaming, TemplateResolver, TEMPLATE_FILE_NAME,
+};
+use crate::{
+    files_identical, find_bursts, DiffCategory, DiffReport, FilterReason, PathRoots, SortOptions,
+    Summary,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ArgEnum;
+use colored::*;
+use filetime::FileTime;
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, error, log, warn, Level};
+use rayon::{prelude::*, ThreadPool};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs::{self, File},
+    io::{ErrorKind, IsTerminal, LineWriter, Write},
+    iter,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+use walkdir::{DirEntry, WalkDir};
+
+/// How a file gets to its target path.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    Copy,
+    Move,
+    Symlink,
+    Hardlink,
+}
+
+impl Transfer {
+    pub fn verb(self) -> &'static str {
+        match self {
+            Transfer::Copy => "Copied",
+            Transfer::Move => "Moved",
+            Transfer::Symlink => "Symlinked",
+            Transfer::Hardlink => "Hardlinked",
+        }
+    }
+
+    pub fn planned_verb(self) -> &'static str {
+        match self {
+            Transfer::Copy => "Would copy",
+            Transfer::Move => "Would move",
+            Transfer::Symlink => "Would symlink",
+            Transfer::Hardlink => "Would hardlink",
+        }
+    }
+
+    pub(crate) fn noun(self) -> &'static str {
+        match self {
+            Transfer::Copy => "copy",
+            Transfer::Move => "move",
+            Transfer::Symlink => "symlink",
+            Transfer::Hardlink => "hard link",
+        }
+    }
+
+    fn gerund(self) -> &'static str {
+        match self {
+            Transfer::Copy => "copying",
+            Transfer::Move => "moving",
+            Transfer::Symlink => "symlinking",
+            Transfer::Hardlink => "hardlinking",
+        }
+    }
+
+    fn outcome(self) -> Outcome {
+        match self {
+            Transfer::Copy => Outcome::Copied,
+            Transfer::Move => Outcome::Moved,
+            Transfer::Symlink => Outcome::Symlinked,
+            Transfer::Hardlink => Outcome::Hardlinked,
+        }
+    }
+
+    // links take up no space at the target.
+    fn budgeted_len(self, len: u64) -> u64 {
+        match self {
+            Transfer::Copy | Transfer::Move => len,
+            Transfer::Symlink | Transfer::Hardlink => 0,
+        }
+    }
+
+    fn mark(self, summary: &mut Summary, source: &Path, bytes: u64) {
+        summary.mark_extension(source);
+        match self {
+            Transfer::Copy => summary.mark_copied(bytes),
+            Transfer::Move => summary.mark_moved(bytes),
+            Transfer::Symlink => summary.mark_symlinked(),
+            Transfer::Hardlink => summary.mark_hardlinked(),
+        }
+    }
+}
+
+/// What to do with a file when a file with the same name is at the target. A
+/// file of the same size is taken as the same file and skipped, unless overwritten.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum TargetExists {
+    // leave a different file for the user to sort out.
+    Skip,
+    // replace the file at the target, whatever its size.
+    Overwrite,
+    // sort a different file under a numbered name.
+    Rename,
+    // fail the file when a different file holds its name.
+    Error,
+}
+
+/// The order the files of the source are sorted in. It decides which of two
+/// different files with the same name is sorted first, eg. which one keeps the
+/// name and which one gets a numbered name.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum SortOrder {
+    // the path of the file.
+    Name,
+    // the modified time of the file, oldest first.
+    Date,
+    // the size of the file, smallest first.
+    Size,
+}
+
+/// Reported to the [`SortOptions::on_event`] hook as the run goes. Every file
+/// is reported exactly once, after the `Scanned` event.
+#[derive(Clone, Debug)]
+pub enum SortEvent {
+    /// The source was walked and this many files are about to be sorted.
+    Scanned { total: usize },
+    /// The file was copied, moved or linked to the target. On a dry run or a
+    /// diff it would be.
+    Copied(SortedFile),
+    /// The file is already present at the target.
+    Skipped(SortedFile),
+    /// A different file with the same name, or a file with the same contents, is
+    /// present at the target.
+    Duplicate(SortedFile),
+    /// The file was left out, eg. by the date range or the byte budget.
+    Filtered(SortedFile),
+    /// The file failed to sort.
+    Error(SortedFile),
+    /// The file at the target was removed by a mirror since its source is gone.
+    /// On a dry run it would be.
+    Removed(PathBuf),
+}
+
+/// A source file reported by a [`SortEvent`].
+#[derive(Clone, Debug)]
+pub struct SortedFile {
+    pub source: PathBuf,
+    /// Left out when the file was left before its target path was known.
+    pub target: Option<PathBuf>,
+    pub bytes: u64,
+    /// Left out when the date of the file could not be read.
+    pub date: Option<DateSource>,
+}
+
+/// Called with every [`SortEvent`] of a run. Files are sorted in parallel, so
+/// the hook is called from the worker threads, one call at a time.
+pub type EventHook = Box<dyn FnMut(SortEvent) + Send + Sync>;
+
+/// Called with the source and the target of every name collision with a
+/// different file, to choose what to do with it in place of
+/// [`SortOptions::target_exists`]. Returning `None` stops the run, leaving the
+/// remaining files for a later one. The calls are made one at a time.
+pub type CollisionHook = Box<dyn FnMut(&Path, &Path) -> Option<TargetExists> + Send + Sync>;
+
+// a copy in progress is written next to its target with this suffix added. It
+// names photosort so that the cleanup never touches other partial downloads.
+static PART_SUFFIX: &str = ".photosort.part";
+
+// gitignore style patterns for the paths never sorted, kept at the root of the source.
+pub(crate) static IGNORE_FILE_NAME: &str = ".photosortignore";
+
+/// Sorts the files of the source into the target, or compares them against the
+/// target when given a diff report to fill in.
+pub(crate) fn run(
+    options: &SortOptions,
+    on_event: Option<EventHook>,
+    on_collision: Option<CollisionHook>,
+    mut diff: Option<&mut DiffReport>,
+) -> Result<Summary> {
+    let (source_dir, source_pattern) = split_source(&options.source_dir)?;
+    if !source_dir.is_dir() {
+        anyhow::bail!("The source path is invalid. Please make sure it exists and is a directory.");
+    }
+    if !options.target_dir.is_dir() {
+        anyhow::bail!("The target path is invalid. Please make sure it exists and is a directory.");
+    }
+    for (extension, target_dir) in &options.routes {
+        if !target_dir.is_dir() {
+            anyhow::bail!(
+                "The target path {} for the {} files is invalid. Please make sure it exists and is a directory.",
+                target_dir.display(),
+                extension
+            );
+        }
+    }
+    if let (Some(min_size), Some(max_size)) = (options.min_size, options.max_size) {
+        if min_size > max_size {
+            anyhow::bail!("The size range is empty. The --min-size is larger than the --max-size.");
+        }
+    }
+    // the source directory itself is at depth 0, and holds no files to sort.
+    if options.max_depth == Some(0) {
+        anyhow::bail!(
+            "The --max-depth must be at least 1, which sorts the files directly in the source."
+        );
+    }
+    if let (Some(min_depth), Some(max_depth)) = (options.min_depth, options.max_depth) {
+        if min_depth > max_depth {
+            anyhow::bail!(
+                "The depth range is empty. The --min-depth is larger than the --max-depth."
+            );
+        }
+    }
+    if let (Some(after), Some(before)) = (options.after, options.before) {
+        if after > before {
+            anyhow::bail!(
+                "The date range is empty. The --after date is later than the --before date."
+            );
+        }
+    }
+    let same_dir = absolute_path(&source_dir) == absolute_path(&options.target_dir);
+    match (same_dir, options.in_place) {
+        (true, false) => anyhow::bail!(
+            "The source and the target are the same directory. Pass --in-place to reorganize it."
+        ),
+        (false, true) => {
+            anyhow::bail!("--in-place needs the source and the target to be the same directory.")
+        }
+        _ => {}
+    }
+    // a file can only be in one place within the directory.
+    if options.in_place
+        && (options.transfer != Transfer::Move
+            || options.archive
+            || options.mirror
+            || !options.routes.is_empty())
+    {
+        anyhow::bail!("--in-place moves the files, so it can't be combined with --symlink, --hardlink, --archive, --mirror or --route.");
+    }
+    // a file left out of the run would look like one whose source is gone.
+    if options.mirror {
+        let filtered = !options.include_ext.is_empty()
+            || !options.exclude_ext.is_empty()
+            || options.min_size.is_some()
+            || options.max_size.is_some()
+            || options.after.is_some()
+            || options.before.is_some()
+            || options.max_bytes.is_some()
+            || options.min_depth.is_some()
+            || options.max_depth.is_some()
+            || options.skip_empty
+            || options.skip_hidden
+            || options.state.is_some()
+            || options.since_last_run.is_some()
+            || source_pattern.is_some();
+        if filtered || options.transfer == Transfer::Move || options.dedupe_by_hash {
+            anyhow::bail!("--mirror can't be combined with --move, --dedupe-by-hash, a source pattern or the filters, which leave files at the target without a source in the run.");
+        }
+        // only the main target is cleaned up.
+        if !options.routes.is_empty() {
+            anyhow::bail!("--mirror can't be combined with --route.");
+        }
+    }
+    // an archive is only ever added to, with copies of the files.
+    if options.archive
+        && (options.transfer != Transfer::Copy
+            || options.mirror
+            || options.journal.is_some()
+            || options.dedupe_by_hash
+            || options.stable_by_hash
+            || options.no_clobber_across_dates
+            || options.verify_content
+            || options.preserve_perms
+            || options.preserve_xattrs
+            || options.checksum_manifest.is_some()
+            || options.target_exists == TargetExists::Overwrite)
+    {
+        anyhow::bail!("--archive can't be combined with --move, --symlink, --hardlink, --mirror, --journal, --checksum-manifest, --dedupe-by-hash, --stable-by-hash, --no-clobber-across-dates, --verify-content, --preserve-perms, --preserve-xattrs or --target-exists overwrite.");
+    }
+    // a link can't replace a file in place.
+    if options.target_exists == TargetExists::Overwrite
+        && matches!(options.transfer, Transfer::Symlink | Transfer::Hardlink)
+    {
+        anyhow::bail!("--target-exists overwrite can't be combined with --symlink or --hardlink.");
+    }
+    // the hook may choose to overwrite, which neither of those can.
+    if on_collision.is_some()
+        && (options.archive || matches!(options.transfer, Transfer::Symlink | Transfer::Hardlink))
+    {
+        anyhow::bail!("--interactive can't be combined with --archive, --symlink or --hardlink.");
+    }
+    // an update decides by the modified time alone, and replaces the file in place.
+    if options.update
+        && (options.target_exists != TargetExists::Skip
+            || on_collision.is_some()
+            || options.archive
+            || matches!(options.transfer, Transfer::Symlink | Transfer::Hardlink))
+    {
+        anyhow::bail!("--update can't be combined with --target-exists, --interactive, --archive, --symlink or --hardlink.");
+    }
+    // the place either replaces the layout or comes before it.
+    if options.by_location && options.group_by.contains(&GroupBy::Location) {
+        anyhow::bail!("--by-location can't be combined with --group-by location.");
+    }
+    // a link would pass the owner on to the file it points to.
+    if options.owner.is_some()
+        && (options.archive || matches!(options.transfer, Transfer::Symlink | Transfer::Hardlink))
+    {
+        anyhow::bail!("--owner can't be combined with --archive, --symlink or --hardlink.");
+    }
+    // the other policies pick the modified time over a capture date.
+    if options.exif_only && options.date_policy != DatePolicy::ExifFirst {
+        anyhow::bail!("--exif-only can't be combined with a --date-policy other than exif-first.");
+    }
+    if let Some(unknown_dir) = &options.unknown_dir {
+        if !options.exif_only {
+            anyhow::bail!("--unknown-dir needs --exif-only.");
+        }
+        let within_target = unknown_dir.components().next().is_some()
+            && unknown_dir
+                .components()
+                .all(|component| matches!(component, std::path::Component::Normal(_)));
+        if !within_target {
+            anyhow::bail!("The --unknown-dir must be a relative path without '..' components.");
+        }
+    }
+    if options.hash_threads.is_some()
+        && !options.dedupe_by_hash
+        && !options.stable_by_hash
+        && !options.no_clobber_across_dates
+    {
+        anyhow::bail!(
+            "--hash-threads needs --dedupe-by-hash, --stable-by-hash or --no-clobber-across-dates."
+        );
+    }
+    if let Err(err) = validate_template(&options.layout) {
+        anyhow::bail!("The layout is invalid - [{}]", err);
+    }
+    let name_patterns = match options.date_from_name {
+        true => name_patterns(&options.name_patterns)
+            .map_err(|err| anyhow::anyhow!("The --name-pattern is invalid - [{}]", err))?,
+        false => Vec::new(),
+    };
+    for (flag, affix) in [
+        ("--name-prefix", &options.name_prefix),
+        ("--name-suffix", &options.name_suffix),
+    ] {
+        if let Some(Err(err)) = affix.as_deref().map(validate_name_affix) {
+            anyhow::bail!("The {} is invalid - [{}]", flag, err);
+        }
+    }
+    if let Some(rename) = &options.rename {
+        if options.name_prefix.is_some() || options.name_suffix.is_some() {
+            anyhow::bail!("--rename can't be combined with --name-prefix or --name-suffix.");
+        }
+        if let Err(err) = validate_rename(rename) {
+            anyhow::bail!("The --rename is invalid - [{}]", err);
+        }
+    }
+
+    let mapping = match &options.mapping_csv {
+        Some(path) => Some(create_mapping_csv(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to create the mapping file {} - [{}]",
+                path.display(),
+                err
+            )
+        })?),
+        None => None,
+    };
+    let manifest = match &options.manifest {
+        Some(path) => Some(create_manifest(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to create the manifest file {} - [{}]",
+                path.display(),
+                err
+            )
+        })?),
+        None => None,
+    };
+    let checksums = match &options.checksum_manifest {
+        Some(path) => Some(open_checksums(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to open the checksum manifest {} - [{}]",
+                path.display(),
+                err
+            )
+        })?),
+        None => None,
+    };
+    let journal = match &options.journal {
+        Some(path) => Some(Journal::open(path, &options.target_dir).map_err(|err| {
+            anyhow::anyhow!("Failed to open the journal {} - [{}]", path.display(), err)
+        })?),
+        None => None,
+    };
+    // a dry run or a diff reads the state without adding to it.
+    let state = match &options.state {
+        Some(path) => Some(
+            SortState::open(path, !options.dry_run && diff.is_none()).map_err(|err| {
+                anyhow::anyhow!(
+                    "Failed to open the state file {} - [{:#}]",
+                    path.display(),
+                    err
+                )
+            })?,
+        ),
+        None => None,
+    };
+    // the files modified before the last run started were sorted by it.
+    let last_run = match &options.since_last_run {
+        Some(path) => read_last_run(path)
+            .map_err(|err| anyhow::anyhow!("Failed to read {} - [{:#}]", path.display(), err))?,
+        None => None,
+    };
+    let started = Utc::now();
+    if let Some(owner) = options.owner.filter(|_| !options.dry_run && diff.is_none()) {
+        check_owner(&options.target_dir, owner)?;
+    }
+
+    // keep photosort from sorting its own output files.
+    let mut own_files = OwnFiles::new();
+    for path in options
+        .own_files
+        .iter()
+        .chain(&options.mapping_csv)
+        .chain(&options.manifest)
+        .chain(&options.checksum_manifest)
+        .chain(&options.journal)
+        .chain(&options.state)
+        .chain(&options.since_last_run)
+    {
+        own_files.add(path);
+    }
+
+    let now = Instant::now();
+
+    let mut summary = Summary::init();
+
+    // ignored directories are not walked at all.
+    let ignore = load_ignore_file(&source_dir);
+    // a target within the source is not walked, so the sorted files are not
+    // sorted again by the next run.
+    let nested_targets = nested_targets(
+        &source_dir,
+        iter::once(&options.target_dir).chain(options.routes.iter().map(|(_, dir)| dir)),
+    );
+    // a directory reached again through a symlink is walked only once, which
+    // also keeps a symlink cycle from being walked forever.
+    let mut visited = HashSet::new();
+    let mut loop_skipped_count = 0;
+    let mut ignored_count = 0;
+    let mut hidden_count = 0;
+    let mut pruned_bytes = 0;
+    // a pattern reaches only so many folders down, unless it has a `**`.
+    let max_depth = [
+        options.max_depth,
+        source_pattern.as_ref().and_then(|pattern| pattern.depth),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let mut matched_count = 0;
+    let walker = WalkDir::new(&source_dir)
+        .follow_links(options.follow_symlinks)
+        .max_depth(max_depth.unwrap_or(usize::MAX))
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() > 0 && nested_targets.iter().any(|target| entry.path() == target) {
+                return false;
+            }
+            if ignore
+                .matched(entry.path(), entry.file_type().is_dir())
+                .is_ignore()
+            {
+                // the files of an ignored directory are counted, not sorted.
+                let (count, bytes) = match entry.file_type().is_dir() {
+                    true => count_files(entry, options.min_depth, options.max_depth),
+                    false => (1, entry.metadata().map_or(0, |metadata| metadata.len())),
+                };
+                ignored_count += count;
+                pruned_bytes += bytes;
+                return false;
+            }
+            // hidden directories, eg. .thumbnails, are not walked at all. The config
+            // files are hidden too, but they are not counted as left out.
+            if options.skip_hidden
+                && entry.depth() > 0
+                && entry.file_name().to_string_lossy().starts_with('.')
+            {
+                if !is_config_file(entry, &source_dir) {
+                    let (count, bytes) = match entry.file_type().is_dir() {
+                        true => count_files(entry, options.min_depth, options.max_depth),
+                        false => (1, entry.metadata().map_or(0, |metadata| metadata.len())),
+                    };
+                    hidden_count += count;
+                    pruned_bytes += bytes;
+                }
+                return false;
+            }
+            if options.follow_symlinks && entry.file_type().is_dir() {
+                if let Ok(path) = fs::canonicalize(entry.path()) {
+                    if !visited.insert(path) {
+                        loop_skipped_count += 1;
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+
+    let mut entries = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // a symlink back to one of its own parent directories.
+            Err(err) if err.loop_ancestor().is_some() => {
+                summary.mark_loop_skipped();
+                continue;
+            }
+            Err(err) => {
+                error!("{} while scanning - [{}]", "Error".red(), err);
+                summary.mark_scan_error();
+                continue;
+            }
+        };
+
+        // walkdir also returns directory entries, and the links to directories
+        // when not following them. Skip them.
+        if entry.file_type().is_dir() || (entry.path_is_symlink() && entry.path().is_dir()) {
+            continue;
+        }
+
+        if is_config_file(&entry, &source_dir) {
+            continue;
+        }
+
+        // the files which don't match the source pattern are not part of the source.
+        if let Some(pattern) = &source_pattern {
+            let path = entry
+                .path()
+                .strip_prefix(&source_dir)
+                .unwrap_or(entry.path());
+            if !pattern.matcher.is_match(path) {
+                continue;
+            }
+            matched_count += 1;
+        }
+
+        if own_files.contains(entry.path()) {
+            continue;
+        }
+
+        // a file whose size can't be read is sorted, or reported, all the same.
+        if let Ok(metadata) = entry.metadata() {
+            summary.mark_scanned(metadata.len());
+        }
+
+        // an interrupted run is resumed after the last file it sorted.
+        if state
+            .as_ref()
+            .is_some_and(|state| state.contains(entry.path()))
+        {
+            mark_walk_filtered(&mut summary, diff.as_deref_mut(), FilterReason::State);
+            continue;
+        }
+
+        if let Some(last_run) = last_run {
+            let modified = entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| metadata.modified().ok());
+            if modified.is_some_and(|modified| DateTime::<Utc>::from(modified) < last_run) {
+                mark_walk_filtered(
+                    &mut summary,
+                    diff.as_deref_mut(),
+                    FilterReason::SinceLastRun,
+                );
+                continue;
+            }
+        }
+
+        // the minimum depth is checked here rather than by walkdir, which would
+        // keep the directories above it from the ignore file.
+        if options
+            .min_depth
+            .is_some_and(|min_depth| entry.depth() < min_depth)
+        {
+            mark_walk_filtered(&mut summary, diff.as_deref_mut(), FilterReason::Depth);
+            continue;
+        }
+
+        if !should_process(&entry, &options.include_ext, &options.exclude_ext) {
+            mark_walk_filtered(&mut summary, diff.as_deref_mut(), FilterReason::Extension);
+            continue;
+        }
+
+        entries.push(entry);
+    }
+    if source_pattern.is_some() && matched_count == 0 {
+        anyhow::bail!(
+            "The source pattern {} matches no files.",
+            options.source_dir.display()
+        );
+    }
+    summary.loop_skipped_count += loop_skipped_count;
+    for _ in 0..ignored_count {
+        mark_walk_filtered(&mut summary, diff.as_deref_mut(), FilterReason::IgnoreFile);
+    }
+    for _ in 0..hidden_count {
+        mark_walk_filtered(&mut summary, diff.as_deref_mut(), FilterReason::Hidden);
+    }
+    summary.mark_scanned(pruned_bytes);
+
+    // the files are sorted in parallel, but the files sharing a name are sorted
+    // one after the other in the sort order, so their numbered names and the
+    // manifest come out the same on every run.
+    let groups = group_by_name(sort_entries(entries, options.sort_order));
+    let total = groups.iter().map(Vec::len).sum();
+
+    // hashing keeps the cores busy on a pool of its own, while fewer workers
+    // copy so they don't thrash a slow disk.
+    let hashing =
+        options.dedupe_by_hash || options.stable_by_hash || options.no_clobber_across_dates;
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    let jobs = options
+        .jobs
+        .unwrap_or(if hashing { HASHING_JOBS } else { cpus });
+    let pool = start_pool(jobs)?;
+    let hash_pool = match hashing {
+        true => Some(start_pool(options.hash_threads.unwrap_or(cpus))?),
+        false => None,
+    };
+    // an interrupted copy from an earlier run is started over.
+    if !options.dry_run && diff.is_none() {
+        remove_partial_copies(&options.target_dir);
+    }
+    let hashes = match (&hash_pool, options.dedupe_by_hash) {
+        (Some(hash_pool), true) => {
+            debug!("Hashing the files at {}", options.target_dir.display());
+            let hashes = hash_pool
+                .install(|| hash_target(&options.target_dir, &own_files))
+                .into_iter()
+                .map(|(hash, _)| hash)
+                .collect();
+            Some(hashes)
+        }
+        _ => None,
+    };
+    // a diff leaves the target alone.
+    let mirror = options.mirror && diff.is_none();
+    let prune = options.in_place && !options.dry_run && diff.is_none();
+    let asking = on_collision.is_some();
+    let context = SortContext {
+        options,
+        templates: Mutex::new(TemplateResolver::new(&source_dir, &options.layout)),
+        name_patterns,
+        planned: Mutex::new(HashMap::new()),
+        target_locks: TargetLocks::new(),
+        budget: Budget::new(options.max_bytes),
+        mapping: Mutex::new(mapping),
+        journal: Mutex::new(journal),
+        state: Mutex::new(state),
+        manifest: Mutex::new(manifest),
+        manifest_rows: Mutex::new(Vec::new()),
+        checksums: Mutex::new(checksums),
+        archives: options.archive.then(Archives::new),
+        diff: diff.map(Mutex::new),
+        hashes: hashes.map(Mutex::new),
+        hash_pool,
+        target_index: OnceLock::new(),
+        own_files: &own_files,
+        on_event: on_event.map(Mutex::new),
+        on_collision: on_collision.map(Mutex::new),
+        stopped: AtomicBool::new(false),
+        progress: if options.progress {
+            Progress::start(total)
+        } else {
+            None
+        },
+        captured: Mutex::new(Vec::new()),
+        kept: mirror.then(|| Mutex::new(HashSet::new())),
+        emptied: prune.then(|| Mutex::new(Vec::new())),
+        // any collision may be overwritten when the hook is asked about it.
+        sorted: (options.target_exists == TargetExists::Overwrite || asking || options.update)
+            .then(|| Mutex::new(HashSet::new())),
+    };
+    context.emit(SortEvent::Scanned { total });
+    let sorted = pool.install(|| {
+        groups
+            .into_par_iter()
+            .fold(Summary::init, |mut summary, group| {
+                for (index, entry) in group {
+                    sort_file(&context, index, entry, &mut summary);
+                }
+                summary
+            })
+            .reduce(Summary::init, Summary::merge)
+    });
+    summary = summary.merge(sorted);
+    if let Some(progress) = &context.progress {
+        progress.bar.finish();
+    }
+    if context.budget.is_reached() {
+        summary.mark_budget_reached();
+    }
+    if let Some(kept) = &context.kept {
+        if summary.error_count > 0 || summary.dir_error_count > 0 || summary.scan_error_count > 0 {
+            warn!(
+                "{} Not removing anything from the target since some files failed to sort.",
+                "Warning.".yellow()
+            );
+        } else {
+            remove_orphans(&context, &kept.lock().unwrap(), &own_files, &mut summary);
+        }
+    }
+    // the folders are pruned once every file has moved, so a worker never
+    // removes the folder another one is moving a file into.
+    if let Some(emptied) = &context.emptied {
+        for path in emptied.lock().unwrap().iter() {
+            prune_empty_dirs(path, &source_dir);
+        }
+    }
+    if let Some(window) = options.burst_window {
+        summary.bursts = find_bursts(&context.captured.into_inner().unwrap(), window);
+    }
+
+    if let Some(mut writer) = context.mapping.into_inner().unwrap() {
+        if let Err(err) = writer.flush() {
+            error!(
+                "{} while writing the mapping file - [{}]",
+                "Error".red(),
+                err
+            );
+        }
+    }
+    if let Some(archives) = context.archives {
+        for (path, err) in archives.finish() {
+            error!(
+                "{} while writing the archive {} - [{}]",
+                "Error".red(),
+                path.display(),
+                err
+            );
+        }
+    }
+    if let Some(mut writer) = context.manifest.into_inner().unwrap() {
+        // the rows follow the sort order, not the order the workers finished in.
+        let mut rows = context.manifest_rows.into_inner().unwrap();
+        rows.sort_by_key(|(index, _, _)| *index);
+        let roots = options.path_roots();
+        for (_, source_path, sorted) in &rows {
+            if let Err(err) = write_manifest(&mut writer, source_path, sorted, &roots) {
+                error!(
+                    "{} while writing the manifest for {} - [{}]",
+                    "Error".red(),
+                    source_path.display(),
+                    err
+                );
+            }
+        }
+        if let Err(err) = writer.flush() {
+            error!(
+                "{} while writing the manifest file - [{}]",
+                "Error".red(),
+                err
+            );
+        }
+    }
+    // the state is only needed until every file is sorted.
+    let finished = summary.error_count == 0
+        && summary.dir_error_count == 0
+        && summary.scan_error_count == 0
+        && !summary.budget_reached
+        && !context.stopped.load(Ordering::Relaxed);
+    drop(context.state.into_inner().unwrap());
+    if let Some(path) = &options.state {
+        if options.remove_state && finished && !options.dry_run && context.diff.is_none() {
+            if let Err(err) = fs::remove_file(path) {
+                error!(
+                    "{} while removing the state file {} - [{}]",
+                    "Error".red(),
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+    // a run which left files behind is picked up from the same time again.
+    if let Some(path) = &options.since_last_run {
+        if finished && !options.dry_run && context.diff.is_none() {
+            if let Err(err) = write_last_run(path, started) {
+                error!(
+                    "{} while recording the run in {} - [{:#}]",
+                    "Error".red(),
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+    summary.set_duration(now.elapsed());
+
+    Ok(summary)
+}
+
+// removes the files at the target which no source file was sorted to, along
+// with the directories left empty.
+fn remove_orphans(
+    context: &SortContext,
+    kept: &HashSet<PathBuf>,
+    own_files: &OwnFiles,
+    summary: &mut Summary,
+) {
+    let target_dir = &context.options.target_dir;
+    let orphans: Vec<PathBuf> = WalkDir::new(target_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir())
+        .map(DirEntry::into_path)
+        .filter(|path| !kept.contains(path) && !own_files.contains(path))
+        .collect();
+    for path in orphans {
+        if !context.options.dry_run {
+            if let Err(err) = fs::remove_file(&path) {
+                context.log(
+                    Level::Error,
+                    format!(
+                        "{} while removing {} from the target - [{}]",
+                        "Error".red(),
+                        path.display(),
+                        err
+                    ),
+                );
+                summary.mark_error(path);
+                continue;
+            }
+            prune_empty_dirs(&path, target_dir);
+        }
+        summary.mark_removed();
+        context.emit(SortEvent::Removed(path));
+    }
+}
+
+// the target directories within the source, as the walk of the source reaches them.
+fn nested_targets<'a>(
+    source_dir: &Path,
+    targets: impl Iterator<Item = &'a PathBuf>,
+) -> Vec<PathBuf> {
+    let Some(source) = absolute_path(source_dir) else {
+        return Vec::new();
+    };
+    targets
+        .filter_map(|target| {
+            let target = absolute_path(target)?;
+            let relative = target.strip_prefix(&source).ok()?;
+            (!relative.as_os_str().is_empty()).then(|| source_dir.join(relative))
+        })
+        .collect()
+}
+
+// template overrides and ignore files are configuration, not photos.
+fn is_config_file(entry: &DirEntry, source_dir: &Path) -> bool {
+    entry.file_name() == TEMPLATE_FILE_NAME || entry.path() == source_dir.join(IGNORE_FILE_NAME)
+}
+
+/// The files of a source given as a glob pattern, eg. `~/Downloads/*.jpg`.
+struct SourcePattern {
+    // matches the paths relative to the directory the pattern starts from.
+    matcher: GlobMatcher,
+    // the number of folders the pattern reaches down, unless it has a `**`.
+    depth: Option<usize>,
+}
+
+// a source with a `*` or `?` in it is a pattern, which is split into the
+// directory before the first wildcard and the pattern for the files below it.
+fn split_source(source: &Path) -> Result<(PathBuf, Option<SourcePattern>)> {
+    let parts: Vec<_> = source.components().collect();
+    let is_wildcard = |part: &std::path::Component| {
+        let part = part.as_os_str().to_string_lossy();
+        part.contains('*') || part.contains('?')
+    };
+    let Some(start) = parts.iter().position(is_wildcard) else {
+        return Ok((source.to_path_buf(), None));
+    };
+    let dir: PathBuf = parts[..start].iter().collect();
+    let dir = match dir.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => dir,
+    };
+    let globs: Vec<String> = parts[start..]
+        .iter()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let pattern = globs.join("/");
+    let matcher = GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| anyhow::anyhow!("The source pattern is invalid - [{}]", err))?
+        .compile_matcher();
+    let depth = (!pattern.contains("**")).then_some(globs.len());
+    Ok((dir, Some(SourcePattern { matcher, depth })))
+}
+
+// the number and total size of the files of the directory within the depth
+// limits of the source walk.
+fn count_files(dir: &DirEntry, min_depth: Option<usize>, max_depth: Option<usize>) -> (u32, u64) {
+    let depth = dir.depth();
+    WalkDir::new(dir.path())
+        .min_depth(min_depth.map_or(0, |min_depth| min_depth.saturating_sub(depth)))
+        .max_depth(max_depth.map_or(usize::MAX, |max_depth| max_depth - depth))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir())
+        .fold((0, 0), |(count, bytes), entry| {
+            let len = entry.metadata().map_or(0, |metadata| metadata.len());
+            (count + 1, bytes + len)
+        })
+}
+
+// a file left out by the walk is filtered in a diff too, so the categories of
+// the diff add up to every file walked.
+fn mark_walk_filtered(summary: &mut Summary, diff: Option<&mut DiffReport>, reason: FilterReason) {
+    match diff {
+        Some(diff) => diff.mark(DiffCategory::Filtered, 0),
+        None => summary.mark_filtered(reason),
+    }
+}
+
+// a broken ignore file is reported and the patterns read before the error still apply.
+pub(crate) fn load_ignore_file(source_dir: &Path) -> Gitignore {
+    let path = source_dir.join(IGNORE_FILE_NAME);
+    let mut builder = GitignoreBuilder::new(source_dir);
+    if path.is_file() {
+        if let Some(err) = builder.add(&path) {
+            warn!(
+                "{} The ignore file {} is invalid - [{}]",
+                "Warning.".yellow(),
+                path.display(),
+                err
+            );
+        }
+    }
+    builder.build().unwrap_or_else(|err| {
+        warn!(
+            "{} The ignore file {} is invalid - [{}]",
+            "Warning.".yellow(),
+            path.display(),
+            err
+        );
+        Gitignore::empty()
+    })
+}
+
+/// The state shared by the workers sorting the files.
+struct SortContext<'a> {
+    options: &'a SortOptions,
+    templates: Mutex<TemplateResolver>,
+    // the patterns for the date in a file name, empty unless --date-from-name.
+    name_patterns: Vec<Regex>,
+    // the files planned by a dry run, which are not at the target yet.
+    planned: Mutex<HashMap<PathBuf, u64>>,
+    target_locks: TargetLocks,
+    budget: Budget,
+    mapping: Mutex<Option<csv::Writer<File>>>,
+    journal: Mutex<Option<Journal>>,
+    state: Mutex<Option<SortState>>,
+    manifest: Mutex<Option<csv::Writer<File>>>,
+    // the manifest rows by the position of their file in the sort order.
+    manifest_rows: Mutex<Vec<(usize, PathBuf, Sorted)>>,
+    // the sha256sum lines of the sorted files, with --checksum-manifest.
+    checksums: Mutex<Option<LineWriter<File>>>,
+    // the zip archive of every day, with --archive.
+    archives: Option<Archives>,
+    diff: Option<Mutex<&'a mut DiffReport>>,
+    // the hashes of the files at the target, and of those sorted so far.
+    hashes: Option<Mutex<HashSet<String>>>,
+    // the files at the target by their hash for --stable-by-hash, built when
+    // the first file would be copied.
+    target_index: OnceLock<HashMap<String, Vec<PathBuf>>>,
+    // the workers hashing the files, apart from those copying them.
+    hash_pool: Option<ThreadPool>,
+    own_files: &'a OwnFiles,
+    on_event: Option<Mutex<EventHook>>,
+    on_collision: Option<Mutex<CollisionHook>>,
+    // set once the collision hook stops the run.
+    stopped: AtomicBool,
+    progress: Option<Progress>,
+    // the targets and capture times of the sorted photos, when reporting bursts.
+    captured: Mutex<Vec<(PathBuf, DateTime<Utc>)>>,
+    // the targets of every source file, which a mirror keeps.
+    kept: Option<Mutex<HashSet<PathBuf>>>,
+    // the moved source files, whose folders an in place run prunes.
+    emptied: Option<Mutex<Vec<PathBuf>>>,
+    // the targets written by this run, which an overwrite doesn't replace.
+    sorted: Option<Mutex<HashSet<PathBuf>>>,
+}
+
+impl SortContext<'_> {
+    // the files planned by a dry run count too.
+    fn sorted_this_run(&self, target_path: &Path) -> bool {
+        self.planned.lock().unwrap().contains_key(target_path)
+            || self
+                .sorted
+                .as_ref()
+                .is_some_and(|sorted| sorted.lock().unwrap().contains(target_path))
+    }
+
+    fn target_index(&self) -> &HashMap<String, Vec<PathBuf>> {
+        self.target_index.get_or_init(|| {
+            debug!("Hashing the files at {}", self.options.target_dir.display());
+            let mut index: HashMap<String, Vec<PathBuf>> = HashMap::new();
+            for (hash, path) in
+                self.on_hash_pool(|| hash_target(&self.options.target_dir, self.own_files))
+            {
+                index.entry(hash).or_default().push(path);
+            }
+            index
+        })
+    }
+
+    fn hash_file(&self, path: &Path) -> Result<String> {
+        self.on_hash_pool(|| crate::file_hash(path))
+    }
+
+    // the calling worker waits on a thread of its own, since a rayon worker
+    // waiting on another pool picks up more files meanwhile, which may need
+    // the target it holds the lock of.
+    fn on_hash_pool<T: Send>(&self, hash: impl FnOnce() -> T + Send) -> T {
+        match &self.hash_pool {
+            Some(hash_pool) => {
+                thread::scope(|scope| scope.spawn(|| hash_pool.install(hash)).join().unwrap())
+            }
+            None => hash(),
+        }
+    }
+
+    // the hook prints above the progress bar so it doesn't tear it.
+    fn emit(&self, event: SortEvent) {
+        let Some(on_event) = &self.on_event else {
+            return;
+        };
+        let mut on_event = on_event.lock().unwrap();
+        match &self.progress {
+            Some(progress) => progress.bar.suspend(|| on_event(event)),
+            None => on_event(event),
+        }
+    }
+
+    // the hook may ask on the terminal, so the progress bar is hidden meanwhile.
+    // None when there's no hook, or once it stopped the run.
+    fn resolve_collision(&self, source: &Path, target: &Path) -> Option<Option<TargetExists>> {
+        let on_collision = self.on_collision.as_ref()?;
+        let mut on_collision = on_collision.lock().unwrap();
+        // another worker may have stopped the run while this one waited.
+        if self.stopped.load(Ordering::Relaxed) {
+            return Some(None);
+        }
+        let choice = match &self.progress {
+            Some(progress) => progress.bar.suspend(|| on_collision(source, target)),
+            None => on_collision(source, target),
+        };
+        if choice.is_none() {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        Some(choice)
+    }
+
+    // the log lines are written with the progress bar hidden so they don't tear it.
+    fn log(&self, level: Level, line: String) {
+        match &self.progress {
+            Some(progress) => progress.bar.suspend(|| log!(level, "{}", line)),
+            None => log!(level, "{}", line),
+        }
+    }
+}
+
+/// The progress bar along with the running counts shown next to it.
+struct Progress {
+    bar: ProgressBar,
+    copied: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Progress {
+    // a progress bar is only drawn for a person watching the run.
+    fn start(total: usize) -> Option<Self> {
+        if !std::io::stdout().is_terminal() {
+            return None;
+        }
+        let bar = ProgressBar::new(total as u64);
+        let style =
+            ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} files, {msg}, eta {eta}")
+                .ok()?;
+        bar.set_style(style);
+        let progress = Progress {
+            bar,
+            copied: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        };
+        progress.update_message();
+        Some(progress)
+    }
+
+    fn advance(&self, outcome: Outcome) {
+        match outcome {
+            Outcome::Copied | Outcome::Moved | Outcome::Symlinked | Outcome::Hardlinked => {
+                self.copied.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Skipped | Outcome::Duplicate => {
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Filtered | Outcome::Error => {}
+        }
+        self.update_message();
+        self.bar.inc(1);
+    }
+
+    fn update_message(&self) {
+        self.bar.set_message(format!(
+            "{} copied, {} skipped",
+            self.copied.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed)
+        ));
+    }
+}
+
+fn start_pool(threads: usize) -> Result<ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|err| anyhow::anyhow!("Failed to start the worker threads - [{}]", err))
+}
+
+/// Hashes every file already present at the target for `--dedupe-by-hash` and
+/// `--stable-by-hash`.
+fn hash_target(target_dir: &Path, own_files: &OwnFiles) -> Vec<(String, PathBuf)> {
+    let paths: Vec<PathBuf> = WalkDir::new(target_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && !own_files.contains(entry.path()))
+        .map(DirEntry::into_path)
+        .collect();
+    paths
+        .par_iter()
+        .filter_map(|path| match crate::file_hash(path) {
+            Ok(hash) => Some((hash, path.clone())),
+            Err(err) => {
+                error!(
+                    "{} while hashing the target file {} - [{:#}]",
+                    "Error".red(),
+                    path.display(),
+                    err
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+fn sort_file(context: &SortContext, index: usize, entry: DirEntry, summary: &mut Summary) {
+    let source_path = entry.path().to_path_buf();
+    // read before sorting, since a moved file is no longer at the source.
+    let captured = context
+        .options
+        .burst_window
+        .and_then(|_| get_capture_time(&entry, context.options.date_tag));
+    let sorted = sort_entry(context, entry, summary);
+    if let (Some(kept), Some(target_path)) = (&context.kept, &sorted.target_path) {
+        kept.lock().unwrap().insert(target_path.clone());
+    }
+    if let (Some(captured), Some(target_path)) = (captured, &sorted.target_path) {
+        if sorted.outcome.is_sorted() {
+            let mut captured_files = context.captured.lock().unwrap();
+            captured_files.push((target_path.clone(), captured));
+        }
+    }
+    if let (Some(emptied), Outcome::Moved) = (&context.emptied, sorted.outcome) {
+        emptied.lock().unwrap().push(source_path.clone());
+    }
+    if let Some(progress) = &context.progress {
+        progress.advance(sorted.outcome);
+    }
+    // a file left for later or which failed is sorted again by the next run.
+    if !matches!(sorted.outcome, Outcome::Filtered | Outcome::Error) {
+        if let Some(state) = context.state.lock().unwrap().as_mut() {
+            if let Err(err) = state.record(&source_path) {
+                context.log(
+                    Level::Error,
+                    format!(
+                        "{} while writing the state for {} - [{:#}]",
+                        "Error".red(),
+                        source_path.display(),
+                        err
+                    ),
+                );
+            }
+        }
+    }
+    if context.manifest.lock().unwrap().is_some() {
+        let row = (index, source_path.clone(), sorted.clone());
+        context.manifest_rows.lock().unwrap().push(row);
+    }
+    context.emit(sorted.into_event(source_path));
+}
+
+/// Where a single file would be sorted to by the options, and the date it
+/// would be sorted by, without touching the target.
+pub(crate) fn locate(options: &SortOptions, path: &Path) -> Result<SortedFile> {
+    let entry = WalkDir::new(path)
+        .follow_links(true)
+        .into_iter()
+        .next()
+        .expect("the walk yields its root first")
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "The file path is invalid. Please make sure it exists - [{}]",
+                err
+            )
+        })?;
+    if entry.file_type().is_dir() {
+        anyhow::bail!(
+            "{} is a directory. Please pass a single file.",
+            path.display()
+        );
+    }
+    if let Err(err) = validate_template(&options.layout) {
+        anyhow::bail!("The layout is invalid - [{}]", err);
+    }
+    let name_patterns = match options.date_from_name {
+        true => name_patterns(&options.name_patterns)
+            .map_err(|err| anyhow::anyhow!("The --name-pattern is invalid - [{}]", err))?,
+        false => Vec::new(),
+    };
+    let bytes = entry.metadata()?.len();
+    let (file_date, _) = get_file_date(
+        &entry,
+        options.date_tag,
+        options.zone,
+        options.date_policy,
+        options.date_skew,
+        &DateSources {
+            name_patterns: &name_patterns,
+            takeout: options.takeout,
+            command: options.date_command.as_ref(),
+        },
+    );
+    let date_source = file_date.context("Failed to read the file date")?;
+    let undated = options.exif_only && matches!(date_source, DateSource::Modified(_));
+    if undated && options.unknown_dir.is_none() {
+        anyhow::bail!(
+            "{} has no capture date, and is left out by --exif-only.",
+            path.display()
+        );
+    }
+    let templates = Mutex::new(TemplateResolver::new(&options.source_dir, &options.layout));
+    let template = template_for(&entry, options, &templates);
+    let target_root = route_target(&entry, &options.routes, &options.target_dir);
+    let (resolvers, camera) = group_resolvers(&entry, options);
+    let naming = FileNaming {
+        prefix: options.name_prefix.as_deref(),
+        suffix: options.name_suffix.as_deref(),
+        rename: options.rename.as_deref(),
+        camera: camera.as_deref(),
+        counter: 1,
+    };
+    let target_path = get_target_path(
+        &entry,
+        date_source.date(),
+        target_root,
+        &resolvers,
+        &template,
+        options.month_format,
+        &naming,
+    );
+    let target = special_target(
+        &entry,
+        options,
+        target_path,
+        &resolvers,
+        date_source.date(),
+        undated,
+    );
+    Ok(SortedFile {
+        source: entry.into_path(),
+        target: Some(target),
+        bytes,
+        date: Some(date_source),
+    })
+}
+
+// the layout of the file, or the place it was taken at when it's known.
+fn template_for(
+    entry: &DirEntry,
+    options: &SortOptions,
+    templates: &Mutex<TemplateResolver>,
+) -> String {
+    let place = options
+        .by_location
+        .then(|| get_gps_from_exif(entry))
+        .flatten()
+        .and_then(|(latitude, longitude)| find_place(latitude, longitude));
+    match place {
+        Some(place) => place.template(),
+        None => templates
+            .lock()
+            .unwrap()
+            .resolve(entry.path().parent().unwrap_or(&options.source_dir)),
+    }
+}
+
+// the resolvers naming the folders the photos of every camera, lens and so on
+// go under, and the camera of the file when it names the file.
+fn group_resolvers(
+    entry: &DirEntry,
+    options: &SortOptions,
+) -> (Vec<&'static dyn GroupResolver>, Option<String>) {
+    let resolvers = options
+        .group_by
+        .iter()
+        .map(|group| group.resolver())
+        .collect();
+    let renamed_by_camera = options
+        .rename
+        .as_deref()
+        .is_some_and(|rename| rename.contains("{camera}"));
+    let camera = renamed_by_camera.then(|| CameraGroup.folder(entry));
+    (resolvers, camera)
+}
+
+// the files without a capture date keep their name in a folder of their own,
+// and the layout gives way to an archive of the day.
+fn special_target(
+    entry: &DirEntry,
+    options: &SortOptions,
+    target_path: PathBuf,
+    resolvers: &[&dyn GroupResolver],
+    file_date: NaiveDate,
+    undated: bool,
+) -> PathBuf {
+    let target_root = || {
+        group_root(
+            entry,
+            route_target(entry, &options.routes, &options.target_dir),
+            resolvers,
+        )
+    };
+    match (&options.unknown_dir, options.archive) {
+        (Some(unknown_dir), _) if undated => {
+            target_root().join(unknown_dir).join(entry.file_name())
+        }
+        (_, true) => target_root()
+            .join(format!("{}.zip", file_date))
+            .join(target_path.file_name().unwrap_or_default()),
+        (_, false) => target_path,
+    }
+}
+
+fn sort_entry(context: &SortContext, entry: DirEntry, summary: &mut Summary) -> Sorted {
+    let options = context.options;
+
+    // the remaining files are left for a later run once the byte budget is used up
+    if context.budget.is_reached() && context.diff.is_none() {
+        summary.mark_filtered(FilterReason::Budget);
+        return Sorted::unresolved(Outcome::Filtered);
+    }
+    if context.stopped.load(Ordering::Relaxed) {
+        summary.mark_filtered(FilterReason::Stopped);
+        return Sorted::unresolved(Outcome::Filtered);
+    }
+
+    let source_len = match entry.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            context.log(
+                Level::Error,
+                format!(
+                    "{} while trying to read the size of the source file {} - [{}]",
+                    "Error".red(),
+                    entry.path().display(),
+                    err
+                ),
+            );
+            summary.mark_error(entry.into_path());
+            return Sorted::unresolved(Outcome::Error);
+        }
+    };
+
+    // an empty file is usually a failed download or export, and would collide
+    // with the real file of the same name.
+    if source_len == 0 {
+        if options.skip_empty {
+            match &context.diff {
+                Some(diff) => diff
+                    .lock()
+                    .unwrap()
+                    .mark(DiffCategory::Filtered, source_len),
+                None => summary.mark_empty_skipped(),
+            }
+            return Sorted::unresolved(Outcome::Filtered);
+        }
+        context.log(
+            Level::Warn,
+            format!(
+                "{} The file {} is empty. Pass --skip-empty to leave the empty files out.",
+                "Warning.".yellow(),
+                entry.path().display()
+            ),
+        );
+    }
+
+    // files outside of --min-size and --max-size are left alone, eg. thumbnails.
+    if !in_size_range(source_len, options.min_size, options.max_size) {
+        match &context.diff {
+            Some(diff) => diff
+                .lock()
+                .unwrap()
+                .mark(DiffCategory::Filtered, source_len),
+            None => summary.mark_filtered(FilterReason::Size),
+        }
+        let mut sorted = Sorted::unresolved(Outcome::Filtered);
+        sorted.bytes = source_len;
+        return sorted;
+    }
+
+    // get the date of the file from the exif or the metadata
+    let (file_date, exif_error) = get_file_date(
+        &entry,
+        options.date_tag,
+        options.zone,
+        options.date_policy,
+        options.date_skew,
+        &DateSources {
+            name_patterns: &context.name_patterns,
+            takeout: options.takeout,
+            command: options.date_command.as_ref(),
+        },
+    );
+    // a file dated by its modified time has no capture date.
+    let undated = options.exif_only && matches!(file_date, Ok(DateSource::Modified(_)));
+    // an exif date which was picked anyway is only suspect, eg. a skewed one.
+    let modified = matches!(file_date, Ok(DateSource::Modified(_)));
+    match exif_error.as_ref().filter(|_| !undated) {
+        Some(err) if modified => context.log(Level::Warn, format!(
+            "{} Could not read the date from the metadata of the file {} - [{}]. Will default to file modified time.",
+            "Warning.".yellow(),
+            entry.path().display(),
+            err.root_cause()
+        )),
+        Some(err) => context.log(
+            Level::Warn,
+            format!(
+                "{} The date in the metadata of the file {} may be wrong - [{}]",
+                "Warning.".yellow(),
+                entry.path().display(),
+                err.root_cause()
+            ),
+        ),
+        None => {}
+    }
+    let date_source = match file_date {
+        Ok(date_source) => {
+            if exif_error.is_some() && modified && !undated {
+                summary.mark_exif_error(entry.clone().into_path());
+            }
+            date_source
+        }
+        Err(err) => {
+            context.log(
+                Level::Error,
+                format!(
+                    "{} while reading the file date for the file {} - [{}]",
+                    "Error".red(),
+                    entry.path().display(),
+                    err
+                ),
+            );
+            summary.mark_error(entry.into_path());
+            return Sorted::unresolved(Outcome::Error);
+        }
+    };
+    if undated && options.unknown_dir.is_none() {
+        match &context.diff {
+            Some(diff) => diff
+                .lock()
+                .unwrap()
+                .mark(DiffCategory::Filtered, source_len),
+            None => summary.mark_filtered(FilterReason::Undated),
+        }
+        let mut sorted = Sorted::unresolved(Outcome::Filtered);
+        sorted.bytes = source_len;
+        return sorted;
+    }
+    let file_date = date_source.date();
+    debug!(
+        "{} is dated {} from the {}",
+        entry.path().display(),
+        file_date,
+        date_source.label()
+    );
+
+    // convert the timestamp to a path at the target. A photo taken at a known
+    // place goes under its country and city instead.
+    let template = template_for(&entry, options, &context.templates);
+    let target_root = route_target(&entry, &options.routes, &options.target_dir);
+    let (resolvers, camera) = group_resolvers(&entry, options);
+    let mut naming = FileNaming {
+        prefix: options.name_prefix.as_deref(),
+        suffix: options.name_suffix.as_deref(),
+        rename: options.rename.as_deref(),
+        camera: camera.as_deref(),
+        counter: 1,
+    };
+    let target_for = |naming: &FileNaming| {
+        let target_path = get_target_path(
+            &entry,
+            file_date,
+            target_root,
+            &resolvers,
+            &template,
+            options.month_format,
+            naming,
+        );
+        special_target(&entry, options, target_path, &resolvers, file_date, undated)
+    };
+    let mut target_path = target_for(&naming);
+
+    // files captured outside of --after and --before are left alone.
+    if !in_date_range(file_date, options.after, options.before) {
+        match &context.diff {
+            Some(diff) => diff
+                .lock()
+                .unwrap()
+                .mark(DiffCategory::Filtered, source_len),
+            None => summary.mark_filtered(FilterReason::DateRange),
+        }
+        return Sorted::at(Outcome::Filtered, &target_path, source_len, date_source);
+    }
+    if !undated {
+        summary.mark_date(file_date);
+    }
+
+    // no other worker touches this target until the file is sorted.
+    let _target_lock = context.target_locks.lock(&target_path);
+
+    // a file already at its target is left where it is.
+    if options.in_place && absolute_path(entry.path()) == absolute_path(&target_path) {
+        match &context.diff {
+            Some(diff) => diff.lock().unwrap().mark(DiffCategory::Present, source_len),
+            None => summary.mark_skipped(),
+        }
+        return Sorted::at(Outcome::Skipped, &target_path, source_len, date_source);
+    }
+
+    let transfer = options.transfer;
+
+    // links, catalog tools and the journal need the absolute source, which
+    // can't be resolved once the file is moved.
+    let mapping_source = context.mapping.lock().unwrap().is_some();
+    let journal_source = context.journal.lock().unwrap().is_some();
+    let absolute_source = (mapping_source || journal_source || transfer == Transfer::Symlink)
+        .then(|| absolute_path(entry.path()))
+        .flatten();
+
+    let decide = |target_path: &Path| {
+        let planned = context.planned.lock().unwrap().get(target_path).copied();
+        match (planned, &absolute_source) {
+            (Some(planned_len), _) => Ok(Action::for_existing(source_len, planned_len)),
+            (None, _) if context.archives.is_some() => {
+                let archives = context.archives.as_ref().unwrap();
+                archives.entry_len(target_path).map(|len| match len {
+                    Some(len) => Action::for_existing(source_len, len),
+                    None => Action::Copy,
+                })
+            }
+            (None, Some(source)) if transfer == Transfer::Symlink => {
+                decide_symlink_action(source, source_len, target_path, options.verify_content)
+            }
+            (None, _) => decide_action(
+                entry.path(),
+                source_len,
+                target_path,
+                options.verify_content,
+            ),
+        }
+    };
+    let mut action = decide(&target_path);
+
+    // the hook chooses what to do with a collision, unless it was sorted this run.
+    let mut target_exists = options.target_exists;
+    if matches!(action, Ok(Action::Duplicate)) && !context.sorted_this_run(&target_path) {
+        match context.resolve_collision(entry.path(), &target_path) {
+            Some(Some(choice)) => target_exists = choice,
+            Some(None) => {
+                summary.mark_filtered(FilterReason::Stopped);
+                return Sorted::unresolved(Outcome::Filtered);
+            }
+            None => {}
+        }
+    }
+
+    // a different file holds the name, so the file goes under the next numbered
+    // name instead. A file already sorted under one of them is found again. The
+    // names of a rename template collide often, so they are always numbered,
+    // by their counter when they have one, unless told to overwrite or fail.
+    // An update replaces the file holding the name instead.
+    let renaming = match options.rename {
+        Some(_) => target_exists != TargetExists::Overwrite && target_exists != TargetExists::Error,
+        None => target_exists == TargetExists::Rename,
+    } && !options.update;
+    // the files in the unknown folder keep their names, without a counter.
+    let counted = !undated
+        && options
+            .rename
+            .as_deref()
+            .is_some_and(|rename| rename.contains("{counter}"));
+    let mut _renamed_locks = Vec::new();
+    while renaming && matches!(action, Ok(Action::Duplicate)) {
+        target_path = match counted {
+            true => {
+                naming.counter += 1;
+                target_for(&naming)
+            }
+            false => dedupe_target_name(&target_path),
+        };
+        _renamed_locks.push(context.target_locks.lock(&target_path));
+        action = decide(&target_path);
+    }
+
+    let action = match action {
+        Ok(action) => action,
+        Err(err) => {
+            context.log(
+                Level::Error,
+                format!(
+                    "{} while trying to read the size of the target file {} - [{}]",
+                    "Error".red(),
+                    target_path.display(),
+                    err
+                ),
+            );
+            summary.mark_error(entry.into_path());
+            return Sorted::at(Outcome::Error, &target_path, source_len, date_source);
+        }
+    };
+
+    // an existing file is replaced unless this run sorted it, or it's known to
+    // hold the same contents with --verify-content.
+    let overwriting = target_exists == TargetExists::Overwrite
+        && match action {
+            Action::Skip => !options.verify_content,
+            Action::Duplicate => true,
+            Action::Copy | Action::SameContents => false,
+        }
+        && !context.sorted_this_run(&target_path);
+    // an update replaces an existing file only by a newer source, whatever its
+    // size, and skips it otherwise.
+    let updating = options.update
+        && matches!(action, Action::Skip | Action::Duplicate)
+        && !context.sorted_this_run(&target_path)
+        && is_newer(entry.path(), &target_path);
+    let action = match action {
+        _ if overwriting || updating => Action::Copy,
+        Action::Duplicate if options.update => Action::Skip,
+        action => action,
+    };
+
+    let hashed =
+        context.hashes.is_some() || options.stable_by_hash || options.no_clobber_across_dates;
+    let source_hash = match action {
+        Action::Copy if hashed => match context.hash_file(entry.path()) {
+            Ok(hash) => Some(hash),
+            Err(err) => {
+                context.log(
+                    Level::Error,
+                    format!(
+                        "{} while hashing the file {} - [{:#}]",
+                        "Error".red(),
+                        entry.path().display(),
+                        err
+                    ),
+                );
+                summary.mark_error(entry.into_path());
+                return Sorted::at(Outcome::Error, &target_path, source_len, date_source);
+            }
+        },
+        _ => None,
+    };
+
+    // a file sorted by an earlier run is found by its contents, even when its
+    // date has changed since, eg. a modified time which drifted. Without
+    // --stable-by-hash it must have kept its name too.
+    let sorted_before = source_hash
+        .as_ref()
+        .filter(|_| options.stable_by_hash || options.no_clobber_across_dates)
+        .and_then(|hash| context.target_index().get(hash))
+        .and_then(|existing| match options.stable_by_hash {
+            true => existing.first(),
+            false => existing
+                .iter()
+                .find(|existing| existing.file_name() == target_path.file_name()),
+        })
+        .cloned();
+    if let Some(existing) = &sorted_before {
+        target_path = existing.clone();
+    }
+
+    // a file with the same contents anywhere at the target is a duplicate too.
+    let mut claimed_hash = None;
+    let action = match (&context.hashes, action, source_hash) {
+        _ if sorted_before.is_some() => Action::Skip,
+        (Some(hashes), Action::Copy, Some(hash)) => {
+            if hashes.lock().unwrap().insert(hash.clone()) {
+                claimed_hash = Some(hash);
+                Action::Copy
+            } else {
+                Action::SameContents
+            }
+        }
+        (_, action, _) => action,
+    };
+
+    let budgeted_len = transfer.budgeted_len(source_len);
+    let within_budget = action == Action::Copy && context.budget.reserve(budgeted_len);
+    let sorted_outcome = transfer.outcome();
+    if !within_budget {
+        release_hash(context, claimed_hash.take());
+    }
+
+    if let Some(diff) = &context.diff {
+        let category = match action {
+            Action::Copy if !within_budget => DiffCategory::Filtered,
+            Action::Copy => {
+                context
+                    .planned
+                    .lock()
+                    .unwrap()
+                    .insert(target_path.clone(), source_len);
+                DiffCategory::New
+            }
+            Action::Skip | Action::SameContents => DiffCategory::Present,
+            Action::Duplicate => DiffCategory::Collision,
+        };
+        diff.lock().unwrap().mark(category, source_len);
+        let outcome = match category {
+            DiffCategory::New => sorted_outcome,
+            DiffCategory::Present => Outcome::Skipped,
+            DiffCategory::Collision => Outcome::Duplicate,
+            DiffCategory::Filtered => Outcome::Filtered,
+        };
+        return Sorted::at(outcome, &target_path, source_len, date_source);
+    }
+
+    match action {
+        Action::Copy => {}
+        Action::Skip => {
+            summary.mark_skipped();
+            return Sorted::at(Outcome::Skipped, &target_path, source_len, date_source);
+        }
+        Action::Duplicate if target_exists == TargetExists::Error => {
+            context.log(
+                Level::Error,
+                format!(
+                    "{} to copy {} since a different file with the same name exists at the target {}",
+                    "Failed".red(),
+                    entry.path().display(),
+                    target_path.parent().unwrap().display()
+                ),
+            );
+            summary.mark_error(entry.into_path());
+            return Sorted::at(Outcome::Error, &target_path, source_len, date_source);
+        }
+        Action::Duplicate => {
+            context.log(Level::Warn, format!("A different file with the same name exists at the target {}. This file would be skipped for copying - {}", 
+                target_path.parent().unwrap().display(), entry.path().display()));
+            summary.mark_duplicate(entry.into_path());
+            return Sorted::at(Outcome::Duplicate, &target_path, source_len, date_source);
+        }
+        Action::SameContents => {
+            context.log(Level::Warn, format!(
+                "A file with the same contents already exists at the target. This file would be skipped for copying - {}",
+                entry.path().display()
+            ));
+            summary.mark_duplicate(entry.into_path());
+            return Sorted::at(Outcome::Duplicate, &target_path, source_len, date_source);
+        }
+    }
+
+    if !within_budget {
+        summary.mark_filtered(FilterReason::Budget);
+        return Sorted::at(Outcome::Filtered, &target_path, source_len, date_source);
+    }
+
+    if options.dry_run {
+        context
+            .planned
+            .lock()
+            .unwrap()
+            .insert(target_path.clone(), source_len);
+        transfer.mark(summary, entry.path(), source_len);
+        if overwriting {
+            summary.mark_overwritten();
+        }
+        if updating {
+            summary.mark_updated();
+        }
+        return Sorted::at(sorted_outcome, &target_path, source_len, date_source);
+    }
+
+    // create the parent directory structure if it does not exist
+    if let Some(parent_path) = target_path.parent().filter(|_| context.archives.is_none()) {
+        // the folders this worker creates are given the owner too.
+        let created: Vec<&Path> = match options.owner {
+            Some(_) => parent_path
+                .ancestors()
+                .take_while(|dir| !dir.exists())
+                .collect(),
+            None => Vec::new(),
+        };
+        match fs::create_dir_all(parent_path) {
+            Ok(_) => {
+                for dir in created {
+                    change_owner(context, dir);
+                }
+            }
+            Err(err) => {
+                context.log(
+                    Level::Error,
+                    format!(
+                        "{} creating the parent directory {} at the target - [{}]",
+                        "Error".red(),
+                        parent_path.display(),
+                        err
+                    ),
+                );
+                context.budget.release(budgeted_len);
+                release_hash(context, claimed_hash);
+                summary.mark_dir_error(parent_path.to_path_buf());
+                return Sorted::at(Outcome::Error, &target_path, source_len, date_source);
+            }
+        }
+    }
+
+    // copy, move or link the file
+    let warn_retry = |err: &std::io::Error| {
+        context.log(
+            Level::Warn,
+            format!(
+                "{} Retrying {} after a failure - [{}]",
+                "Warning.".yellow(),
+                entry.path().display(),
+                err
+            ),
+        )
+    };
+    let result = match transfer {
+        Transfer::Copy => match &context.archives {
+            Some(archives) => with_retries(options.retries, warn_retry, || {
+                archives.add(entry.path(), &target_path, options.preserve_mtime)
+            }),
+            None => with_retries(options.retries, warn_retry, || {
+                copy_file(entry.path(), &target_path)
+            }),
+        },
+        Transfer::Move => with_retries(options.retries, warn_retry, || {
+            move_file(entry.path(), &target_path)
+        }),
+        Transfer::Symlink => match &absolute_source {
+            Some(source) if options.relative_symlink => relative_link(source, &target_path)
+                .and_then(|link| symlink_file(&link, &target_path))
+                .and_then(|()| check_link(source, &target_path))
+                .map(|()| 0),
+            Some(source) => symlink_file(source, &target_path).map(|()| 0),
+            None => Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                "Failed to resolve the source path",
+            )),
+        },
+        Transfer::Hardlink => hard_link_file(entry.path(), &target_path).map(|()| 0),
+    };
+    match result {
+        Ok(bytes) => {
+            // a copy gets the current time unless it's carried over.
+            if transfer == Transfer::Copy && options.preserve_mtime && context.archives.is_none() {
+                if let Err(err) = preserve_mtime(entry.path(), &target_path) {
+                    context.log(
+                        Level::Warn,
+                        format!(
+                            "{} Could not keep the modified time of {} - [{}]",
+                            "Warning.".yellow(),
+                            target_path.display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            change_owner(context, &target_path);
+            if transfer == Transfer::Copy && options.preserve_perms {
+                if let Err(err) = preserve_permissions(entry.path(), &target_path) {
+                    context.log(
+                        Level::Warn,
+                        format!(
+                            "{} Could not keep the permissions of {} - [{}]",
+                            "Warning.".yellow(),
+                            target_path.display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            // a target filesystem without extended attributes doesn't fail the copy.
+            if transfer == Transfer::Copy && options.preserve_xattrs {
+                if let Err(err) = preserve_xattrs(entry.path(), &target_path) {
+                    context.log(
+                        Level::Warn,
+                        format!(
+                            "{} Could not keep the extended attributes of {} - [{}]",
+                            "Warning.".yellow(),
+                            target_path.display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            transfer.mark(summary, entry.path(), bytes);
+            if overwriting {
+                summary.mark_overwritten();
+            }
+            if updating {
+                summary.mark_updated();
+            }
+            if let Some(sorted) = &context.sorted {
+                sorted.lock().unwrap().insert(target_path.clone());
+            }
+            if let Some(journal) = context.journal.lock().unwrap().as_mut() {
+                let recorded = absolute_source
+                    .as_deref()
+                    .context("Failed to resolve the source path")
+                    .and_then(|source| journal.record(transfer, source, &target_path, source_len));
+                if let Err(err) = recorded {
+                    context.log(
+                        Level::Error,
+                        format!(
+                            "{} while writing the journal for {} - [{}]",
+                            "Error".red(),
+                            entry.path().display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            if context.checksums.lock().unwrap().is_some() {
+                // the copy is hashed rather than the source, so the line holds what landed.
+                let recorded = context.hash_file(&target_path).and_then(|hash| {
+                    let line = checksum_line(&hash, &target_path, &options.target_dir);
+                    let mut checksums = context.checksums.lock().unwrap();
+                    let writer = checksums.as_mut().expect("the checksums are open");
+                    writer.write_all(line.as_bytes())?;
+                    Ok(())
+                });
+                if let Err(err) = recorded {
+                    context.log(
+                        Level::Error,
+                        format!(
+                            "{} while writing the checksum for {} - [{:#}]",
+                            "Error".red(),
+                            target_path.display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            if let Some(writer) = context.mapping.lock().unwrap().as_mut() {
+                if let Err(err) = write_mapping(writer, absolute_source, &target_path) {
+                    context.log(
+                        Level::Error,
+                        format!(
+                            "{} while writing the mapping for {} - [{}]",
+                            "Error".red(),
+                            entry.path().display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            Sorted::at(sorted_outcome, &target_path, bytes, date_source)
+        }
+        Err(err) => {
+            context.log(
+                Level::Error,
+                format!(
+                    "{} while {} {} to {} - [{}]",
+                    "Error".red(),
+                    transfer.gerund(),
+                    entry.path().display(),
+                    target_path.display(),
+                    err
+                ),
+            );
+            context.budget.release(budgeted_len);
+            release_hash(context, claimed_hash);
+            summary.mark_error(entry.into_path());
+            Sorted::at(Outcome::Error, &target_path, source_len, date_source)
+        }
+    }
+}
+
+/// What happened to a single source file, as recorded in the manifest.
+#[derive(Clone)]
+struct Sorted {
+    outcome: Outcome,
+    target_path: Option<PathBuf>,
+    bytes: u64,
+    date_source: Option<DateSource>,
+}
+
+impl Sorted {
+    // the file was left before its target path was known.
+    fn unresolved(outcome: Outcome) -> Self {
+        Sorted {
+            outcome,
+            target_path: None,
+            bytes: 0,
+            date_source: None,
+        }
+    }
+
+    fn at(outcome: Outcome, target_path: &Path, bytes: u64, date_source: DateSource) -> Self {
+        Sorted {
+            outcome,
+            target_path: Some(target_path.to_path_buf()),
+            bytes,
+            date_source: Some(date_source),
+        }
+    }
+
+    fn into_event(self, source: PathBuf) -> SortEvent {
+        let file = SortedFile {
+            source,
+            target: self.target_path,
+            bytes: self.bytes,
+            date: self.date_source,
+        };
+        match self.outcome {
+            Outcome::Copied | Outcome::Moved | Outcome::Symlinked | Outcome::Hardlinked => {
+                SortEvent::Copied(file)
+            }
+            Outcome::Skipped => SortEvent::Skipped(file),
+            Outcome::Duplicate => SortEvent::Duplicate(file),
+            Outcome::Filtered => SortEvent::Filtered(file),
+            Outcome::Error => SortEvent::Error(file),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Copied,
+    Moved,
+    Symlinked,
+    Hardlinked,
+    Skipped,
+    Duplicate,
+    // left for a later run, eg. by the byte budget.
+    Filtered,
+    Error,
+}
+
+impl Outcome {
+    // the file is at its target, whether sorted by this run or an earlier one.
+    fn is_sorted(self) -> bool {
+        !matches!(
+            self,
+            Outcome::Duplicate | Outcome::Filtered | Outcome::Error
+        )
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Copied => "copied",
+            Outcome::Moved => "moved",
+            Outcome::Symlinked => "symlinked",
+            Outcome::Hardlinked => "hardlinked",
+            Outcome::Skipped => "skipped",
+            Outcome::Duplicate => "duplicate",
+            Outcome::Filtered => "filtered",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+// both bounds are inclusive and a missing bound leaves that side open.
+fn in_date_range(date: NaiveDate, after: Option<NaiveDate>, before: Option<NaiveDate>) -> bool {
+    after.is_none_or(|after| date >= after) && before.is_none_or(|before| date <= before)
+}
+
+fn in_size_range(len: u64, min_size: Option<u64>, max_size: Option<u64>) -> bool {
+    min_size.is_none_or(|min_size| len >= min_size)
+        && max_size.is_none_or(|max_size| len <= max_size)
+}
+
+// a file that failed to sort no longer holds its contents at the target.
+fn release_hash(context: &SortContext, hash: Option<String>) {
+    if let (Some(hashes), Some(hash)) = (&context.hashes, hash) {
+        hashes.lock().unwrap().remove(&hash);
+    }
+}
+
+/// Serializes the work on a target path so that two workers never sort
+/// different files to the same target at once.
+struct TargetLocks {
+    locked: Mutex<HashSet<PathBuf>>,
+    released: Condvar,
+}
+
+impl TargetLocks {
+    fn new() -> Self {
+        TargetLocks {
+            locked: Mutex::new(HashSet::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    fn lock(&self, path: &Path) -> TargetLock<'_> {
+        let mut locked = self.locked.lock().unwrap();
+        while locked.contains(path) {
+            locked = self.released.wait(locked).unwrap();
+        }
+        locked.insert(path.to_path_buf());
+        TargetLock {
+            locks: self,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+struct TargetLock<'a> {
+    locks: &'a TargetLocks,
+    path: PathBuf,
+}
+
+impl Drop for TargetLock<'_> {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.path);
+        self.locks.released.notify_all();
+    }
+}
+
+/// The bytes which may still be copied in this run.
+struct Budget {
+    max_bytes: Option<u64>,
+    used: AtomicU64,
+    reached: AtomicBool,
+}
+
+impl Budget {
+    fn new(max_bytes: Option<u64>) -> Self {
+        Budget {
+            max_bytes,
+            used: AtomicU64::new(0),
+            reached: AtomicBool::new(false),
+        }
+    }
+
+    // claims the bytes for a copy. Once a copy doesn't fit the budget is
+    // reached and no more copies are allowed.
+    fn reserve(&self, len: u64) -> bool {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return true,
+        };
+        if self.is_reached() {
+            return false;
+        }
+        let reserved = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used + len <= max_bytes).then_some(used + len)
+            })
+            .is_ok();
+        if !reserved {
+            self.reached.store(true, Ordering::SeqCst);
+        }
+        reserved
+    }
+
+    fn release(&self, len: u64) {
+        if self.max_bytes.is_some() {
+            self.used.fetch_sub(len, Ordering::SeqCst);
+        }
+    }
+
+    fn is_reached(&self) -> bool {
+        self.reached.load(Ordering::SeqCst)
+    }
+}
+
+/// The files written or run by photosort itself, matched by their absolute path.
+struct OwnFiles {
+    paths: HashMap<OsString, Vec<PathBuf>>,
+}
+
+impl OwnFiles {
+    fn new() -> Self {
+        OwnFiles {
+            paths: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, path: &Path) {
+        if let (Some(name), Some(path)) = (path.file_name(), absolute_path(path)) {
+            self.paths
+                .entry(name.to_os_string())
+                .or_default()
+                .push(path);
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        // only resolve the path when the name matches to keep the walk cheap.
+        let candidates = match path.file_name().and_then(|name| self.paths.get(name)) {
+            Some(candidates) => candidates,
+            None => return false,
+        };
+        absolute_path(path).is_some_and(|path| candidates.contains(&path))
+    }
+}
+
+// prefer the canonical path so symlinks and relative paths compare equal.
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .ok()
+}
+
+fn create_mapping_csv(path: &Path) -> Result<csv::Writer<File>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["old_path", "new_path"])?;
+    Ok(writer)
+}
+
+// a checksum manifest is added to by every run, like sha256sum >> SHA256SUMS.
+fn open_checksums(path: &Path) -> std::io::Result<LineWriter<File>> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    Ok(LineWriter::new(file))
+}
+
+// the format of sha256sum, where a path holding a backslash or a newline is
+// escaped and its line starts with a backslash.
+fn checksum_line(hash: &str, target_path: &Path, target_dir: &Path) -> String {
+    let path = target_path
+        .strip_prefix(target_dir)
+        .unwrap_or(target_path)
+        .to_string_lossy();
+    if path.contains(['\\', '\n', '\r']) {
+        let path = path
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        format!("\\{}  {}\n", hash, path)
+    } else {
+        format!("{}  {}\n", hash, path)
+    }
+}
+
+fn create_manifest(path: &Path) -> Result<csv::Writer<File>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "source_path",
+        "target_path",
+        "action",
+        "bytes",
+        "date_source",
+    ])?;
+    Ok(writer)
+}
+
+fn write_manifest(
+    writer: &mut csv::Writer<File>,
+    source: &Path,
+    sorted: &Sorted,
+    roots: &PathRoots,
+) -> Result<()> {
+    let target = sorted.target_path.as_ref().map_or(&[][..], |target| {
+        roots.target_path(target).as_os_str().as_encoded_bytes()
+    });
+    writer.write_record([
+        roots.source_path(source).as_os_str().as_encoded_bytes(),
+        target,
+        sorted.outcome.label().as_bytes(),
+        sorted.bytes.to_string().as_bytes(),
+        sorted.date_source.map_or("", DateSource::label).as_bytes(),
+    ])?;
+    Ok(())
+}
+
+// catalog tools need absolute paths to reconcile moved files.
+fn write_mapping(
+    writer: &mut csv::Writer<File>,
+    source: Option<PathBuf>,
+    target: &Path,
+) -> Result<()> {
+    let source = source.context("Failed to resolve the source path")?;
+    let target = fs::canonicalize(target)?;
+    writer.write_record([
+        source.as_os_str().as_encoded_bytes(),
+        target.as_os_str().as_encoded_bytes(),
+    ])?;
+    Ok(())
+}
+
+// the copy is renamed into place once complete, so an interrupted copy never
+// leaves a partial file at the target path.
+fn copy_file(source: &Path, target: &Path) -> std::io::Result<u64> {
+    copy_via_part(target, |part| fs::copy(source, part))
+}
+
+// the first retry waits this long, and each one after it twice as long.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// the files copied at once while others are hashed, unless --jobs is given.
+const HASHING_JOBS: usize = 4;
+
+// runs the transfer again on a failure which may go away by itself, eg. a
+// flaky network share, up to the given number of times.
+fn with_retries<T>(
+    retries: u32,
+    on_retry: impl Fn(&std::io::Error),
+    mut transfer: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match transfer() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                on_retry(&err);
+                thread::sleep(RETRY_BACKOFF * 2u32.pow(attempt.min(16)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::Other
+    )
+}
+
+fn copy_via_part(
+    target: &Path,
+    copy: impl FnOnce(&Path) -> std::io::Result<u64>,
+) -> std::io::Result<u64> {
+    let mut part = target.as_os_str().to_os_string();
+    part.push(PART_SUFFIX);
+    let part = PathBuf::from(part);
+    let result = copy(&part).and_then(|bytes| fs::rename(&part, target).map(|()| bytes));
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    result
+}
+
+fn remove_partial_copies(target_dir: &Path) {
+    let parts = WalkDir::new(target_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_type().is_file()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.ends_with(PART_SUFFIX))
+        });
+    for entry in parts {
+        match fs::remove_file(entry.path()) {
+            Ok(()) => warn!(
+                "{} Removed the partial copy {} left by an interrupted run",
+                "Warning.".yellow(),
+                entry.path().display()
+            ),
+            Err(err) => error!(
+                "{} while removing the partial copy {} - [{}]",
+                "Error".red(),
+                entry.path().display(),
+                err
+            ),
+        }
+    }
+}
+
+// rename only works within a filesystem. Across filesystems the file is copied
+// and the source removed.
+pub(crate) fn move_file(source: &Path, target: &Path) -> std::io::Result<u64> {
+    let len = source.metadata()?.len();
+    match fs::rename(source, target) {
+        Ok(()) => Ok(len),
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            let bytes = copy_file(source, target)?;
+            preserve_mtime(source, target)?;
+            fs::remove_file(source)?;
+            Ok(bytes)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// a run which may change the owner was checked to be allowed to, so a failure
+// is rare enough to only be logged.
+fn change_owner(context: &SortContext, path: &Path) {
+    let Some(owner) = context.options.owner else {
+        return;
+    };
+    if let Err(err) = set_owner(path, owner) {
+        context.log(
+            Level::Error,
+            format!(
+                "{} to give {} the owner {}:{} - [{}]",
+                "Failed".red(),
+                path.display(),
+                owner.uid,
+                owner.gid,
+                err
+            ),
+        );
+    }
+}
+
+// a modified time which can't be read leaves the target alone.
+fn is_newer(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|metadata| metadata.modified());
+    match (modified(source), modified(target)) {
+        (Ok(source), Ok(target)) => source > target,
+        _ => false,
+    }
+}
+
+fn preserve_mtime(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mtime = FileTime::from_last_modification_time(&source.metadata()?);
+    filetime::set_file_mtime(target, mtime)
+}
+
+#[cfg(unix)]
+fn preserve_permissions(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::set_permissions(target, source.metadata()?.permissions())
+}
+
+// the permission bits are a Unix notion, and a read only flag isn't worth
+// carrying over by itself.
+#[cfg(windows)]
+fn preserve_permissions(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// every attribute is tried, so one which can't be set, eg. a trusted.* one
+// without root, doesn't keep the rest from the copy.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn preserve_xattrs(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let names = read_xattr(|buf| rustix::fs::listxattr(source, buf))?;
+    let mut result = Ok(());
+    for name in names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let name = std::ffi::OsStr::from_bytes(name);
+        let copied = read_xattr(|buf| rustix::fs::getxattr(source, name, buf)).and_then(|value| {
+            rustix::fs::setxattr(target, name, &value, rustix::fs::XattrFlags::empty())
+                .map_err(Into::into)
+        });
+        result = result.and(copied);
+    }
+    result
+}
+
+// the size of a list or a value is asked for first with an empty buffer.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_xattr(read: impl Fn(&mut [u8]) -> rustix::io::Result<usize>) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; read(&mut [])?];
+    let len = read(&mut buf)?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn preserve_xattrs(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// What to do with a source file once its target path is known.
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Copy,
+    // the same file is already present at the target.
+    Skip,
+    // a different file with the same name is present at the target.
+    Duplicate,
+    // a file with the same contents is present elsewhere at the target.
+    SameContents,
+}
+
+impl Action {
+    fn for_existing(source_len: u64, target_len: u64) -> Self {
+        if source_len == target_len {
+            Action::Skip
+        } else {
+            Action::Duplicate
+        }
+    }
+}
+
+// files of the same size are taken as the same file, unless told to compare
+// their contents.
+fn decide_action(
+    source: &Path,
+    source_len: u64,
+    target_path: &Path,
+    verify_content: bool,
+) -> std::io::Result<Action> {
+    if !target_path.exists() {
+        return Ok(Action::Copy);
+    }
+    let target_len = target_path.metadata()?.len();
+    match Action::for_existing(source_len, target_len) {
+        Action::Skip if verify_content && !files_identical(source, target_path)? => {
+            Ok(Action::Duplicate)
+        }
+        action => Ok(action),
+    }
+}
+
+// an existing link is the same file when it resolves back to the source.
+fn decide_symlink_action(
+    source: &Path,
+    source_len: u64,
+    target_path: &Path,
+    verify_content: bool,
+) -> std::io::Result<Action> {
+    let is_symlink = target_path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+    if !is_symlink {
+        return decide_action(source, source_len, target_path, verify_content);
+    }
+    match fs::canonicalize(target_path) {
+        Ok(resolved) if resolved == source => Ok(Action::Skip),
+        _ => Ok(Action::Duplicate),
+    }
+}
+
+// the path from the folder of the link back to the source. The folder is
+// resolved first, so a link under a symlinked target still finds the source.
+fn relative_link(source: &Path, target_path: &Path) -> std::io::Result<PathBuf> {
+    let folder = target_path.parent().and_then(absolute_path);
+    folder
+        .and_then(|folder| pathdiff::diff_paths(source, folder))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Failed to find a relative path to the source",
+            )
+        })
+}
+
+// a link which doesn't lead back to the source, eg. through a symlinked folder
+// on the way, is removed rather than left dangling.
+fn check_link(source: &Path, target_path: &Path) -> std::io::Result<()> {
+    if fs::canonicalize(target_path).is_ok_and(|resolved| resolved == source) {
+        return Ok(());
+    }
+    let _ = fs::remove_file(target_path);
+    Err(std::io::Error::new(
+        ErrorKind::InvalidData,
+        "The relative link doesn't resolve to the source",
+    ))
+}
+
+#[cfg(unix)]
+fn symlink_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+#[cfg(windows)]
+fn symlink_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(source, target)
+}
+
+// a hard link can't cross filesystems, unlike a copy or a move.
+fn hard_link_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::hard_link(source, target).map_err(|err| match err.kind() {
+        ErrorKind::CrossesDevices => std::io::Error::new(
+            err.kind(),
+            "The source and the target are on different filesystems. Hard links can only be created within a filesystem",
+        ),
+        _ => err,
+    })
+}
+
+// IMG_0001.jpg becomes IMG_0001 (1).jpg, which becomes IMG_0001 (2).jpg and so on.
+fn dedupe_target_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let numbered = stem
+        .to_str()
+        .and_then(|stem| stem.strip_suffix(')'))
+        .and_then(|stem| stem.rsplit_once(" ("))
+        .and_then(|(base, counter)| Some((base, counter.parse::<u32>().ok()?)));
+    let mut name = match numbered {
+        Some((base, counter)) => OsString::from(format!("{} ({})", base, counter + 1)),
+        None => {
+            let mut name = stem.to_os_string();
+            name.push(" (1)");
+            name
+        }
+    };
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+// the files in the order of --sort-order, the path breaking the ties so the
+// order is the same on every run.
+fn sort_entries(mut entries: Vec<DirEntry>, order: SortOrder) -> Vec<DirEntry> {
+    match order {
+        SortOrder::Name => entries.sort_by(|a, b| a.path().cmp(b.path())),
+        // a file whose metadata can't be read goes first, and fails there.
+        SortOrder::Date => entries.sort_by_cached_key(|entry| {
+            let modified = entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| metadata.modified().ok());
+            (modified, entry.path().to_path_buf())
+        }),
+        SortOrder::Size => entries.sort_by_cached_key(|entry| {
+            let len = entry.metadata().ok().map(|metadata| metadata.len());
+            (len, entry.path().to_path_buf())
+        }),
+    }
+    entries
+}
+
+// groups the files which could be sorted to the same target, keeping them in
+// order along with their position. The names are compared regardless of case
+// for the filesystems which ignore it.
+fn group_by_name(entries: Vec<DirEntry>) -> Vec<Vec<(usize, DirEntry)>> {
+    let mut groups: Vec<Vec<(usize, DirEntry)>> = Vec::new();
+    let mut group_of_name = HashMap::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        let group = *group_of_name.entry(name).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push((index, entry));
+    }
+    groups
+}
+
+// an empty include list lets every extension through.
+fn should_process(entry: &DirEntry, include: &[String], exclude: &[String]) -> bool {
+    let extension = lowercase_extension(entry);
+    let listed = |extensions: &[String]| extensions.iter().any(|e| same_extension(e, &extension));
+    (include.is_empty() || listed(include)) && !listed(exclude)
+}
+
+// the directory routed to the extension of the file, or the main target.
+fn route_target<'a>(
+    entry: &DirEntry,
+    routes: &'a [(String, PathBuf)],
+    target_dir: &'a Path,
+) -> &'a Path {
+    let extension = lowercase_extension(entry);
+    routes
+        .iter()
+        .find(|(routed, _)| same_extension(routed, &extension))
+        .map_or(target_dir, |(_, routed_dir)| routed_dir)
+}
+
+fn lowercase_extension(entry: &DirEntry) -> String {
+    entry
+        .path()
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// an extension given with or without the dot and in any case.
+fn same_extension(given: &str, extension: &str) -> bool {
+    given.trim_start_matches('.').to_lowercase() == extension
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn failed_copy_leaves_no_partial_file() {
+        let dir = std::env::temp_dir().join(format!("photosort-part-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("photo.jpg");
+
+        // the copy fails half way through.
+        let result = copy_via_part(&target, |part| {
+            File::create(part)?.write_all(b"half a photo")?;
+            Err(std::io::Error::new(ErrorKind::Interrupted, "interrupted"))
+        });
+
+        assert!(result.is_err());
+        assert!(!target.exists());
+        assert!(!dir.join("photo.jpg.photosort.part").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transient_failure_is_retried() {
+        let attempts = std::cell::Cell::new(0);
+        let retried = std::cell::Cell::new(0);
+
+        // the copy fails once, then goes through.
+        let result = with_retries(
+            2,
+            |_| retried.set(retried.get() + 1),
+            || {
+                attempts.set(attempts.get() + 1);
+                match attempts.get() {
+                    1 => Err(std::io::Error::new(ErrorKind::TimedOut, "timed out")),
+                    _ => Ok(42),
+                }
+            },
+        );
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(retried.get(), 1);
+    }
+
+    #[test]
+    fn permanent_failure_is_not_retried() {
+        let attempts = std::cell::Cell::new(0);
+
+        let result: std::io::Result<u64> = with_retries(
+            3,
+            |_| {},
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(std::io::Error::new(ErrorKind::PermissionDenied, "denied"))
+            },
+        );
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn checksum_lines_follow_sha256sum() {
+        let target_dir = Path::new("target");
+        assert_eq!(
+            checksum_line("abc", &target_dir.join("2008/May/30/a.jpg"), target_dir),
+            "abc  2008/May/30/a.jpg\n"
+        );
+        // the routes outside of the target keep their whole path.
+        assert_eq!(
+            checksum_line("abc", Path::new("videos/a.mp4"), target_dir),
+            "abc  videos/a.mp4\n"
+        );
+        assert_eq!(
+            checksum_line("abc", &target_dir.join("a\\b\nc.jpg"), target_dir),
+            "\\abc  a\\\\b\\nc.jpg\n"
+        );
+    }
+}
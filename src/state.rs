@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, LineWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The source files sorted so far by a run which may be interrupted, one JSON
+/// line each, so the next run can leave them out without sorting them again.
+pub(crate) struct SortState {
+    sorted: HashSet<PathBuf>,
+    // a run which leaves the target alone leaves the state alone too.
+    writer: Option<LineWriter<File>>,
+}
+
+impl SortState {
+    pub(crate) fn open(path: &Path, writable: bool) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).context("Failed to read the state file"),
+        };
+        // the last line may be cut short by a crash, and is sorted again.
+        let sorted = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let writer = match writable {
+            true => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                // the lines of this run start after the one cut short.
+                if !contents.is_empty() && !contents.ends_with('\n') {
+                    file.write_all(b"\n")?;
+                }
+                Some(LineWriter::new(file))
+            }
+            false => None,
+        };
+        Ok(SortState { sorted, writer })
+    }
+
+    pub(crate) fn contains(&self, source: &Path) -> bool {
+        std::path::absolute(source).is_ok_and(|source| self.sorted.contains(&source))
+    }
+
+    pub(crate) fn record(&mut self, source: &Path) -> Result<()> {
+        if let Some(writer) = &mut self.writer {
+            let source = std::path::absolute(source)?;
+            writeln!(writer, "{}", serde_json::to_string(&source)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The time the last successful run started at, kept in a file of its own by
+/// `--since-last-run`. A missing file means there was no such run yet.
+pub(crate) fn read_last_run(path: &Path) -> Result<Option<DateTime<Utc>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("Failed to read the last run file"),
+    };
+    let time = DateTime::parse_from_rfc3339(contents.trim())
+        .context("The last run file doesn't hold a time")?;
+    Ok(Some(time.with_timezone(&Utc)))
+}
+
+// the time is written in full, so a file modified within the same second as
+// the start of the run is sorted by the next one.
+pub(crate) fn write_last_run(path: &Path, time: DateTime<Utc>) -> Result<()> {
+    let line = time.to_rfc3339_opts(SecondsFormat::Nanos, true);
+    fs::write(path, format!("{}\n", line)).context("Failed to write the last run file")
+}
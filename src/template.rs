@@ -0,0 +1,341 @@
+use crate::camera::UNKNOWN_CAMERA;
+use crate::group::GroupResolver;
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, NaiveDate};
+use clap::ArgEnum;
+use colored::*;
+use log::warn;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+};
+use walkdir::DirEntry;
+
+/// The default layout at the target - year/month name/day. eg. 2022/January/9
+pub static DEFAULT_TEMPLATE: &str = "%Y/%B/%-d";
+
+// a file with this name in a source directory overrides the template for its subtree.
+pub(crate) static TEMPLATE_FILE_NAME: &str = ".photosort-template";
+
+/// How the month name of a template, `%B`, is written in the folder names.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Debug)]
+pub enum MonthFormat {
+    /// The English name, eg. May.
+    Name,
+    /// The two digit number, eg. 05.
+    Number,
+    /// The abbreviated English name, eg. Sep.
+    Short,
+    /// The name in the language of the LC_ALL, LC_TIME or LANG environment
+    /// variable, eg. Mai for de_DE. Falls back to the English name.
+    Locale,
+}
+
+// the month names by language code, for the languages photosort knows.
+#[rustfmt::skip]
+static MONTH_NAMES: [(&str, [&str; 12]); 10] = [
+    ("da", ["januar", "februar", "marts", "april", "maj", "juni", "juli", "august", "september", "oktober", "november", "december"]),
+    ("de", ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"]),
+    ("es", ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"]),
+    ("fi", ["tammikuu", "helmikuu", "maaliskuu", "huhtikuu", "toukokuu", "kesäkuu", "heinäkuu", "elokuu", "syyskuu", "lokakuu", "marraskuu", "joulukuu"]),
+    ("fr", ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"]),
+    ("it", ["gennaio", "febbraio", "marzo", "aprile", "maggio", "giugno", "luglio", "agosto", "settembre", "ottobre", "novembre", "dicembre"]),
+    ("nl", ["januari", "februari", "maart", "april", "mei", "juni", "juli", "augustus", "september", "oktober", "november", "december"]),
+    ("pl", ["styczeń", "luty", "marzec", "kwiecień", "maj", "czerwiec", "lipiec", "sierpień", "wrzesień", "październik", "listopad", "grudzień"]),
+    ("pt", ["janeiro", "fevereiro", "março", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro"]),
+    ("sv", ["januari", "februari", "mars", "april", "maj", "juni", "juli", "augusti", "september", "oktober", "november", "december"]),
+];
+
+// the month names for the locale of the environment, eg. de_DE.UTF-8 picks de.
+fn locale_month_names() -> Option<&'static [&'static str; 12]> {
+    static NAMES: OnceLock<Option<&'static [&'static str; 12]>> = OnceLock::new();
+    *NAMES.get_or_init(|| {
+        let locale = ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())?;
+        let language = locale.split(['_', '.', '@', '-']).next()?.to_lowercase();
+        MONTH_NAMES
+            .iter()
+            .find(|(code, _)| *code == language)
+            .map(|(_, names)| names)
+    })
+}
+
+// rewrites the month name of the template in the given format, leaving an
+// escaped %%B alone.
+fn month_template(template: &str, month_format: MonthFormat, month: u32) -> Cow<'_, str> {
+    let replacement = match month_format {
+        MonthFormat::Name => return Cow::Borrowed(template),
+        MonthFormat::Number => "%m",
+        MonthFormat::Short => "%b",
+        MonthFormat::Locale => match locale_month_names() {
+            Some(names) => names[month as usize - 1],
+            None => return Cow::Borrowed(template),
+        },
+    };
+    let mut rewritten = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rewritten.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('B') => rewritten.push_str(replacement),
+            Some(next) => {
+                rewritten.push('%');
+                rewritten.push(next);
+            }
+            None => rewritten.push('%'),
+        }
+    }
+    Cow::Owned(rewritten)
+}
+
+// the tokens of a rename template, see [`FileNaming::rename`].
+static RENAME_TOKENS: [&str; 8] = [
+    "date", "year", "month", "day", "camera", "name", "ext", "counter",
+];
+
+/// How the files are named at the target, see [`get_target_path`]. By default
+/// a file keeps its name.
+#[derive(Clone, Copy, Default)]
+pub struct FileNaming<'a> {
+    /// Added to the start of the file name as a strftime pattern, eg. `%Y-%m-%d_`.
+    pub prefix: Option<&'a str>,
+    /// Added to the end of the file name, before the extension.
+    pub suffix: Option<&'a str>,
+    /// Replaces the whole file name, eg. `{date}_{camera}_{counter}.{ext}`. The
+    /// prefix and suffix don't apply to it.
+    pub rename: Option<&'a str>,
+    /// The camera model for the `{camera}` token.
+    pub camera: Option<&'a str>,
+    /// The number for the `{counter}` token, from 1.
+    pub counter: u32,
+}
+
+/// The folder under the target root which the layout of a file starts from,
+/// with a folder named by each of the resolvers in turn, eg. `Canon EOS 40D`
+/// for a [`CameraGroup`](crate::CameraGroup).
+pub fn group_root(
+    entry: &DirEntry,
+    target_root: &Path,
+    resolvers: &[&dyn GroupResolver],
+) -> PathBuf {
+    let mut root = target_root.to_path_buf();
+    for resolver in resolvers {
+        root.push(resolver.folder(entry));
+    }
+    root
+}
+
+/// The path at the target for a file with the given date and template. The
+/// folders of the resolvers go before the layout, see [`group_root`]. The
+/// prefix and suffix are added to the file name, the suffix before the extension.
+pub fn get_target_path(
+    entry: &DirEntry,
+    file_date: NaiveDate,
+    target_root: &Path,
+    resolvers: &[&dyn GroupResolver],
+    template: &str,
+    month_format: MonthFormat,
+    naming: &FileNaming,
+) -> PathBuf {
+    let template = month_template(template, month_format, file_date.month());
+    let mut final_path = group_root(entry, target_root, resolvers);
+    final_path.push(file_date.format(&template).to_string());
+    if let Some(rename) = naming.rename {
+        final_path.push(render_name(rename, entry, file_date, naming));
+        return final_path;
+    }
+    if naming.prefix.is_none() && naming.suffix.is_none() {
+        final_path.push(entry.file_name());
+        return final_path;
+    }
+
+    let source = Path::new(entry.file_name());
+    let mut file_name = OsString::new();
+    if let Some(prefix) = naming.prefix {
+        file_name.push(file_date.format(prefix).to_string());
+    }
+    file_name.push(source.file_stem().unwrap_or(entry.file_name()));
+    if let Some(suffix) = naming.suffix {
+        file_name.push(file_date.format(suffix).to_string());
+    }
+    if let Some(extension) = source.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    final_path.push(file_name);
+
+    final_path
+}
+
+// fills in the tokens of a rename template, eg. {date}_{counter}.{ext} becomes
+// 2008-05-30_1.jpg. The extension is the one of the source, without its dot.
+fn render_name(
+    rename: &str,
+    entry: &DirEntry,
+    file_date: NaiveDate,
+    naming: &FileNaming,
+) -> OsString {
+    let source = Path::new(entry.file_name());
+    let mut name = OsString::new();
+    let mut rest = rename;
+    while let Some(start) = rest.find('{') {
+        name.push(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let token = &rest[start + 1..start + end];
+        match token {
+            "date" => name.push(file_date.format("%Y-%m-%d").to_string()),
+            "year" => name.push(file_date.format("%Y").to_string()),
+            "month" => name.push(file_date.format("%m").to_string()),
+            "day" => name.push(file_date.format("%d").to_string()),
+            "camera" => name.push(naming.camera.unwrap_or(UNKNOWN_CAMERA)),
+            "name" => name.push(source.file_stem().unwrap_or_default()),
+            "ext" => name.push(source.extension().unwrap_or_default()),
+            "counter" => name.push(naming.counter.to_string()),
+            _ => name.push(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    name.push(rest);
+    name
+}
+
+/// Checks that a rename template only has known tokens and stays within the
+/// file name.
+pub(crate) fn validate_rename(rename: &str) -> Result<()> {
+    let mut rest = rename;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("The template {} has an unclosed {{", rename))?;
+        let token = &rest[start + 1..start + end];
+        if !RENAME_TOKENS.contains(&token) {
+            anyhow::bail!(
+                "Unknown token {{{}}} in {}. The tokens are {}",
+                token,
+                rename,
+                RENAME_TOKENS
+                    .map(|token| format!("{{{}}}", token))
+                    .join(", ")
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if rename.contains(['/', '\\']) {
+        anyhow::bail!("The template {} must not contain a path separator", rename);
+    }
+    if matches!(rename.trim(), "" | "." | "..") {
+        anyhow::bail!("The template {} is not a file name", rename);
+    }
+    Ok(())
+}
+
+/// Checks that a file name prefix or suffix is a valid strftime pattern which
+/// stays within the file name.
+pub(crate) fn validate_name_affix(affix: &str) -> Result<()> {
+    if StrftimeItems::new(affix).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("Invalid pattern {}", affix);
+    }
+    let sample = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .unwrap()
+        .format(affix)
+        .to_string();
+    if sample.contains(['/', '\\']) {
+        anyhow::bail!("The pattern {} must not contain a path separator", affix);
+    }
+    Ok(())
+}
+
+/// Resolves the template for a source directory. The nearest `.photosort-template`
+/// file at or above the directory wins, falling back to the layout of the run.
+pub(crate) struct TemplateResolver {
+    source_root: PathBuf,
+    default: String,
+    cache: HashMap<PathBuf, String>,
+}
+
+impl TemplateResolver {
+    pub(crate) fn new(source_root: &Path, default: &str) -> Self {
+        TemplateResolver {
+            source_root: source_root.to_path_buf(),
+            default: default.to_string(),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn resolve(&mut self, dir: &Path) -> String {
+        if let Some(template) = self.cache.get(dir) {
+            return template.clone();
+        }
+        let inherited = match dir.parent() {
+            Some(parent) if dir != self.source_root && dir.starts_with(&self.source_root) => {
+                self.resolve(parent)
+            }
+            _ => self.default.clone(),
+        };
+        let template_file = dir.join(TEMPLATE_FILE_NAME);
+        let template = if template_file.is_file() {
+            match read_template_file(&template_file) {
+                Ok(template) => template,
+                Err(err) => {
+                    warn!(
+                        "{} Ignoring the template file {} - [{}]",
+                        "Warning.".yellow(),
+                        template_file.display(),
+                        err
+                    );
+                    inherited
+                }
+            }
+        } else {
+            inherited
+        };
+        self.cache.insert(dir.to_path_buf(), template.clone());
+        template
+    }
+}
+
+fn read_template_file(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path).context("Failed to read the template file")?;
+    let template = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .context("The template file is empty")?;
+    validate_template(template)?;
+    Ok(template.to_string())
+}
+
+/// Checks that a template is a valid strftime pattern which yields a path
+/// relative to, and contained within, the target directory.
+pub fn validate_template(template: &str) -> Result<()> {
+    if StrftimeItems::new(template).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("Invalid template {}", template);
+    }
+    let sample = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .unwrap()
+        .format(template)
+        .to_string();
+    let escapes_root = Path::new(&sample)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)));
+    if escapes_root {
+        anyhow::bail!(
+            "The template {} must be a relative path without '..' components",
+            template
+        );
+    }
+    Ok(())
+}
@@ -1,12 +1,19 @@
 use assert_cmd::prelude::*;
 use assert_fs::{
     assert::PathAssert,
-    fixture::{FileTouch, PathChild},
+    fixture::{FileTouch, FileWriteBin, FileWriteFile, FileWriteStr, PathChild, PathCreateDir},
 };
+use chrono::NaiveDate;
 use filetime::FileTime;
-use photosort::Summary;
-use predicates::prelude::predicate;
-use std::{env, fs, path::PathBuf, process::Command, time::Duration};
+use photosort::{FilterReason, GroupBy, HistoryEntry, SortEvent, SortOptions, Summary};
+use predicates::prelude::{predicate, PredicateBooleanExt};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 // the files in the data folder correspond to the following files
 // from the exif-samples GitHub repo - https://github.com/ianare/exif-samples
@@ -14,6 +21,43 @@ use std::{env, fs, path::PathBuf, process::Command, time::Duration};
 // jpeg with valid exif - exif-samples/jpg/Canon_40D.jpg
 // jpeg with no exif - exif-samples/jpg/invalid/image00971.jpg
 // jpeg with no datetimeoriginal tag in exif - exif-samples/jpg/Canon_40D_photoshop_import.jpg
+// it only has the DateTime tag, set to 31-Jul-2008.
+//
+// the remaining files are the valid exif jpeg with its exif replaced by hand written tags.
+//
+// jpeg from scanner - make EPSON, model Perfection V600, original 4-Mar-2021, digitized 7-Aug-2019
+//
+// heic with valid exif - a HEIF container holding only an exif item, original 15-Jul-2021
+//
+// the raw files hold only the exif of the camera.
+//
+// dng raw with valid exif - a plain tiff, original 2-Nov-2019
+// orf raw with valid exif - a tiff with the olympus magic number, original 21-Apr-2018
+// raf raw with valid exif - a fujifilm header and a jpeg with exif, original 29-Feb-2020
+//
+// video with creation time - a hand written mp4 with only a movie header, created 15-Aug-2020
+//
+// jpeg with gps - original 12-Jun-2019, taken at 48.8584 N 2.2945 E in Paris
+//
+// jpeg with offset - original 31-Dec-2020 23:30, at an offset of +00:00
+//
+// raw with xmp sidecar - a cr2 file without exif, next to an xmp file with the
+// original date of 23-Sep-2017 and a later create date
+//
+// png with valid exif - a 1x1 png with an eXIf chunk, original 18-Apr-2016
+// png with no exif - the same png without the eXIf chunk
+//
+// jpeg with epoch date - make Canon, model Canon EOS 5D, a corrupt original of 1-Jan-1970
+//
+// jpeg with orientation - the landscape jpeg turned a quarter by its orientation
+// tag of 6, so it's shown as a portrait, original 10-Mar-2023
+// tiff with valid exif - a tiff holding only the exif, original 3-Oct-2015
+//
+// png from takeout - the png without exif, next to the json sidecar of a google
+// takeout export with the photo taken time of 19-Aug-2017 23:30 UTC
+//
+// jpeg with lens - make Canon, model Canon EOS 40D, lens model
+// EF-S17-85mm f/4-5.6 IS USM, original 22-Sep-2012
 
 #[test]
 fn cli_test() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,30 +74,64 @@ fn cli_test() -> Result<(), Box<dyn std::error::Error>> {
         error_count: 0,
         skipped_count: 0,
         duplicate_count: 0,
-        copy_count: 4,
-        copied_bytes: 181870,
+        copy_count: 24,
+        copied_bytes: 212260,
         duration: Duration::new(0, 0),
         duplicate_files: Vec::new(),
         errored_files: Vec::new(),
         exif_error_count: 0,
         exif_errored_files: Vec::new(),
+        ..Summary::init()
     };
 
+    // a png without exif is common, so it's not warned about.
     cmd.assert()
         .success()
         .stdout(predicate::str::contains(strip_timestamp_from_summary(
             expected_summary,
-        )));
+        )))
+        .stderr(predicate::str::contains("jpeg_with_no_exif.jpg"))
+        .stderr(predicate::str::contains("png_with_no_exif.png").not());
 
     let expected_paths = vec![
         // jpeg with valid exif
         r"2008/May/30/jpeg_with_valid_exif.jpg",
         // jpeg with no exif, target path based on the file modified time.
         r"2022/January/6/jpeg_with_no_exif.jpg",
-        // jpeg with valid exif but no datetime, target path based on the file modified time.
-        r"2022/January/6/jpeg_with_valid_exif_but_no_datetimeoriginal.jpg",
+        // jpeg with valid exif but no datetimeoriginal, target path based on the DateTime tag.
+        r"2008/July/31/jpeg_with_valid_exif_but_no_datetimeoriginal.jpg",
         // non image file, target path based on the file modified time.
         r"2022/January/6/non_image_file.txt",
+        // jpeg from a scanner, target path based on the original date by default.
+        r"2021/March/4/jpeg_from_scanner.jpg",
+        // heic with valid exif
+        r"2021/July/15/heic_with_valid_exif.heic",
+        // raw files with valid exif
+        r"2019/November/2/raw_with_valid_exif.dng",
+        r"2018/April/21/raw_with_valid_exif.orf",
+        r"2020/February/29/raw_with_valid_exif.raf",
+        // video with a creation time in the movie header.
+        r"2020/August/15/video_with_creation_time.mp4",
+        // jpeg with gps, sorted by its date unless sorting by location.
+        r"2019/June/12/jpeg_with_gps.jpg",
+        // jpeg with an offset, sorted by its local date unless given a time zone.
+        r"2020/December/31/jpeg_with_offset.jpg",
+        // raw file without exif, target path based on the original date in its xmp sidecar.
+        r"2017/September/23/raw_with_xmp_sidecar.cr2",
+        // the sidecar itself has no date of its own.
+        r"2022/January/6/raw_with_xmp_sidecar.xmp",
+        // png and tiff with valid exif
+        r"2016/April/18/png_with_valid_exif.png",
+        r"2015/October/3/tiff_with_valid_exif.tif",
+        // png without exif, target path based on the file modified time.
+        r"2022/January/6/png_with_no_exif.png",
+        // jpeg with a corrupt date, sorted by it unless given a skew.
+        r"1970/January/1/jpeg_with_epoch_date.jpg",
+        // jpeg shown as a portrait, sorted by its date unless sorting by orientation.
+        r"2023/March/10/jpeg_with_orientation.jpg",
+        // png from a takeout export, sorted by its sidecar only with --takeout.
+        r"2022/January/6/png_from_takeout.png",
+        r"2022/January/6/png_from_takeout.png.json",
     ];
 
     for path in &expected_paths {
@@ -63,7 +141,7 @@ fn cli_test() -> Result<(), Box<dyn std::error::Error>> {
     let expected_summary_second_run = Summary {
         scan_error_count: 0,
         error_count: 0,
-        skipped_count: 4,
+        skipped_count: 24,
         duplicate_count: 0,
         copy_count: 0,
         copied_bytes: 0,
@@ -72,6 +150,7 @@ fn cli_test() -> Result<(), Box<dyn std::error::Error>> {
         errored_files: Vec::new(),
         exif_error_count: 0,
         exif_errored_files: Vec::new(),
+        ..Summary::init()
     };
 
     // run the same command again. all files should get skipped.
@@ -110,6 +189,7 @@ fn cli_test() -> Result<(), Box<dyn std::error::Error>> {
         errored_files: Vec::new(),
         exif_error_count: 0,
         exif_errored_files: Vec::new(),
+        ..Summary::init()
     };
 
     cmd.assert()
@@ -142,11 +222,3860 @@ fn set_default_modified_time(path: PathBuf) -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
+// leaves out the duration, which differs from run to run, and the scanned files.
 fn strip_timestamp_from_summary(summary: Summary) -> String {
     summary
         .display()
         .lines()
-        .skip(3)
+        .skip(4)
         .collect::<Vec<&str>>()
         .join("\n")
 }
+
+#[test]
+fn template_file_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+
+    // the root uses the default template while the scans subtree overrides it.
+    temp_source
+        .child("jpeg_with_valid_exif.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source
+        .child("scans/.photosort-template")
+        .write_str("%Y/%m\n")?;
+    temp_source
+        .child("scans/nested/scan.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert().success();
+
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/05/scan.jpg")
+        .assert(predicate::path::exists());
+    // the template file itself is never sorted.
+    temp_target
+        .child("2022/January/6/.photosort-template")
+        .assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[test]
+fn mapping_csv_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let mapping = temp_dir.child("mapping.csv");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--mapping-csv").arg(mapping.path());
+    cmd.assert().success();
+
+    let contents = fs::read_to_string(mapping.path())?;
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines[0], "old_path,new_path");
+    // one row for each copied file.
+    assert_eq!(lines.len(), 25);
+
+    let source = fs::canonicalize("tests/data/jpeg_with_valid_exif.jpg")?;
+    let target = fs::canonicalize(
+        temp_target
+            .child("2008/May/30/jpeg_with_valid_exif.jpg")
+            .path(),
+    )?;
+    let expected = format!("{},{}", source.display(), target.display());
+    assert!(lines.contains(&expected.as_str()));
+
+    Ok(())
+}
+
+#[test]
+fn manifest_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source.child("present.txt").write_str("present")?;
+    temp_source.child("clash.txt").write_str("clash")?;
+    for name in ["present.txt", "clash.txt"] {
+        set_default_modified_time(temp_source.child(name).path().to_path_buf())?;
+    }
+    temp_target
+        .child("2022/January/6/present.txt")
+        .write_str("present")?;
+    temp_target
+        .child("2022/January/6/clash.txt")
+        .write_str("a different file")?;
+    let manifest = temp_dir.child("manifest.csv");
+    // a stale manifest is replaced.
+    manifest.write_str("stale\n")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--manifest").arg(manifest.path());
+    cmd.assert().success();
+
+    let mut reader = csv::Reader::from_path(manifest.path())?;
+    assert_eq!(
+        reader.headers()?,
+        vec![
+            "source_path",
+            "target_path",
+            "action",
+            "bytes",
+            "date_source"
+        ]
+    );
+    let mut rows = reader
+        .records()
+        .collect::<Result<Vec<csv::StringRecord>, _>>()?;
+    // one row for each source file.
+    assert_eq!(rows.len(), 3);
+    rows.sort_by(|a, b| a[0].cmp(&b[0]));
+
+    let expected = [
+        (
+            "clash.txt",
+            "2022/January/6/clash.txt",
+            "duplicate",
+            "5",
+            "mtime",
+        ),
+        (
+            "photo.jpg",
+            "2008/May/30/photo.jpg",
+            "copied",
+            "7958",
+            "exif",
+        ),
+        (
+            "present.txt",
+            "2022/January/6/present.txt",
+            "skipped",
+            "7",
+            "mtime",
+        ),
+    ];
+    for (row, (source, target, action, bytes, date_source)) in rows.iter().zip(expected) {
+        assert_eq!(&row[0], temp_source.child(source).path().to_str().unwrap());
+        assert_eq!(&row[1], temp_target.child(target).path().to_str().unwrap());
+        assert_eq!(&row[2], action);
+        assert_eq!(&row[3], bytes);
+        assert_eq!(&row[4], date_source);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn relative_paths_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // the source is under the target, yet its files are recorded from the source.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    let temp_source = temp_target.child("incoming");
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source.child("clash.txt").write_str("clash")?;
+    set_default_modified_time(temp_source.child("clash.txt").path().to_path_buf())?;
+    temp_target
+        .child("2022/January/6/clash.txt")
+        .write_str("a different file")?;
+    let manifest = temp_dir.child("manifest.csv");
+    let checksums = temp_dir.child("SHA256SUMS");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--manifest").arg(manifest.path());
+    cmd.arg("--checksum-manifest").arg(checksums.path());
+    cmd.arg("--relative-paths");
+    // the printed summary keeps the whole paths.
+    cmd.assert().success().stdout(predicate::str::contains(
+        temp_source.child("clash.txt").path().to_str().unwrap(),
+    ));
+
+    let mut rows = csv::Reader::from_path(manifest.path())?
+        .records()
+        .collect::<Result<Vec<csv::StringRecord>, _>>()?;
+    rows.sort_by(|a, b| a[0].cmp(&b[0]));
+    let expected = [
+        ("clash.txt", "2022/January/6/clash.txt"),
+        ("photo.jpg", "2008/May/30/photo.jpg"),
+    ];
+    assert_eq!(rows.len(), expected.len());
+    for (row, (source, target)) in rows.iter().zip(expected) {
+        assert_eq!(&row[0], source);
+        assert_eq!(&row[1], target);
+    }
+    assert!(fs::read_to_string(checksums.path())?.ends_with("  2008/May/30/photo.jpg\n"));
+
+    // while the json is shared without them.
+    let output = cmd.arg("--format").arg("json").output()?;
+    assert!(output.status.success());
+    let summary: Summary = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(summary.duplicate_files, vec![PathBuf::from("clash.txt")]);
+
+    Ok(())
+}
+
+#[test]
+fn ignore_file_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child(".photosortignore")
+        .write_str("*.tmp\nthumbnails/\n")?;
+    for name in ["photo.jpg", "nested/photo_copy.jpg", "thumbnails/thumb.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    temp_source.child("upload.tmp").write_str("partial")?;
+    temp_source.child("nested/cache.tmp").write_str("partial")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+
+    // the ignored files are only counted as filtered, along with those of an ignored directory.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files totalling"))
+        .stdout(predicate::str::contains("  3 matched by the ignore file"))
+        .stdout(predicate::str::contains("Skipped").not());
+
+    temp_target
+        .child("2008/May/30/photo_copy.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/thumb.jpg")
+        .assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[test]
+fn own_files_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child("jpeg_with_valid_exif.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    // the mapping file lives in the source and is given as a relative path.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.current_dir(temp_source.path());
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--mapping-csv").arg("mapping.csv");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"));
+
+    temp_source
+        .child("mapping.csv")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn max_bytes_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // two files of 7958 bytes each. only one of them fits in the budget.
+    for name in ["first.jpg", "second.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--max-bytes").arg("10KB");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains(
+            "Stopped copying after reaching the byte budget",
+        ))
+        .stdout(predicate::str::contains(
+            "1 left for the next run after reaching the byte budget",
+        ));
+
+    // the next run skips the copied file and copies the remaining one.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    // --max-total is the same budget, and only whole files are copied.
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--max-total").arg("10KB").arg("--jobs").arg("2");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains(
+            "1 left for the next run after reaching the byte budget",
+        ));
+    let copied =
+        ["first.jpg", "second.jpg"].map(|name| temp_target.child(format!("2008/May/30/{}", name)));
+    assert_eq!(copied.iter().filter(|file| file.path().exists()).count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn date_tag_auto_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--date-tag").arg("auto");
+    cmd.assert().success();
+
+    // scans use the digitized date while cameras keep using the original date.
+    temp_dir
+        .child("2019/August/7/jpeg_from_scanner.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn history_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--history");
+    cmd.arg("--history-limit").arg("2");
+    for _ in 0..3 {
+        cmd.assert().success();
+    }
+
+    // only the last two runs are kept and both skipped every file.
+    let contents = fs::read_to_string(temp_dir.child(".photosort-history.jsonl").path())?;
+    let entries = contents
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<HistoryEntry>, _>>()?;
+    assert_eq!(entries.len(), 2);
+    for entry in &entries {
+        assert_eq!(entry.copy_count, 0);
+        assert_eq!(entry.skipped_count, 24);
+    }
+    assert!(entries[0].timestamp <= entries[1].timestamp);
+
+    Ok(())
+}
+
+#[test]
+fn summary_out_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let summary_file = temp_dir.child("photosort.log");
+
+    // the colours are forced on the terminal but left out of the file.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.env("CLICOLOR_FORCE", "1");
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--summary-out").arg(summary_file.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b["));
+    cmd.assert().success();
+
+    let contents = fs::read_to_string(summary_file.path())?;
+    assert!(!contents.contains('\x1b'));
+    // the summaries of both runs are kept.
+    assert_eq!(contents.matches("photosort run at ").count(), 2);
+    assert_eq!(contents.matches("\nCompleted in ").count(), 2);
+    assert!(contents.contains("Copied 24 files totalling"));
+    assert!(contents.contains("Skipped copying 24 files"));
+
+    Ok(())
+}
+
+#[test]
+fn quiet_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--quiet");
+
+    let expected_summary = Summary {
+        copy_count: 24,
+        copied_bytes: 212260,
+        ..Summary::init()
+    };
+
+    // the warnings for the files without exif still go to stderr.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(strip_timestamp_from_summary(
+            expected_summary,
+        )))
+        .stdout(predicate::str::is_match("(?m)^Copied .* to ")?.not())
+        .stderr(predicate::str::contains("jpeg_with_no_exif.jpg"));
+
+    Ok(())
+}
+
+#[test]
+fn log_level_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // only the errors are logged, the warning for the file without exif is left out.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.env("RUST_LOG", "error");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files"))
+        .stderr(predicate::str::contains("Warning.").not());
+
+    Ok(())
+}
+
+#[test]
+fn verbose_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("-v");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match(
+            r"Dated tests/data.jpeg_with_valid_exif\.jpg is from 2008-05-30 by its exif",
+        )?)
+        .stdout(predicate::str::is_match(
+            r"Dated tests/data.jpeg_with_no_exif\.jpg is from 2022-01-06 by its mtime",
+        )?)
+        .stdout(predicate::str::is_match(
+            r"Dated tests/data.raw_with_xmp_sidecar\.cr2 is from 2017-09-23 by its xmp",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn progress_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // stdout is not a terminal here so the bar is left out.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--progress");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"))
+        .stderr(predicate::str::contains("/23 files").not());
+
+    Ok(())
+}
+
+#[test]
+fn extension_filter_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // the extensions are matched regardless of case.
+    let runs = [
+        (
+            vec!["--include-ext", "jpg,HEIC"],
+            12,
+            "2021/July/15/heic_with_valid_exif.heic",
+            "2022/January/6/non_image_file.txt",
+        ),
+        (
+            vec!["--exclude-ext", "JPG"],
+            13,
+            "2022/January/6/non_image_file.txt",
+            "2008/May/30/jpeg_with_valid_exif.jpg",
+        ),
+        (
+            vec!["--include-ext", "jpg,txt", "--exclude-ext", "txt"],
+            11,
+            "2008/May/30/jpeg_with_valid_exif.jpg",
+            "2022/January/6/non_image_file.txt",
+        ),
+    ];
+    for (filter, copy_count, sorted, left_out) in runs {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.args(&filter);
+
+        // the files left out are not counted as skipped.
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "Copied {} files totalling",
+                copy_count
+            )))
+            .stdout(predicate::str::contains("Skipped").not());
+
+        temp_dir.child(sorted).assert(predicate::path::exists());
+        temp_dir.child(left_out).assert(predicate::path::missing());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn filtered_reasons_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source.child(".photosortignore").write_str("skip/\n")?;
+    temp_source
+        .child("scan.jpg")
+        .write_file(Path::new("tests/data/jpeg_from_scanner.jpg"))?;
+    temp_source
+        .child("old.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source
+        .child("skip/ignored.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source.child("notes.txt").write_str("notes")?;
+    temp_source.child("tiny.jpg").write_str("tiny")?;
+
+    let summary = photosort::sort(SortOptions {
+        exclude_ext: vec!["txt".to_string()],
+        min_size: Some(1000),
+        after: NaiveDate::from_ymd_opt(2010, 1, 1),
+        ..SortOptions::new(temp_source.path(), temp_target.path())
+    })?;
+
+    // every file lands in exactly one of the counts.
+    assert_eq!(summary.copy_count, 1);
+    assert_eq!(summary.filtered_count, 4);
+    assert_eq!(summary.file_count(), 5);
+    for reason in [
+        FilterReason::Extension,
+        FilterReason::IgnoreFile,
+        FilterReason::DateRange,
+        FilterReason::Size,
+    ] {
+        assert_eq!(summary.filtered_reasons[&reason], 1);
+    }
+    assert!(summary
+        .display()
+        .contains("Filtered 4 files which were left out of the run"));
+
+    Ok(())
+}
+
+#[test]
+fn depth_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    for name in ["top.jpg", "backup/middle.jpg", "backup/old/deep.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    let sort = |depths: &[&str]| -> Result<assert_fs::TempDir, Box<dyn std::error::Error>> {
+        let temp_target = assert_fs::TempDir::new()?;
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.args(depths);
+        cmd.assert().success();
+        Ok(temp_target)
+    };
+
+    // the files below the maximum depth are not sorted.
+    let temp_target = sort(&["--max-depth", "2"])?;
+    for (name, sorted) in [("top.jpg", true), ("middle.jpg", true), ("deep.jpg", false)] {
+        let path = temp_target.child(format!("2008/May/30/{}", name));
+        assert_eq!(path.path().exists(), sorted, "{}", name);
+    }
+
+    // and neither are the files above the minimum depth.
+    let temp_target = sort(&["--min-depth", "2", "--max-depth", "2"])?;
+    for (name, sorted) in [
+        ("top.jpg", false),
+        ("middle.jpg", true),
+        ("deep.jpg", false),
+    ] {
+        let path = temp_target.child(format!("2008/May/30/{}", name));
+        assert_eq!(path.path().exists(), sorted, "{}", name);
+    }
+
+    // a depth of 0 is the source directory itself, which holds no files.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_source.path());
+    cmd.arg("--max-depth").arg("0");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "The --max-depth must be at least 1",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn date_range_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // every run has a file exactly on each of its bounds.
+    let runs = [
+        (
+            vec!["--after", "2021-03-04"],
+            9,
+            "2021/March/4/jpeg_from_scanner.jpg",
+        ),
+        (
+            vec!["--before", "2008-07-31"],
+            3,
+            "2008/July/31/jpeg_with_valid_exif_but_no_datetimeoriginal.jpg",
+        ),
+        (
+            vec!["--after", "2019-11-02", "--before", "2020-02-29"],
+            2,
+            "2019/November/2/raw_with_valid_exif.dng",
+        ),
+    ];
+    for (range, copy_count, sorted) in runs {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.args(&range);
+
+        // the files outside of the range are reported on their own line.
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "Copied {} files totalling",
+                copy_count
+            )))
+            .stdout(predicate::str::contains(format!(
+                "Filtered {0} files which were left out of the run - \n  {0} captured outside of the date range",
+                24 - copy_count
+            )));
+
+        temp_dir.child(sorted).assert(predicate::path::exists());
+    }
+
+    // an empty range is rejected.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--after").arg("2022-01-07");
+    cmd.arg("--before").arg("2022-01-06");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn json_format_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--format").arg("json");
+
+    // the whole of stdout is the summary, without any per file lines.
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let summary: Summary = serde_json::from_slice(&output)?;
+    assert_eq!(summary.copy_count, 24);
+    assert_eq!(summary.copied_bytes, 212260);
+    assert_eq!(summary.skipped_count, 0);
+    assert!(summary.errored_files.is_empty());
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let summary: Summary = serde_json::from_slice(&output)?;
+    assert_eq!(summary.copy_count, 0);
+    assert_eq!(summary.skipped_count, 24);
+
+    Ok(())
+}
+
+#[test]
+fn diff_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    // one file is already present and another has a different file with the same name.
+    temp_dir
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_dir
+        .child("2022/January/6/non_image_file.txt")
+        .write_str("different contents")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--diff").arg("--diff-list");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("New 22 files totalling"))
+        .stdout(predicate::str::contains("Present 1 files"))
+        .stdout(predicate::str::contains("Collision 1 files"))
+        .stdout(predicate::str::contains("Filtered 0 files"))
+        .stdout(predicate::str::is_match(
+            r"PRESENT +tests/data.jpeg_with_valid_exif\.jpg",
+        )?)
+        .stdout(predicate::str::is_match(
+            r"COLLISION +tests/data.non_image_file\.txt",
+        )?);
+
+    // nothing is copied.
+    temp_dir
+        .child("2022/January/6/jpeg_with_no_exif.jpg")
+        .assert(predicate::path::missing());
+
+    // files beyond the byte budget are filtered.
+    cmd.arg("--max-bytes").arg("100B");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("New 0 files"))
+        .stdout(predicate::str::contains("Filtered 22 files"));
+
+    Ok(())
+}
+
+#[test]
+fn diff_counts_every_file_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // the files left out by the walk are filtered like those left out later.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--dry-run").arg("--diff");
+    cmd.arg("--exclude-ext").arg("txt,json,xmp");
+    cmd.arg("--max-size").arg("100KB");
+    let output = cmd.arg("--format").arg("json").output()?;
+    assert!(output.status.success());
+
+    let report: photosort::DiffReport = serde_json::from_slice(&output.stdout)?;
+    let walked = fs::read_dir("tests/data")?.count() as u32;
+    assert_eq!(
+        report.new_count + report.present_count + report.collision_count + report.filtered_count,
+        walked
+    );
+    // the three excluded files and the one over the size.
+    assert_eq!(report.filtered_count, 4);
+
+    Ok(())
+}
+
+#[test]
+fn dry_run_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--dry-run");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Would copy"))
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+
+    // nothing is written to the target.
+    assert_eq!(fs::read_dir(temp_dir.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn move_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    for name in ["moved.jpg", "present.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    temp_target
+        .child("2008/May/30/present.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--move");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Moved 1 files totalling"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    temp_target
+        .child("2008/May/30/moved.jpg")
+        .assert(predicate::path::exists());
+    temp_source
+        .child("moved.jpg")
+        .assert(predicate::path::missing());
+    // a file which is already present at the target is never removed from the source.
+    temp_source
+        .child("present.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn symlink_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let source = temp_source.child("photo.jpg");
+    source.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--symlink");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Symlinked 1 files"));
+
+    let target = temp_target.child("2008/May/30/photo.jpg");
+    assert!(fs::symlink_metadata(target.path())?
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::canonicalize(target.path())?,
+        fs::canonicalize(source.path())?
+    );
+
+    // a link which resolves back to the source is already present.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn relative_symlink_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // the source and the target are moved together, as a library would be.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let library = temp_dir.child("library");
+    let source = library.child("source/photo.jpg");
+    source.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    library.child("target").create_dir_all()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(library.child("source").path());
+    cmd.arg("--target-dir").arg(library.child("target").path());
+    cmd.arg("--symlink").arg("--relative-symlink");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Symlinked 1 files"));
+
+    let link = fs::read_link(library.child("target/2008/May/30/photo.jpg").path())?;
+    assert_eq!(link, Path::new("../../../../source/photo.jpg"));
+
+    let moved = temp_dir.child("moved");
+    fs::rename(library.path(), moved.path())?;
+    let target = moved.child("target/2008/May/30/photo.jpg");
+    assert_eq!(
+        fs::canonicalize(target.path())?,
+        fs::canonicalize(moved.child("source/photo.jpg").path())?
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn relative_symlink_undo_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_target.create_dir_all()?;
+    let journal = temp_dir.child("journal.jsonl");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--symlink").arg("--relative-symlink");
+    cmd.arg("--journal").arg(journal.path());
+    cmd.assert().success();
+
+    // the relative link is matched against the absolute source of the journal.
+    let mut undo = Command::cargo_bin("photosort")?;
+    undo.arg("undo").arg(journal.path());
+    undo.assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 1 actions"));
+    temp_target
+        .child("2008/May/30/photo.jpg")
+        .assert(predicate::path::missing());
+    temp_source
+        .child("photo.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn hardlink_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    setup()?;
+
+    // both live in the same temp directory so they share a filesystem.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let source = temp_source.child("photo.jpg");
+    source.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--hardlink");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Hardlinked 1 files"));
+
+    let target = temp_target.child("2008/May/30/photo.jpg");
+    assert_eq!(
+        fs::metadata(target.path())?.ino(),
+        fs::metadata(source.path())?.ino()
+    );
+
+    // the existing link is never clobbered.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    Ok(())
+}
+
+#[test]
+fn preserve_mtime_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let source = Path::new("tests/data/jpeg_with_valid_exif.jpg");
+    let source_mtime = FileTime::from_last_modification_time(&fs::metadata(source)?);
+    for (preserve, target_dir) in [
+        (true, assert_fs::TempDir::new()?),
+        (false, assert_fs::TempDir::new()?),
+    ] {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(target_dir.path());
+        if !preserve {
+            cmd.arg("--no-preserve-mtime");
+        }
+        cmd.assert().success();
+
+        let target = target_dir.child("2008/May/30/jpeg_with_valid_exif.jpg");
+        let target_mtime = FileTime::from_last_modification_time(&fs::metadata(target.path())?);
+        let difference = (target_mtime.unix_seconds() - source_mtime.unix_seconds()).abs();
+        assert_eq!(difference <= 1, preserve);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn target_exists_rename_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // two different files with the same name and date.
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    for (dir, contents) in [("first", "first note"), ("second", "second, longer note")] {
+        let file = temp_source.child(format!("{}/note.txt", dir));
+        file.write_str(contents)?;
+        set_default_modified_time(file.path().to_path_buf())?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--target-exists").arg("rename");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files totalling"));
+
+    let mut contents = vec![
+        fs::read_to_string(temp_target.child("2022/January/6/note.txt").path())?,
+        fs::read_to_string(temp_target.child("2022/January/6/note (1).txt").path())?,
+    ];
+    contents.sort();
+    assert_eq!(contents, vec!["first note", "second, longer note"]);
+
+    // both are found again under their names on the next run.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 2 files"));
+    temp_target
+        .child("2022/January/6/note (2).txt")
+        .assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[test]
+fn video_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child("clip.MOV")
+        .write_file(Path::new("tests/data/video_with_creation_time.mp4"))?;
+    // a container without a movie header falls back to the modified time.
+    let no_header = temp_source.child("no_header.m4v");
+    no_header.write_binary(b"\0\0\0\x10ftypisom\0\0\x02\0")?;
+    set_default_modified_time(no_header.path().to_path_buf())?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("No moov box in the video"));
+
+    temp_target
+        .child("2020/August/15/clip.MOV")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2022/January/6/no_header.m4v")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn exit_code_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_source.child("note.txt").write_str("note")?;
+    set_default_modified_time(temp_source.child("note.txt").path().to_path_buf())?;
+    // a file where the year directory should be makes the copy fail.
+    temp_target.child("2008").write_str("in the way")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert().code(1);
+
+    // a clean run exits with 0 even when files are already present.
+    fs::remove_file(temp_target.child("2008").path())?;
+    cmd.assert().code(0);
+    cmd.assert().code(0);
+
+    // duplicates only fail a strict run.
+    temp_source
+        .child("note.txt")
+        .write_str("a different note")?;
+    set_default_modified_time(temp_source.child("note.txt").path().to_path_buf())?;
+    cmd.assert().code(0);
+    cmd.arg("--strict");
+    cmd.assert().code(2);
+
+    Ok(())
+}
+
+#[test]
+fn parallel_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // every file lands in the same new day directory.
+    for i in 0..32 {
+        temp_source
+            .child(format!("photo_{}.jpg", i))
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    // the same file in two directories maps to a single target.
+    for dir in ["first", "second"] {
+        temp_source
+            .child(format!("{}/same.jpg", dir))
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--jobs").arg("8");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 33 files"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    for i in 0..32 {
+        temp_target
+            .child(format!("2008/May/30/photo_{}.jpg", i))
+            .assert(predicate::path::exists());
+    }
+    temp_target
+        .child("2008/May/30/same.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn dedupe_by_hash_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // the same contents under two different names, only one of which is copied.
+    for name in ["first.jpg", "second.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    // a renamed copy which is already present at the target.
+    temp_source
+        .child("renamed.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+    temp_target
+        .child("elsewhere/old_name.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--dedupe-by-hash");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains("Skipped copying 2 files"))
+        .stderr(predicate::str::contains("same contents").count(2));
+
+    temp_target
+        .child("2022/January/6/renamed.jpg")
+        .assert(predicate::path::missing());
+    let copied = ["first.jpg", "second.jpg"]
+        .iter()
+        .filter(|name| {
+            temp_target
+                .child(format!("2008/May/30/{}", name))
+                .path()
+                .exists()
+        })
+        .count();
+    assert_eq!(copied, 1);
+
+    Ok(())
+}
+
+#[test]
+fn stable_by_hash_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let photo = temp_source.child("photo.jpg");
+    photo.write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+    set_default_modified_time(photo.to_path_buf())?;
+
+    let sort = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        Ok(cmd)
+    };
+    sort()?.assert().success();
+    temp_target
+        .child("2022/January/6/photo.jpg")
+        .assert(predicate::path::exists());
+
+    // the modified time drifts, which moves the photo to a different day.
+    filetime::set_file_mtime(photo.path(), FileTime::from_unix_time(1641582179, 0))?;
+    sort()?
+        .arg("--stable-by-hash")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 0 files"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+    temp_target
+        .child("2022/January/7/photo.jpg")
+        .assert(predicate::path::missing());
+
+    // without it the photo is sorted again under the new date.
+    sort()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"));
+    temp_target
+        .child("2022/January/7/photo.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn no_clobber_across_dates_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let photo = temp_source.child("photo.jpg");
+    photo.write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+    set_default_modified_time(photo.to_path_buf())?;
+    // the same contents under another name are a different file.
+    let copy = temp_source.child("copy.jpg");
+    copy.write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+    set_default_modified_time(copy.to_path_buf())?;
+
+    let sort = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.arg("--no-clobber-across-dates");
+        Ok(cmd)
+    };
+    sort()?.assert().success();
+    fs::remove_file(temp_target.child("2022/January/6/copy.jpg").path())?;
+
+    // the date of the photo changes, which moves it to a different day.
+    for file in [&photo, &copy] {
+        filetime::set_file_mtime(file.path(), FileTime::from_unix_time(1641582179, 0))?;
+    }
+    sort()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+    temp_target
+        .child("2022/January/6/photo.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2022/January/7/photo.jpg")
+        .assert(predicate::path::missing());
+    // the copy is only at the target under the name of the photo.
+    temp_target
+        .child("2022/January/7/copy.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn granularity_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    for (granularity, expected_path) in [
+        ("year", "2008/jpeg_with_valid_exif.jpg"),
+        ("month", "2008/May/jpeg_with_valid_exif.jpg"),
+        ("day", "2008/May/30/jpeg_with_valid_exif.jpg"),
+    ] {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.arg("--granularity").arg(granularity);
+        cmd.assert().success();
+
+        temp_dir
+            .child(expected_path)
+            .assert(predicate::path::exists());
+    }
+
+    // only one of the two can describe the layout.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--granularity").arg("year");
+    cmd.arg("--layout").arg("%Y/%m");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn layout_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--layout").arg("%Y/%m/%d");
+    cmd.assert().success();
+
+    temp_dir
+        .child("2008/05/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2022/01/06/non_image_file.txt")
+        .assert(predicate::path::exists());
+
+    // layouts escaping the target are rejected.
+    for layout in ["../%Y", "/%Y/%m", "%Y/../../%m", "%Q"] {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.arg("--layout").arg(layout);
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains("The layout is invalid"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn library_sort_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let options = SortOptions {
+        layout: "%Y/%m".to_string(),
+        ..SortOptions::new("tests/data", temp_dir.path())
+    };
+    let summary = photosort::sort(options)?;
+    assert_eq!(summary.copy_count, 24);
+    assert_eq!(summary.copied_bytes, 212260);
+    temp_dir
+        .child("2008/05/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    // the same options report every file as present on a diff.
+    let report = photosort::diff(SortOptions {
+        layout: "%Y/%m".to_string(),
+        ..SortOptions::new("tests/data", temp_dir.path())
+    })?;
+    assert_eq!(report.present_count, 24);
+    assert_eq!(report.new_count, 0);
+
+    // invalid options fail before anything is sorted.
+    let options = SortOptions::new("tests/data/missing", temp_dir.path());
+    assert!(photosort::sort(options).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn scanned_bytes_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let first = photosort::sort(SortOptions::new("tests/data", temp_dir.path()))?;
+    assert_eq!(first.scanned_bytes, 212260);
+    assert_eq!(first.copied_bytes, first.scanned_bytes);
+
+    // every file is scanned again, though none is copied.
+    let second = photosort::sort(SortOptions::new("tests/data", temp_dir.path()))?;
+    assert_eq!(second.scanned_bytes, 212260);
+    assert_eq!(second.copied_bytes, 0);
+    assert!(second.scanned_bytes > second.copied_bytes);
+    assert!(second.display().contains(&format!(
+        "24 files totalling {}",
+        bytesize::to_string(212260, true)
+    )));
+
+    Ok(())
+}
+
+#[test]
+fn summary_date_range_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // the epoch date in an exif and the newest photo of the fixtures.
+    let summary = photosort::sort(SortOptions::new("tests/data", temp_dir.path()))?;
+    assert_eq!(summary.min_date, NaiveDate::from_ymd_opt(1970, 1, 1));
+    assert_eq!(summary.max_date, NaiveDate::from_ymd_opt(2023, 3, 10));
+    assert!(summary
+        .display()
+        .contains("Date range: 1970-01-01 to 2023-03-10"));
+
+    // nothing is dated when every file is left out.
+    let summary = photosort::sort(SortOptions {
+        include_ext: vec!["gif".to_string()],
+        ..SortOptions::new("tests/data", temp_dir.path())
+    })?;
+    assert_eq!(summary.min_date, None);
+    assert!(!summary.display().contains("Date range"));
+
+    Ok(())
+}
+
+#[test]
+fn extension_counts_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let summary = photosort::sort(SortOptions::new("tests/data", temp_dir.path()))?;
+    assert_eq!(summary.extension_counts["jpg"], 11);
+    assert_eq!(summary.extension_counts["png"], 3);
+    assert_eq!(summary.extension_counts["mp4"], 1);
+    assert_eq!(summary.extension_counts.values().sum::<u32>(), 24);
+    // the most common first, and the ties by their name.
+    assert!(summary
+        .display()
+        .contains("Extensions 11 jpg, 3 png, 1 cr2, 1 dng, 1 heic"));
+
+    // the extensions are counted in lowercase, and a file without one on its own.
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    for name in ["a.JPG", "b.jpg", "README"] {
+        temp_source.child(name).write_str(name)?;
+    }
+    let summary = photosort::sort(SortOptions::new(temp_source.path(), temp_target.path()))?;
+    assert_eq!(summary.extension_counts["jpg"], 2);
+    assert_eq!(summary.extension_counts["(none)"], 1);
+    assert!(summary.display().contains("Extensions 2 jpg, 1 (none)"));
+
+    Ok(())
+}
+
+#[test]
+fn plan_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // the jpegs are already sorted, and a different png holds the name of one.
+    photosort::sort(SortOptions {
+        include_ext: vec!["jpg".to_string()],
+        ..SortOptions::new("tests/data", temp_dir.path())
+    })?;
+    temp_dir
+        .child("2016/April/18/png_with_valid_exif.png")
+        .write_str("a different png")?;
+
+    let plan = photosort::plan(SortOptions::new("tests/data", temp_dir.path()))?;
+    assert_eq!(plan.new.len(), 12);
+    assert_eq!(plan.skipped.len(), 11);
+    assert_eq!(plan.conflicting.len(), 1);
+    assert!(plan.new.contains(&(
+        PathBuf::from("tests/data/video_with_creation_time.mp4"),
+        temp_dir
+            .path()
+            .join("2020/August/15/video_with_creation_time.mp4")
+    )));
+    assert!(plan.skipped.contains(&(
+        PathBuf::from("tests/data/jpeg_with_valid_exif.jpg"),
+        temp_dir.path().join("2008/May/30/jpeg_with_valid_exif.jpg")
+    )));
+    assert_eq!(
+        plan.conflicting[0].1,
+        temp_dir
+            .path()
+            .join("2016/April/18/png_with_valid_exif.png")
+    );
+    // nothing was copied.
+    temp_dir
+        .child("2020/August/15/video_with_creation_time.mp4")
+        .assert(predicate::path::missing());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--plan");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "11 files are already present at the target",
+        ))
+        .stdout(predicate::str::contains("Copying").not());
+
+    Ok(())
+}
+
+#[test]
+fn library_events_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let sort = |events: Arc<Mutex<Vec<SortEvent>>>| {
+        photosort::sort(SortOptions {
+            on_event: Some(Box::new(move |event| events.lock().unwrap().push(event))),
+            ..SortOptions::new("tests/data", temp_dir.path())
+        })
+    };
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let summary = sort(events.clone())?;
+    let events = events.lock().unwrap();
+
+    // the scan comes first, followed by one event for every file.
+    assert!(matches!(events[0], SortEvent::Scanned { total: 24 }));
+    assert_eq!(events.len(), 25);
+    let mut sources: Vec<&Path> = events[1..]
+        .iter()
+        .map(|event| match event {
+            SortEvent::Copied(file) => {
+                assert!(file.target.as_ref().is_some_and(|target| target.exists()));
+                file.source.as_path()
+            }
+            event => panic!("Unexpected event {:?}", event),
+        })
+        .collect();
+    sources.sort();
+    sources.dedup();
+    assert_eq!(sources.len() as u32, summary.copy_count);
+
+    // every file is already present on the second run.
+    let events = Arc::new(Mutex::new(Vec::new()));
+    sort(events.clone())?;
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 25);
+    assert!(events[1..]
+        .iter()
+        .all(|event| matches!(event, SortEvent::Skipped(_))));
+
+    Ok(())
+}
+
+#[test]
+fn by_location_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--by-location");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+
+    temp_dir
+        .child("France/Paris/2019/jpeg_with_gps.jpg")
+        .assert(predicate::path::exists());
+    // the photos without gps keep the date layout.
+    temp_dir
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn by_camera_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--by-camera");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+
+    // the Canon_40D sample names its model in the exif.
+    temp_dir
+        .child("Canon EOS 40D/2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("Perfection V600/2021/March/4/jpeg_from_scanner.jpg")
+        .assert(predicate::path::exists());
+    // the files without a model go under Unknown.
+    temp_dir
+        .child("Unknown/2020/August/15/video_with_creation_time.mp4")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("Unknown/2022/January/6/jpeg_with_no_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn by_lens_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--by-camera").arg("--by-lens");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+
+    // the lens folder goes under the camera one, with the slash of the aperture replaced.
+    temp_dir
+        .child("Canon EOS 40D/EF-S17-85mm f-4-5.6 IS USM/2012/September/22/jpeg_with_lens.jpg")
+        .assert(predicate::path::exists());
+    // the files without a lens model go under Unknown Lens.
+    temp_dir
+        .child("Canon EOS 40D/Unknown Lens/2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("Unknown/Unknown Lens/2020/August/15/video_with_creation_time.mp4")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn date_from_name_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    temp_source.create_dir_all()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    for name in ["IMG_20190715_123456.jpg", "IMG-20180102-WA0001.jpg"] {
+        let photo = temp_source.child(name);
+        photo.write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+        set_default_modified_time(photo.to_path_buf())?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--date-from-name");
+    cmd.arg("--verbose");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("is from 2019-07-15 by its name"))
+        // the date in the name stands in for the exif without a warning.
+        .stderr(predicate::str::contains("Will default to file modified time").not());
+
+    temp_target
+        .child("2019/July/15/IMG_20190715_123456.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2018/January/2/IMG-20180102-WA0001.jpg")
+        .assert(predicate::path::exists());
+
+    // without the flag the modified time is used.
+    let temp_target = temp_dir.child("mtime");
+    temp_target.create_dir_all()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert().success();
+    temp_target
+        .child("2022/January/6/IMG_20190715_123456.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn skip_empty_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    temp_source.create_dir_all()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    temp_source
+        .child("jpeg_with_valid_exif.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    let empty = temp_source.child("empty.jpg");
+    empty.touch()?;
+    set_default_modified_time(empty.to_path_buf())?;
+
+    // without the flag the empty file is sorted with a warning.
+    let summary = photosort::sort(SortOptions::new(temp_source.path(), temp_target.path()))?;
+    assert_eq!(summary.copy_count, 2);
+    temp_target
+        .child("2022/January/6/empty.jpg")
+        .assert(predicate::path::exists());
+
+    let temp_target = temp_dir.child("skipped");
+    temp_target.create_dir_all()?;
+    let summary = photosort::sort(SortOptions {
+        skip_empty: true,
+        ..SortOptions::new(temp_source.path(), temp_target.path())
+    })?;
+    assert_eq!(summary.copy_count, 1);
+    assert_eq!(summary.empty_skipped_count, 1);
+    assert_eq!(summary.duplicate_count, 0);
+    assert_eq!(summary.file_count(), 2);
+    temp_target
+        .child("2022/January/6/empty.jpg")
+        .assert(predicate::path::missing());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--skip-empty");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped 1 empty files"))
+        .stderr(predicate::str::contains("is empty").not());
+
+    Ok(())
+}
+
+#[test]
+fn skip_hidden_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    for name in [
+        "photo.jpg",
+        ".hidden.jpg",
+        ".cache/thumbnail.jpg",
+        ".cache/nested/thumbnail.jpg",
+        "album/.hidden.jpg",
+    ] {
+        let file = temp_source.child(name);
+        file.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--skip-hidden");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains(
+            "4 hidden by a leading dot in their name or folder",
+        ));
+    temp_target
+        .child("2008/May/30/photo.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/.hidden.jpg")
+        .assert(predicate::path::missing());
+    temp_target
+        .child("2008/May/30/thumbnail.jpg")
+        .assert(predicate::path::missing());
+
+    // without the flag the hidden files are sorted too.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files totalling"));
+    temp_target
+        .child("2008/May/30/thumbnail.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn route_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let videos = temp_dir.child("videos");
+    videos.create_dir_all()?;
+    let scans = temp_dir.child("scans");
+    scans.create_dir_all()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--route")
+        .arg(format!("mp4={}", videos.path().display()));
+    cmd.arg("--route")
+        .arg(format!(".TIF={}", scans.path().display()));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+
+    // the routed files still get the date folders.
+    videos
+        .child("2020/August/15/video_with_creation_time.mp4")
+        .assert(predicate::path::exists());
+    scans
+        .child("2015/October/3/tiff_with_valid_exif.tif")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2020/August/15/video_with_creation_time.mp4")
+        .assert(predicate::path::missing());
+    temp_target
+        .child("2015/October/3/tiff_with_valid_exif.tif")
+        .assert(predicate::path::missing());
+    // the rest go to the target.
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    // a routed directory must exist.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--route").arg(format!(
+        "mp4={}",
+        temp_dir.child("missing").path().display()
+    ));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("for the mp4 files is invalid"));
+
+    Ok(())
+}
+
+#[test]
+fn undo_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let journal = temp_dir.child("journal.jsonl");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("sort");
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--journal").arg(journal.path());
+    cmd.assert().success();
+    assert_eq!(fs::read_to_string(journal.path())?.lines().count(), 24);
+
+    let mut undo = Command::cargo_bin("photosort")?;
+    undo.arg("undo").arg(journal.path());
+    undo.assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 24 actions"));
+    // the copies and the directories holding them are gone.
+    assert_eq!(fs::read_dir(temp_target.path())?.count(), 0);
+    assert!(Path::new("tests/data/jpeg_with_valid_exif.jpg").exists());
+
+    // a second undo has nothing left to reverse.
+    undo.assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 0 actions"))
+        .stdout(predicate::str::contains("Skipped 24 actions"));
+
+    // moved files are moved back.
+    let temp_source = temp_dir.child("source");
+    temp_source
+        .child("nested/photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--move");
+    cmd.arg("--journal").arg(journal.path());
+    cmd.assert().success();
+    temp_source
+        .child("nested/photo.jpg")
+        .assert(predicate::path::missing());
+
+    undo.assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 1 actions"));
+    temp_source
+        .child("nested/photo.jpg")
+        .assert(predicate::path::exists());
+    assert_eq!(fs::read_dir(temp_target.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn partial_copy_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    // an interrupted run left a partial copy behind.
+    let part = temp_dir.child("2008/May/30/jpeg_with_valid_exif.jpg.photosort.part");
+    part.write_str("half a photo")?;
+    let other = temp_dir.child("downloads/video.part");
+    other.write_str("not ours")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"))
+        .stderr(predicate::str::contains("Removed the partial copy"));
+
+    part.assert(predicate::path::missing());
+    other.assert(predicate::path::exists());
+    temp_dir
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn time_zone_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // 23:30 at +00:00 is already the next day east of it.
+    for zone in ["+05:30", "Asia/Tokyo"] {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.arg("--tz").arg(zone);
+        cmd.assert().success();
+
+        temp_dir
+            .child("2021/January/1/jpeg_with_offset.jpg")
+            .assert(predicate::path::exists());
+        // a date without an offset is taken as it is.
+        temp_dir
+            .child("2008/May/30/jpeg_with_valid_exif.jpg")
+            .assert(predicate::path::exists());
+    }
+
+    // and the previous day west of it.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--tz").arg("America/New_York");
+    cmd.assert().success();
+    temp_dir
+        .child("2020/December/31/jpeg_with_offset.jpg")
+        .assert(predicate::path::exists());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--tz").arg("Mars/Olympus");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid time zone Mars/Olympus"));
+
+    Ok(())
+}
+
+#[test]
+fn report_bursts_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // copies of the same photo share their exif timestamp.
+    let temp_source = assert_fs::TempDir::new()?;
+    for name in ["burst_1.jpg", "burst_2.jpg", "burst_3.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    temp_source
+        .child("alone.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_offset.jpg"))?;
+    let temp_target = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--report-bursts").arg("--burst-window").arg("1");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 4 files totalling"))
+        .stdout(predicate::str::contains("Found 1 bursts"))
+        .stdout(predicate::str::contains("Burst 1 of 3 photos"))
+        .stdout(predicate::str::is_match(r"2008.May.30.burst_1\.jpg")?)
+        .stdout(predicate::str::is_match(r"(?m)^  .*alone\.jpg")?.not());
+
+    // the bursts are only reported when asked for.
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("bursts").not());
+
+    Ok(())
+}
+
+#[test]
+fn verify_content_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // a file with the same name and size but different contents is at the target.
+    let temp_source = assert_fs::TempDir::new()?;
+    let source = temp_source.child("note.txt");
+    source.write_str("first note")?;
+    set_default_modified_time(source.path().to_path_buf())?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let existing = temp_target.child("2022/January/6/note.txt");
+    existing.write_str("other note")?;
+
+    assert!(!photosort::files_identical(source.path(), existing.path())?);
+    assert!(photosort::files_identical(source.path(), source.path())?);
+
+    // the size alone takes it for the same file.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    // comparing the contents finds the collision.
+    cmd.arg("--verify-content");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 0 files"))
+        .stderr(predicate::str::contains(
+            "A different file with the same name exists at the target",
+        ));
+
+    // which is renamed like any other collision.
+    cmd.arg("--target-exists").arg("rename");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"));
+    assert_eq!(
+        fs::read_to_string(temp_target.child("2022/January/6/note (1).txt").path())?,
+        "first note"
+    );
+    assert_eq!(fs::read_to_string(existing.path())?, "other note");
+
+    // an identical file is still skipped.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+
+    Ok(())
+}
+
+#[test]
+fn target_exists_overwrite_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // a re-exported photo replaces the older export at the target.
+    let temp_source = assert_fs::TempDir::new()?;
+    let source = temp_source.child("export.jpg");
+    source.write_str("the new export")?;
+    set_default_modified_time(source.path().to_path_buf())?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let existing = temp_target.child("2022/January/6/export.jpg");
+    existing.write_str("old export")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--target-exists").arg("overwrite");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stdout(predicate::str::contains(
+            "Overwrote 1 files which were already at the target",
+        ));
+    assert_eq!(fs::read_to_string(existing.path())?, "the new export");
+
+    // the same contents are not written again when compared.
+    cmd.arg("--verify-content");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 1 files"))
+        .stdout(predicate::str::contains("Overwrote").not());
+
+    Ok(())
+}
+
+#[test]
+fn update_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // an edited photo which is newer than its copy at the target, whatever its
+    // size, and one which is older than the copy edited at the target.
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    for name in ["newer.jpg", "older.jpg"] {
+        let source = temp_source.child(name);
+        source.write_str("the source")?;
+        set_default_modified_time(source.path().to_path_buf())?;
+    }
+    let newer = temp_target.child("2022/January/6/newer.jpg");
+    newer.write_str("the target")?;
+    // 1-Jan-2022
+    filetime::set_file_mtime(newer.path(), FileTime::from_unix_time(1641038400, 0))?;
+    let older = temp_target.child("2022/January/6/older.jpg");
+    older.write_str("the edited target")?;
+    // 1-Feb-2022
+    filetime::set_file_mtime(older.path(), FileTime::from_unix_time(1643716800, 0))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--update");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stdout(predicate::str::contains(
+            "Updated 1 files which were older at the target",
+        ))
+        .stdout(predicate::str::contains(
+            "Skipped copying 1 files since they were",
+        ))
+        .stdout(predicate::str::contains("Overwrote").not());
+    assert_eq!(fs::read_to_string(newer.path())?, "the source");
+    assert_eq!(fs::read_to_string(older.path())?, "the edited target");
+
+    // the copy keeps the modified time of its source, so it's not updated again.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Skipped copying 2 files since they were",
+        ))
+        .stdout(predicate::str::contains("Updated").not());
+
+    Ok(())
+}
+
+#[test]
+fn sort_order_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // three different files with the same name and date, the longest first by name.
+    let temp_source = assert_fs::TempDir::new()?;
+    for (dir, contents) in [
+        ("a", "the longest note"),
+        ("b", "a mid note"),
+        ("c", "short"),
+    ] {
+        let file = temp_source.child(format!("{}/note.txt", dir));
+        file.write_str(contents)?;
+        set_default_modified_time(file.path().to_path_buf())?;
+    }
+
+    let run = |order: &str| -> Result<(String, assert_fs::TempDir), Box<dyn std::error::Error>> {
+        let temp_target = assert_fs::TempDir::new()?;
+        let manifest = temp_target.child("manifest.csv");
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.arg("--target-exists").arg("rename");
+        cmd.arg("--sort-order").arg(order);
+        cmd.arg("--manifest").arg(manifest.path());
+        cmd.assert().success();
+        let contents = fs::read_to_string(manifest.path())?
+            .replace(&temp_target.path().display().to_string(), "TARGET");
+        Ok((contents, temp_target))
+    };
+
+    // the numbered names and the manifest are the same on every run.
+    let (first, temp_target) = run("name")?;
+    let (second, _) = run("name")?;
+    assert_eq!(first, second);
+    let sources: Vec<&str> = first
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert!(sources[0].ends_with("a/note.txt"));
+    assert!(sources[2].ends_with("c/note.txt"));
+    assert_eq!(
+        fs::read_to_string(temp_target.child("2022/January/6/note.txt").path())?,
+        "the longest note"
+    );
+    assert_eq!(
+        fs::read_to_string(temp_target.child("2022/January/6/note (2).txt").path())?,
+        "short"
+    );
+
+    // the smallest file keeps the name when sorted by size.
+    let (_, temp_target) = run("size")?;
+    assert_eq!(
+        fs::read_to_string(temp_target.child("2022/January/6/note.txt").path())?,
+        "short"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn target_exists_skip_and_error_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // one file is already sorted and a different one holds the name of another.
+    let temp_source = assert_fs::TempDir::new()?;
+    for (name, contents) in [("same.txt", "same note"), ("other.txt", "the new note")] {
+        let file = temp_source.child(name);
+        file.write_str(contents)?;
+        set_default_modified_time(file.path().to_path_buf())?;
+    }
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_target
+        .child("2022/January/6/same.txt")
+        .write_str("same note")?;
+    let existing = temp_target.child("2022/January/6/other.txt");
+    existing.write_str("old note")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--target-exists").arg("skip");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Skipped copying 1 files since they were",
+        ))
+        .stdout(predicate::str::contains(
+            "Skipped copying 1 files since a different file",
+        ));
+
+    // the different file fails, while the same one is still skipped.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--target-exists").arg("error");
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "Skipped copying 1 files since they were",
+        ))
+        .stdout(predicate::str::contains("Failed to copy 1 files"))
+        .stderr(predicate::str::contains(
+            "since a different file with the same name exists at the target",
+        ));
+    assert_eq!(fs::read_to_string(existing.path())?, "old note");
+
+    Ok(())
+}
+
+#[test]
+fn interactive_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // every note collides with a different one of the same name at the target.
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+    for name in names {
+        let file = temp_source.child(name);
+        file.write_str("the new note")?;
+        set_default_modified_time(file.path().to_path_buf())?;
+        temp_target
+            .child(format!("2022/January/6/{}", name))
+            .write_str("old note")?;
+    }
+    let note =
+        |name: &str| fs::read_to_string(temp_target.child(format!("2022/January/6/{}", name)));
+
+    // the answers are read in the sort order, an unknown one being asked again,
+    // and the last note is skipped without asking.
+    let script = "maybe\no\nr\ns\na\n";
+    let summary = photosort::sort(SortOptions {
+        jobs: Some(1),
+        on_collision: Some(photosort::ask_on_collision(
+            std::io::Cursor::new(script),
+            std::io::sink(),
+        )),
+        ..SortOptions::new(temp_source.path(), temp_target.path())
+    })?;
+    assert_eq!(summary.copy_count, 2);
+    assert_eq!(summary.overwritten_count, 1);
+    assert_eq!(summary.duplicate_count, 3);
+    assert_eq!(note("a.txt")?, "the new note");
+    assert_eq!(note("b.txt")?, "old note");
+    assert_eq!(note("b (1).txt")?, "the new note");
+    for name in ["c.txt", "d.txt", "e.txt"] {
+        assert_eq!(note(name)?, "old note");
+    }
+
+    // quitting leaves the remaining notes for a later run.
+    let script = "s\nq\n";
+    let summary = photosort::sort(SortOptions {
+        jobs: Some(1),
+        on_collision: Some(photosort::ask_on_collision(
+            std::io::Cursor::new(script),
+            std::io::sink(),
+        )),
+        ..SortOptions::new(temp_source.path(), temp_target.path())
+    })?;
+    assert_eq!(
+        summary.filtered_reasons.get(&FilterReason::Stopped),
+        Some(&3)
+    );
+
+    // the answers on stdin are not asked for without a terminal.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--interactive");
+    let answers = temp_target.child("answers");
+    answers.write_str("o\no\no\no\no\n")?;
+    cmd.stdin(fs::File::open(answers.path())?);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("[s]kip").not())
+        .stderr(predicate::str::contains(
+            "--interactive needs a terminal to ask on",
+        ));
+    assert_eq!(note("c.txt")?, "old note");
+
+    Ok(())
+}
+
+#[test]
+fn size_filter_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let runs = [
+        // the files below 1000 bytes are left out.
+        (
+            vec!["--min-size", "1KB"],
+            12,
+            "2008/May/30/jpeg_with_valid_exif.jpg",
+            "2022/January/6/non_image_file.txt",
+        ),
+        // the files above 8192 bytes are left out.
+        (
+            vec!["--max-size", "8KiB"],
+            22,
+            "2008/May/30/jpeg_with_valid_exif.jpg",
+            "2022/January/6/jpeg_with_no_exif.jpg",
+        ),
+    ];
+    for (range, copy_count, sorted, left_out) in runs {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.args(&range);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "Copied {} files totalling",
+                copy_count
+            )))
+            .stdout(predicate::str::contains(format!(
+                "Filtered {0} files which were left out of the run - \n  {0} with a size outside of the size range",
+                24 - copy_count
+            )));
+
+        temp_dir.child(sorted).assert(predicate::path::exists());
+        temp_dir.child(left_out).assert(predicate::path::missing());
+    }
+
+    // an empty range is rejected.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.args(["--min-size", "2MB", "--max-size", "1MB"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("The size range is empty"));
+
+    Ok(())
+}
+
+#[test]
+fn date_policy_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // the modified time of every file is 6-Jan-2022.
+    let runs = [
+        ("mtime-first", "2022/January/6/jpeg_with_valid_exif.jpg"),
+        ("oldest", "2008/May/30/jpeg_with_valid_exif.jpg"),
+        ("newest", "2022/January/6/jpeg_with_valid_exif.jpg"),
+    ];
+    for (policy, sorted) in runs {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.arg("--date-policy").arg(policy);
+        cmd.assert().success();
+
+        temp_dir.child(sorted).assert(predicate::path::exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn month_format_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let runs = [
+        ("name", "2008/May/30/jpeg_with_valid_exif.jpg"),
+        ("number", "2008/05/30/jpeg_with_valid_exif.jpg"),
+        ("short", "2019/Nov/2/raw_with_valid_exif.dng"),
+        ("locale", "2008/Mai/30/jpeg_with_valid_exif.jpg"),
+    ];
+    for (month_format, sorted) in runs {
+        let temp_dir = assert_fs::TempDir::new()?;
+
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg("tests/data");
+        cmd.arg("--target-dir").arg(temp_dir.path());
+        cmd.arg("--month-format").arg(month_format);
+        cmd.env("LC_ALL", "").env("LC_TIME", "");
+        cmd.env("LANG", "de_DE.UTF-8");
+        cmd.assert().success();
+
+        temp_dir.child(sorted).assert(predicate::path::exists());
+    }
+
+    // an unknown language keeps the english names.
+    let temp_dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.args(["--month-format", "locale", "--layout", "%Y/%B"]);
+    cmd.env("LC_ALL", "xx_XX.UTF-8");
+    cmd.assert().success();
+    temp_dir
+        .child("2008/May/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn mirror_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    for name in ["kept.jpg", "deleted.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    temp_source
+        .child("gone.txt")
+        .write_file(Path::new("tests/data/non_image_file.txt"))?;
+    set_default_modified_time(temp_source.child("gone.txt").path().to_path_buf())?;
+    let temp_target = assert_fs::TempDir::new()?;
+
+    let sort = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        Ok(cmd)
+    };
+    sort()?.arg("--history").assert().success();
+    fs::remove_file(temp_source.child("deleted.jpg").path())?;
+    fs::remove_file(temp_source.child("gone.txt").path())?;
+
+    // a mirror outside of a terminal has to be confirmed.
+    sort()?
+        .arg("--mirror")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Pass --yes to confirm it"));
+
+    // a dry run only lists the files.
+    sort()?
+        .args(["--mirror", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"Would remove .*deleted\.jpg")?);
+    temp_target
+        .child("2008/May/30/deleted.jpg")
+        .assert(predicate::path::exists());
+
+    sort()?
+        .args(["--mirror", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Removed 2 files from the target since their source is gone",
+        ));
+    temp_target
+        .child("2008/May/30/deleted.jpg")
+        .assert(predicate::path::missing());
+    temp_target
+        .child("2008/May/30/kept.jpg")
+        .assert(predicate::path::exists());
+    // the folders left empty are removed, the history of earlier runs is kept.
+    temp_target.child("2022").assert(predicate::path::missing());
+    temp_target
+        .child(".photosort-history.jsonl")
+        .assert(predicate::path::exists());
+
+    // a filter would leave files without a source in the run.
+    sort()?
+        .args(["--mirror", "--yes", "--include-ext", "jpg"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn follow_symlinks_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    // a folder linked in from elsewhere, and a link back to the source itself.
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_linked = assert_fs::TempDir::new()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    temp_linked
+        .child("linked.jpg")
+        .write_file(Path::new("tests/data/jpeg_from_scanner.jpg"))?;
+    std::os::unix::fs::symlink(temp_linked.path(), temp_source.child("linked").path())?;
+    std::os::unix::fs::symlink(temp_source.path(), temp_source.child("loop").path())?;
+    // a second link to the same folder is only walked once.
+    std::os::unix::fs::symlink(temp_linked.path(), temp_source.child("again").path())?;
+
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--follow-symlinks");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files totalling"))
+        .stdout(predicate::str::contains(
+            "Skipped 2 directories which were already scanned through a symlink",
+        ));
+    temp_target
+        .child("2021/March/4/linked.jpg")
+        .assert(predicate::path::exists());
+
+    // the links are left alone by default.
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"));
+
+    Ok(())
+}
+
+#[test]
+fn config_file_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let source = fs::canonicalize("tests/data")?;
+    temp_dir.child("photosort.toml").write_str(&format!(
+        "source-dir = {:?}\ntarget-dir = {:?}\nlayout = \"%Y/%m\"\nquiet = true\n",
+        source,
+        temp_target.path()
+    ))?;
+    // keeps the config of the user out of the test.
+    let photosort = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.current_dir(temp_dir.path());
+        cmd.env("HOME", temp_dir.path())
+            .env_remove("XDG_CONFIG_HOME");
+        Ok(cmd)
+    };
+
+    // the directories and the layout come from the config.
+    photosort()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"))
+        .stdout(predicate::str::contains("Copied tests").not());
+    temp_target
+        .child("2008/05/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    // the command line wins over the config.
+    let temp_target = assert_fs::TempDir::new()?;
+    photosort()?
+        .arg("sort")
+        .arg("--target-dir")
+        .arg(temp_target.path())
+        .arg("--layout")
+        .arg("%Y")
+        .assert()
+        .success();
+    temp_target
+        .child("2008/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+
+    // an option photosort doesn't know is reported.
+    temp_dir
+        .child("photosort.toml")
+        .write_str("layuot = \"%Y\"\n")?;
+    photosort()?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown option layuot in the config file",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn scan_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let report = photosort::scan(Path::new("tests/data"))?;
+    assert_eq!(report.file_count, 24);
+    assert_eq!(report.error_count, 0);
+    assert_eq!(
+        report.years.keys().copied().collect::<Vec<_>>(),
+        [1970, 2008, 2012, 2015, 2016, 2017, 2018, 2019, 2020, 2021, 2022, 2023]
+    );
+    assert_eq!(report.year_count(2008), 2);
+    assert_eq!(report.year_count(2020), 3);
+    // the files without a date in their metadata fall back to the modified time.
+    assert_eq!(report.years[&2022][&1], 6);
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("scan").arg("--source-dir").arg("tests/data");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2020 3 files"))
+        .stdout(predicate::str::contains("Total 24 files"));
+
+    Ok(())
+}
+
+#[test]
+fn name_affix_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--layout").arg("%Y");
+    cmd.arg("--name-prefix").arg("%Y-%m-%d_");
+    cmd.arg("--name-suffix").arg("_%Y");
+    cmd.assert().success();
+
+    // the date is added around the name and the extension stays at the end.
+    temp_dir
+        .child("2008/2008-05-30_jpeg_with_valid_exif_2008.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2020/2020-08-15_video_with_creation_time_2020.mp4")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2008/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::missing());
+
+    // a pattern which would add a folder is rejected.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--name-prefix").arg("%Y/");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("The --name-prefix is invalid"));
+
+    Ok(())
+}
+
+#[test]
+fn archive_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_target = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--archive");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 24 files totalling"));
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::missing());
+    temp_target
+        .child("2022-01-06.zip")
+        .assert(predicate::path::exists());
+
+    // the files are stored as they are.
+    let mut archive =
+        zip::ZipArchive::new(fs::File::open(temp_target.child("2008-05-30.zip").path())?)?;
+    let mut entry = archive.by_name("jpeg_with_valid_exif.jpg")?;
+    let mut contents = Vec::new();
+    std::io::Read::read_to_end(&mut entry, &mut contents)?;
+    assert_eq!(contents, fs::read("tests/data/jpeg_with_valid_exif.jpg")?);
+
+    // the files already in the archives are skipped.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--archive");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 24 files"));
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--archive").arg("--move");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn preserve_perms_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let source = temp_source.child("photo.jpg");
+    source.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    fs::set_permissions(source.path(), fs::Permissions::from_mode(0o604))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--preserve-perms");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"));
+
+    let target = temp_target.child("2008/May/30/photo.jpg");
+    let mode = fs::metadata(target.path())?.permissions().mode();
+    assert_eq!(mode & 0o7777, 0o604);
+
+    Ok(())
+}
+
+#[test]
+fn baseline_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let baseline = temp_dir.child("summary.json");
+    baseline.write_str(&serde_json::to_string(&Summary::init())?)?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--baseline").arg(baseline.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Compared with the baseline"))
+        .stdout(predicate::str::contains(format!(
+            "Copied +24 files totalling +{}",
+            bytesize::to_string(212260, true)
+        )));
+
+    // the json summary of a run is the baseline of the next.
+    let output = Command::cargo_bin("photosort")?
+        .arg("--source-dir")
+        .arg("tests/data")
+        .arg("--target-dir")
+        .arg(temp_target.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--baseline")
+        .arg(baseline.path())
+        .output()?;
+    assert!(output.status.success());
+    let current: Summary = serde_json::from_slice(&output.stdout)?;
+    let changes = photosort::diff_summaries(&Summary::init(), &current);
+    assert_eq!(changes.copy_count, 0);
+    assert_eq!(changes.skipped_count, 24);
+    assert!(String::from_utf8(output.stderr)?.contains("Skipped +24 files"));
+
+    baseline.write_str("not json")?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--baseline").arg(baseline.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to load the baseline"));
+
+    Ok(())
+}
+
+#[test]
+fn source_pattern_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_target = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data/jpeg_with_*.jpg");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Scanned 10 files"))
+        .stdout(predicate::str::contains("Copied 10 files"));
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/tiff_with_valid_exif.tif")
+        .assert(predicate::path::missing());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data/*.bmp");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "The source pattern tests/data/*.bmp matches no files.",
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn dir_error_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+
+    // a file in place of the year folder keeps even root from creating it.
+    temp_target.child("2008").touch()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "Failed to create 1 folders at the target, so 1 files were not copied",
+        ))
+        .stdout(predicate::str::contains("2008/May/30"))
+        .stdout(predicate::str::contains("Failed to copy").not());
+    fs::remove_file(temp_target.child("2008").path())?;
+
+    fs::set_permissions(temp_target.path(), fs::Permissions::from_mode(0o555))?;
+    // root can write to a read only folder, which leaves nothing to test.
+    let writable = fs::create_dir(temp_target.child("probe").path()).is_ok();
+    if !writable {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.assert().code(1).stdout(predicate::str::contains(
+            "Failed to create 1 folders at the target",
+        ));
+    }
+    fs::set_permissions(temp_target.path(), fs::Permissions::from_mode(0o755))?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn owner_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::MetadataExt;
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--owner").arg("1234:5678");
+
+    // only root may give a file away, so anyone else is told before anything is copied.
+    if temp_source.path().metadata()?.uid() != 0 {
+        cmd.assert().failure().stderr(predicate::str::contains(
+            "Changing the owner usually needs root",
+        ));
+        temp_target.child("2008").assert(predicate::path::missing());
+        return Ok(());
+    }
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"));
+    for path in ["2008", "2008/May", "2008/May/30", "2008/May/30/photo.jpg"] {
+        let metadata = temp_target.child(path).path().metadata()?;
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 5678), "{}", path);
+    }
+    // the target itself was there before the run.
+    assert_eq!(temp_target.path().metadata()?.uid(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn rename_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let photo = fs::read("tests/data/jpeg_with_valid_exif.jpg")?;
+    temp_source.child("a.jpg").write_binary(&photo)?;
+    // the same photo with a byte more is a different file under the same name.
+    temp_source
+        .child("b.jpg")
+        .write_binary(&[photo.as_slice(), &[0]].concat())?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--rename").arg("{date}_{camera}_{counter}.{ext}");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files"));
+    temp_target
+        .child("2008/May/30/2008-05-30_Canon EOS 40D_1.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/2008-05-30_Canon EOS 40D_2.jpg")
+        .assert(predicate::path::exists());
+
+    // the renamed files are found again.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 2 files"));
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--rename").arg("{date}_{lens}.{ext}");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown token {lens}"));
+
+    Ok(())
+}
+
+#[test]
+fn state_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    for name in ["a.jpg", "b.jpg", "c.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+    // an interrupted run sorted a.jpg, and crashed while recording b.jpg.
+    let state = temp_dir.child("state.jsonl");
+    let sorted = serde_json::to_string(&temp_source.child("a.jpg").path())?;
+    state.write_str(&format!("{}\n\"{}", sorted, temp_source.path().display()))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--state").arg(state.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files"))
+        .stdout(predicate::str::contains(
+            "1 sorted by an earlier run as recorded in the state file",
+        ));
+    temp_target
+        .child("2008/May/30/a.jpg")
+        .assert(predicate::path::missing());
+    temp_target
+        .child("2008/May/30/b.jpg")
+        .assert(predicate::path::exists());
+
+    // the files sorted by the run are recorded too, and the state is removed
+    // once nothing is left.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--state").arg(state.path());
+    cmd.arg("--remove-state");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 0 files"))
+        .stdout(predicate::str::contains(
+            "3 sorted by an earlier run as recorded in the state file",
+        ));
+    state.assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[test]
+fn since_last_run_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_source = temp_dir.child("source");
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    for name in ["a.jpg", "b.jpg"] {
+        let photo = temp_source.child(name);
+        photo.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+        set_default_modified_time(photo.to_path_buf())?;
+    }
+    let last_run = temp_dir.child("last-run");
+
+    let sort = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.arg("--since-last-run").arg(last_run.path());
+        Ok(cmd)
+    };
+    // without a last run every file is sorted.
+    sort()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 2 files"));
+    last_run.assert(predicate::path::exists());
+
+    // a photo added since is the only one sorted, and the photos left out are
+    // not read again.
+    temp_source
+        .child("c.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    fs::remove_file(temp_target.child("2008/May/30/a.jpg").path())?;
+    sort()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stdout(predicate::str::contains(
+            "2 modified before the last run started",
+        ));
+    temp_target
+        .child("2008/May/30/c.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/a.jpg")
+        .assert(predicate::path::missing());
+
+    last_run.write_str("yesterday")?;
+    sort()?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("doesn't hold a time"));
+
+    Ok(())
+}
+
+#[test]
+fn target_in_source_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    temp_source
+        .child("photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    let temp_target = temp_source.child("sorted");
+    temp_target.create_dir_all()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Scanned 1 files"))
+        .stdout(predicate::str::contains("Copied 1 files"));
+
+    // the sorted files are not sorted again, however often it runs.
+    for _ in 0..2 {
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Scanned 1 files"))
+            .stdout(predicate::str::contains("Copied 0 files"));
+    }
+    temp_target
+        .child("2008/May/30/photo.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2008/May/30/sorted")
+        .assert(predicate::path::missing());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_source.path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "The source and the target are the same directory.",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn in_place_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    for name in ["a.jpg", "old/b.jpg", "2008/May/30/c.jpg"] {
+        temp_dir
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    }
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_dir.path());
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--in-place");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Moved 2 files"))
+        .stdout(predicate::str::contains("Skipped copying 1 files"));
+    for name in ["a.jpg", "b.jpg", "c.jpg"] {
+        temp_dir
+            .child(format!("2008/May/30/{}", name))
+            .assert(predicate::path::exists());
+    }
+    temp_dir.child("a.jpg").assert(predicate::path::missing());
+    // the folders emptied by the run are removed.
+    temp_dir.child("old").assert(predicate::path::missing());
+
+    // a reorganized folder is left as it is.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 3 files"));
+
+    Ok(())
+}
+
+#[test]
+fn exif_only_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    for name in [
+        "jpeg_with_valid_exif.jpg",
+        "jpeg_with_no_exif.jpg",
+        "png_with_no_exif.png",
+    ] {
+        let file = temp_source.child(name);
+        file.write_file(&Path::new("tests/data").join(name))?;
+        set_default_modified_time(file.to_path_buf())?;
+    }
+
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--exif-only");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stdout(predicate::str::contains(
+            "2 without a capture date in their metadata",
+        ))
+        .stdout(predicate::str::contains("Error reading the exif data").not());
+    temp_target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("2022/January/6")
+        .assert(predicate::path::missing());
+
+    // or they go to a folder of their own.
+    let temp_target = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--exif-only").arg("--unknown-dir").arg("Unknown");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 3 files"));
+    temp_target
+        .child("Unknown/jpeg_with_no_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_target
+        .child("Unknown/png_with_no_exif.png")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn hash_threads_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // many copies of two photos, more than either pool sorts at once.
+    for index in 0..12 {
+        let data = match index % 2 {
+            0 => "tests/data/jpeg_with_valid_exif.jpg",
+            _ => "tests/data/jpeg_with_no_exif.jpg",
+        };
+        temp_source
+            .child(format!("photo_{:02}.jpg", index))
+            .write_file(Path::new(data))?;
+    }
+    temp_target
+        .child("elsewhere/old_name.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--dedupe-by-hash");
+    cmd.arg("--jobs").arg("2").arg("--hash-threads").arg("3");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files totalling"))
+        .stdout(predicate::str::contains("Skipped copying 11 files"));
+    let copied = (0..12)
+        .step_by(2)
+        .filter(|index| {
+            temp_target
+                .child(format!("2008/May/30/photo_{:02}.jpg", index))
+                .path()
+                .exists()
+        })
+        .count();
+    assert_eq!(copied, 1);
+
+    // the hash pool is only started for a run which hashes.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--hash-threads").arg("3");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "--hash-threads needs --dedupe-by-hash, --stable-by-hash or --no-clobber-across-dates",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn date_skew_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let photo = temp_source.child("jpeg_with_epoch_date.jpg");
+    photo.write_file(Path::new("tests/data/jpeg_with_epoch_date.jpg"))?;
+    set_default_modified_time(photo.to_path_buf())?;
+
+    let sort = |args: &[&str]| -> Result<_, Box<dyn std::error::Error>> {
+        let temp_target = assert_fs::TempDir::new()?;
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.args(args);
+        Ok((cmd.assert().success(), temp_target))
+    };
+
+    // the suspect date is warned about, but still used.
+    let (assert, temp_target) = sort(&["--max-date-skew", "3650"])?;
+    assert.stderr(predicate::str::contains(
+        "The exif date 1970-01-01 is 18998 days from the file modified time 2022-01-06",
+    ));
+    temp_target
+        .child("1970/January/1/jpeg_with_epoch_date.jpg")
+        .assert(predicate::path::exists());
+
+    // or the file is sorted by its modified time instead.
+    let (assert, temp_target) = sort(&["--max-date-skew", "3650", "--skew-fallback"])?;
+    assert
+        .stderr(predicate::str::contains(
+            "Will default to file modified time",
+        ))
+        .stdout(predicate::str::contains(
+            "reading the exif data for 1 files",
+        ));
+    temp_target
+        .child("2022/January/6/jpeg_with_epoch_date.jpg")
+        .assert(predicate::path::exists());
+
+    // a date within the skew is left alone.
+    let (assert, temp_target) = sort(&["--max-date-skew", "20000", "--skew-fallback"])?;
+    assert.stderr(predicate::str::contains("Warning").not());
+    temp_target
+        .child("1970/January/1/jpeg_with_epoch_date.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn color_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    temp_source
+        .child("jpeg_with_no_exif.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+
+    let temp_target = assert_fs::TempDir::new()?;
+    let sort = |color: &str| -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("photosort")?;
+        cmd.arg("--source-dir").arg(temp_source.path());
+        cmd.arg("--target-dir").arg(temp_target.path());
+        cmd.arg("--color").arg(color);
+        Ok(cmd)
+    };
+
+    // the output is piped, yet always colors it over NO_COLOR.
+    sort("always")?
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("\u{1b}["));
+    // and never wins over CLICOLOR_FORCE.
+    fs::remove_dir_all(temp_target.path())?;
+    fs::create_dir(temp_target.path())?;
+    sort("never")?
+        .env_remove("NO_COLOR")
+        .env("CLICOLOR_FORCE", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\u{1b}[").not())
+        .stderr(predicate::str::contains("\u{1b}[").not());
+    // the option applies to the subcommands too.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("scan").arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--color").arg("never");
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_xattrs_test() -> Result<(), Box<dyn std::error::Error>> {
+    use rustix::fs::{getxattr, setxattr, XattrFlags};
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    let source = temp_source.child("photo.jpg");
+    source.write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    // a filesystem without user attributes can't run the test.
+    if setxattr(source.path(), "user.tags", b"holiday", XattrFlags::empty()).is_err() {
+        return Ok(());
+    }
+    setxattr(
+        source.path(),
+        "user.comment",
+        b"by the lake",
+        XattrFlags::empty(),
+    )?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--preserve-xattrs");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 1 files"))
+        .stderr(predicate::str::contains("extended attributes").not());
+
+    let target = temp_target.child("2008/May/30/photo.jpg");
+    let mut value = [0; 64];
+    let len = getxattr(target.path(), "user.tags", &mut value)?;
+    assert_eq!(&value[..len], b"holiday");
+    let len = getxattr(target.path(), "user.comment", &mut value)?;
+    assert_eq!(&value[..len], b"by the lake");
+
+    Ok(())
+}
+
+#[test]
+fn checksum_manifest_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let temp_target = temp_dir.child("target");
+    temp_target.create_dir_all()?;
+    let checksums = temp_dir.child("SHA256SUMS");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data/jpeg_with_*.jpg");
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--checksum-manifest").arg(checksums.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Copied 10 files"));
+
+    // every line holds the hash of the copy, relative to the target.
+    let contents = fs::read_to_string(checksums.path())?;
+    assert_eq!(contents.lines().count(), 10);
+    for line in contents.lines() {
+        let (hash, path) = line.split_once("  ").unwrap();
+        assert_eq!(hash, photosort::file_hash(&temp_target.path().join(path))?);
+    }
+    assert!(contents.contains("  2008/May/30/jpeg_with_valid_exif.jpg\n"));
+
+    // a run with nothing new leaves the manifest as it was.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped copying 10 files"));
+    assert_eq!(fs::read_to_string(checksums.path())?, contents);
+
+    Ok(())
+}
+
+#[test]
+fn by_orientation_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--by-orientation");
+    cmd.assert().success();
+
+    let expected_paths = [
+        // the orientation tag turns the landscape jpeg into a portrait.
+        "Portrait/2023/March/10/jpeg_with_orientation.jpg",
+        "Landscape/2008/May/30/jpeg_with_valid_exif.jpg",
+        // a jpeg without exif has its dimensions in its header.
+        "Landscape/2022/January/6/jpeg_with_no_exif.jpg",
+        // a 1x1 png.
+        "Square/2016/April/18/png_with_valid_exif.png",
+        "Unknown/2020/August/15/video_with_creation_time.mp4",
+        "Unknown/2022/January/6/non_image_file.txt",
+    ];
+    for path in expected_paths {
+        temp_dir.child(path).assert(predicate::path::exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn group_by_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    // the place comes before the layout rather than replacing it.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--group-by").arg("location,orientation");
+    cmd.assert().success();
+    let expected_paths = [
+        "France/Paris/Landscape/2019/June/12/jpeg_with_gps.jpg",
+        "Unknown Location/Portrait/2023/March/10/jpeg_with_orientation.jpg",
+        "Unknown Location/Unknown/2020/August/15/video_with_creation_time.mp4",
+    ];
+    for path in expected_paths {
+        temp_dir.child(path).assert(predicate::path::exists());
+    }
+
+    // the folders nest in the order of the fields.
+    let temp_dir = assert_fs::TempDir::new()?;
+    photosort::sort(SortOptions {
+        group_by: vec![GroupBy::Lens, GroupBy::Camera],
+        ..SortOptions::new("tests/data", temp_dir.path())
+    })?;
+    temp_dir
+        .child("EF-S17-85mm f-4-5.6 IS USM/Canon EOS 40D/2012/September/22/jpeg_with_lens.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("Unknown Lens/Unknown/2022/January/6/jpeg_with_no_exif.jpg")
+        .assert(predicate::path::exists());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--group-by").arg("location").arg("--by-location");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "--by-location can't be combined with --group-by location",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn collision_report_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // two different photos of the same name and day, from different folders.
+    temp_source
+        .child("first/photo.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_valid_exif.jpg"))?;
+    let second = temp_source.child("second/photo.jpg");
+    second.write_file(Path::new("tests/data/jpeg_with_no_exif.jpg"))?;
+    // 30-May-2008
+    filetime::set_file_mtime(second.path(), FileTime::from_unix_time(1212148800, 0))?;
+    temp_source
+        .child("second/other.jpg")
+        .write_file(Path::new("tests/data/jpeg_with_gps.jpg"))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--collision-report");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Colliding 1 target paths have more than one source file -",
+        ))
+        .stdout(predicate::str::is_match("  .*2008.May.30.photo\\.jpg\n")?)
+        .stdout(predicate::str::is_match("    .*first.photo\\.jpg\n")?)
+        .stdout(predicate::str::is_match("    .*second.photo\\.jpg")?)
+        .stdout(predicate::str::contains("other.jpg").not());
+    // nothing is copied.
+    temp_target
+        .child("2008/May/30/photo.jpg")
+        .assert(predicate::path::missing());
+
+    let output = cmd.arg("--format").arg("json").output()?;
+    let report: photosort::CollisionReport = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(report.collisions.len(), 1);
+    assert_eq!(report.collisions[0].sources.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn find_duplicates_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_source = assert_fs::TempDir::new()?;
+    let temp_target = assert_fs::TempDir::new()?;
+    // the same photo imported twice under different names, and a different
+    // note of the same size as another.
+    for name in ["2019/photo.jpg", "backup/copy of photo.jpg"] {
+        temp_source
+            .child(name)
+            .write_file(Path::new("tests/data/jpeg_with_gps.jpg"))?;
+    }
+    temp_source.child("a.txt").write_str("note a")?;
+    temp_source.child("b.txt").write_str("note b")?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg(temp_source.path());
+    cmd.arg("--target-dir").arg(temp_target.path());
+    cmd.arg("--find-duplicates");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Found 1 groups of files with the same contents -",
+        ))
+        .stdout(predicate::str::is_match("    .*2019.photo\\.jpg\n")?)
+        .stdout(predicate::str::is_match(
+            "    .*backup.copy of photo\\.jpg",
+        )?)
+        .stdout(predicate::str::contains("a.txt").not());
+    // nothing is copied.
+    assert_eq!(fs::read_dir(temp_target.path())?.count(), 0);
+
+    let output = cmd.arg("--format").arg("json").output()?;
+    let report: photosort::DuplicateReport = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(
+        report.groups[0].hash,
+        photosort::file_hash(Path::new("tests/data/jpeg_with_gps.jpg"))?
+    );
+    assert_eq!(report.groups[0].files.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn where_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data/jpeg_with_offset.jpg");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "{}\n",
+            temp_dir
+                .path()
+                .join("2020/December/31/jpeg_with_offset.jpg")
+                .display()
+        )))
+        .stdout(predicate::str::contains("Dated 2020-12-31 from the exif"));
+    // nothing is copied.
+    temp_dir
+        .child("2020/December/31/jpeg_with_offset.jpg")
+        .assert(predicate::path::missing());
+
+    // the layout and the time zone of a sort are honored, and the target is
+    // relative to the current directory without one.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data/jpeg_with_offset.jpg");
+    cmd.arg("--layout").arg("%Y-%m-%d");
+    cmd.arg("--tz").arg("Asia/Tokyo");
+    cmd.assert()
+        .success()
+        .stdout("2021-01-01/jpeg_with_offset.jpg\nDated 2021-01-01 from the exif\n");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data/jpeg_with_no_exif.jpg");
+    cmd.arg("--format").arg("json");
+    let output = cmd.output()?;
+    let located: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(located["date_source"], "mtime");
+    assert_eq!(located["date"], "2022-01-06");
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is a directory"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn date_command_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let target = temp_dir.child("target");
+    target.create_dir_all()?;
+    // dates the png, fails on the jpeg and prints no date for the text file.
+    let script = temp_dir.child("date.sh");
+    script.write_str(
+        "#!/bin/sh\ncase \"$1\" in\n  *.png) echo 2015-03-14 ;;\n  *.txt) echo unknown ;;\n  *) exit 1 ;;\nesac\n",
+    )?;
+    fs::set_permissions(script.path(), fs::Permissions::from_mode(0o755))?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(target.path());
+    cmd.arg("--date-command").arg(script.path());
+    cmd.assert().success();
+
+    // the command is asked ahead of the exif.
+    target
+        .child("2015/March/14/png_with_no_exif.png")
+        .assert(predicate::path::exists());
+    target
+        .child("2015/March/14/png_with_valid_exif.png")
+        .assert(predicate::path::exists());
+    // the other dates are read as before when it has none.
+    target
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    target
+        .child("2022/January/6/non_image_file.txt")
+        .assert(predicate::path::exists());
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data/png_with_no_exif.png");
+    cmd.arg("--date-command").arg(script.path());
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Dated 2015-03-14 from the command",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn exif_date_formats_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.assert().success();
+
+    // a date without the time, and a time with subseconds, are read from the
+    // exif rather than falling back to the modified time.
+    temp_dir
+        .child("2015/March/14/jpeg_with_colon_date.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2016/July/4/jpeg_with_subsecond_date.jpg")
+        .assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn takeout_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(temp_dir.path());
+    cmd.arg("--takeout");
+    cmd.assert().success();
+
+    temp_dir
+        .child("2017/August/19/png_from_takeout.png")
+        .assert(predicate::path::exists());
+    // the sidecar itself has no date of its own.
+    temp_dir
+        .child("2022/January/6/png_from_takeout.png.json")
+        .assert(predicate::path::exists());
+    // the exif still wins over a sidecar, and the files without one keep their date.
+    temp_dir
+        .child("2008/May/30/jpeg_with_valid_exif.jpg")
+        .assert(predicate::path::exists());
+    temp_dir
+        .child("2022/January/6/png_with_no_exif.png")
+        .assert(predicate::path::exists());
+
+    // the timestamp is in UTC, so it's moved to the time zone.
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("where").arg("tests/data/png_from_takeout.png");
+    cmd.arg("--takeout");
+    cmd.arg("--tz").arg("+05:30");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "2017/August/20/png_from_takeout.png",
+        ))
+        .stdout(predicate::str::contains(
+            "Dated 2017-08-20 from the takeout",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn buffered_output_test() -> Result<(), Box<dyn std::error::Error>> {
+    setup()?;
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let log = temp_dir.child("log.txt");
+    let target = temp_dir.child("target");
+    target.create_dir_all()?;
+
+    // the lines and the warnings go to the same file, as with 2>&1.
+    let log_file = fs::File::create(log.path())?;
+    let mut cmd = Command::cargo_bin("photosort")?;
+    cmd.arg("--source-dir").arg("tests/data");
+    cmd.arg("--target-dir").arg(target.path());
+    cmd.arg("--jobs").arg("1");
+    cmd.stdout(log_file.try_clone()?).stderr(log_file);
+    cmd.assert().success();
+
+    let contents = fs::read_to_string(log.path())?;
+    // every line comes out once the buffer is flushed, ahead of the summary.
+    let summary = contents.find("Copied 24 files totalling").unwrap();
+    for entry in fs::read_dir("tests/data")? {
+        let line = format!(
+            "{} to ",
+            Path::new("tests/data").join(entry?.file_name()).display()
+        );
+        let at = contents.find(&line).unwrap();
+        assert!(at < summary, "{} is after the summary", line);
+    }
+    // the files are sorted one by one in order of their name, so the lines of
+    // the files before a warning are printed before it.
+    let before = contents.find("heic_with_valid_exif.heic to ").unwrap();
+    let warning = contents
+        .find("Could not read the date from the metadata of the file")
+        .unwrap();
+    let after = contents.find("jpeg_with_no_exif.jpg to ").unwrap();
+    assert!(before < warning && warning < after);
+
+    Ok(())
+}